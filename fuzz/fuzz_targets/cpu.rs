@@ -0,0 +1,33 @@
+//! Random instruction streams straight through `CPU::execute_instruction` on the mock bus,
+//! every step checked against the invariants and ALU model in `tostiloco::reference`. The
+//! first byte picks the instruction set.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use tostiloco::chip8::{Chip8, Chip8Platform};
+use tostiloco::cpu::InstructionSet;
+use tostiloco::instruction::Instruction;
+use tostiloco::reference::{execute_and_check, is_known_instruction};
+
+fuzz_target!(|data: &[u8]| {
+    let Some((&set, data)) = data.split_first() else {
+        return;
+    };
+    let instruction_set = match set % 3 {
+        0 => InstructionSet::Chip8,
+        1 => InstructionSet::Superchip,
+        _ => InstructionSet::XoChip,
+    };
+    let mut chip8 = Chip8::new(Chip8Platform::MOCK);
+    chip8.cpu.instruction_set = instruction_set;
+    chip8.reset();
+
+    let instructions = data
+        .chunks_exact(2)
+        .map(|bytes| Instruction::new(u16::from_be_bytes([bytes[0], bytes[1]])))
+        .filter(|instruction| is_known_instruction(instruction, instruction_set));
+    for instruction in instructions {
+        execute_and_check(&mut chip8, instruction);
+    }
+});
@@ -0,0 +1,233 @@
+//! Zip archives of roms, e.g. a game jam's entries downloaded in one go. Only the entries
+//! with a rom's extension are kept, and the extension says which platform each was
+//! written for.
+
+use crate::cpu::InstructionSet;
+use crate::quirks::Quirks;
+use std::fmt;
+use std::io::{Cursor, Read};
+use std::path::Path;
+
+// every zip starts with a local file header
+const ZIP_SIGNATURE: &[u8] = b"PK\x03\x04";
+// no instruction set can load more than this, anything bigger isn't unpacked
+const MAX_ROM_SIZE: u64 = 0x10000;
+
+/// The machine a rom was written for, going by its file's extension
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RomPlatform {
+    /// `.ch8`
+    Chip8,
+    /// `.sc8`
+    Superchip,
+    /// `.xo8`
+    XoChip,
+}
+
+impl RomPlatform {
+    pub fn from_file_name(name: &str) -> Option<Self> {
+        let extension = Path::new(name).extension()?.to_str()?;
+        match extension.to_ascii_lowercase().as_str() {
+            "ch8" => Some(RomPlatform::Chip8),
+            "sc8" => Some(RomPlatform::Superchip),
+            "xo8" => Some(RomPlatform::XoChip),
+            _ => None,
+        }
+    }
+
+    /// The quirks the platform's roms expect. Plain CHIP-8 roms keep the ones picked by
+    /// hand since interpreters never agreed on theirs.
+    pub fn quirks(self) -> Option<Quirks> {
+        match self {
+            RomPlatform::Chip8 => None,
+            RomPlatform::Superchip => Some(Quirks::superchip()),
+            RomPlatform::XoChip => Some(Quirks::xo_chip()),
+        }
+    }
+
+    pub fn instruction_set(self) -> InstructionSet {
+        match self {
+            RomPlatform::Chip8 => InstructionSet::Chip8,
+            RomPlatform::Superchip => InstructionSet::Superchip,
+            RomPlatform::XoChip => InstructionSet::XoChip,
+        }
+    }
+}
+
+/// Why an archive couldn't be opened
+#[derive(Clone, Debug, PartialEq)]
+pub enum ArchiveError {
+    /// Not a zip, or a damaged one
+    Zip(String),
+    /// Nothing in it has a rom's extension
+    NoRoms,
+}
+
+impl fmt::Display for ArchiveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ArchiveError::Zip(e) => write!(f, "Couldn't read the archive: {}", e),
+            ArchiveError::NoRoms => write!(f, "The archive has no .ch8, .sc8 or .xo8 roms"),
+        }
+    }
+}
+
+impl std::error::Error for ArchiveError {}
+
+pub struct ArchiveRom {
+    /// Its path inside the archive
+    pub name: String,
+    pub platform: RomPlatform,
+    pub bytes: Vec<u8>,
+}
+
+/// The roms in a zip, unpacked and sorted by name
+pub struct RomArchive {
+    roms: Vec<ArchiveRom>,
+}
+
+impl RomArchive {
+    pub fn read(zip_bytes: &[u8]) -> Result<Self, ArchiveError> {
+        let zip_error = |e: zip::result::ZipError| ArchiveError::Zip(e.to_string());
+        let mut zip = zip::ZipArchive::new(Cursor::new(zip_bytes)).map_err(zip_error)?;
+
+        let mut roms = Vec::new();
+        for index in 0..zip.len() {
+            let file = zip.by_index(index).map_err(zip_error)?;
+            // macOS puts a resource fork next to every file it zips
+            if !file.is_file() || file.name().starts_with("__MACOSX/") {
+                continue;
+            }
+            let Some(platform) = RomPlatform::from_file_name(file.name()) else {
+                continue;
+            };
+            // a few bytes of zip can inflate to gigabytes, so none of it is trusted
+            if file.size() > MAX_ROM_SIZE {
+                continue;
+            }
+            let name = file.name().to_string();
+            let mut bytes = Vec::new();
+            file.take(MAX_ROM_SIZE + 1)
+                .read_to_end(&mut bytes)
+                .map_err(|e| ArchiveError::Zip(e.to_string()))?;
+            if bytes.len() as u64 > MAX_ROM_SIZE {
+                continue;
+            }
+            roms.push(ArchiveRom {
+                name,
+                platform,
+                bytes,
+            });
+        }
+
+        if roms.is_empty() {
+            return Err(ArchiveError::NoRoms);
+        }
+        roms.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(Self { roms })
+    }
+
+    pub fn names(&self) -> Vec<&str> {
+        self.roms.iter().map(|rom| rom.name.as_str()).collect()
+    }
+
+    pub fn rom(&self, name: &str) -> Option<&ArchiveRom> {
+        self.roms.iter().find(|rom| rom.name == name)
+    }
+}
+
+/// Whether the bytes are a zip rather than a rom
+pub fn is_zip(bytes: &[u8]) -> bool {
+    bytes.starts_with(ZIP_SIGNATURE)
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use super::*;
+    use std::io::Write;
+
+    /// A zip of the named files, compressed the way most zips are
+    pub(crate) fn zip_files(files: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut writer = zip::ZipWriter::new(Cursor::new(Vec::new()));
+        let options =
+            zip::write::FileOptions::default().compression_method(zip::CompressionMethod::Deflated);
+        for (name, bytes) in files {
+            writer.start_file(*name, options).unwrap();
+            writer.write_all(bytes).unwrap();
+        }
+        writer.finish().unwrap().into_inner()
+    }
+
+    #[test]
+    fn platform_from_file_name() {
+        assert_eq!(
+            RomPlatform::from_file_name("pong.ch8"),
+            Some(RomPlatform::Chip8)
+        );
+        assert_eq!(
+            RomPlatform::from_file_name("jam/Spacejam.SC8"),
+            Some(RomPlatform::Superchip)
+        );
+        assert_eq!(
+            RomPlatform::from_file_name("t8nks.xo8"),
+            Some(RomPlatform::XoChip)
+        );
+        assert_eq!(RomPlatform::from_file_name("readme.txt"), None);
+        assert_eq!(RomPlatform::from_file_name("ch8"), None);
+        assert_eq!(RomPlatform::Chip8.quirks(), None);
+        assert!(RomPlatform::Superchip.quirks().unwrap().jump_vx);
+        assert_eq!(
+            RomPlatform::XoChip.instruction_set(),
+            InstructionSet::XoChip
+        );
+    }
+
+    #[test]
+    fn read() {
+        let zip_bytes = zip_files(&[
+            ("readme.txt", b"not a rom"),
+            ("roms/b.xo8", &[0x12, 0x00]),
+            ("__MACOSX/roms/._b.xo8", &[0x00]),
+            ("roms/a.ch8", &[0x00, 0xe0, 0x12, 0x02]),
+        ]);
+        assert!(is_zip(&zip_bytes));
+
+        let archive = RomArchive::read(&zip_bytes).unwrap();
+        assert_eq!(archive.names(), vec!["roms/a.ch8", "roms/b.xo8"]);
+        assert_eq!(
+            archive.rom("roms/a.ch8").unwrap().bytes,
+            vec![0x00, 0xe0, 0x12, 0x02]
+        );
+        let rom = archive.rom("roms/b.xo8").unwrap();
+        assert_eq!(rom.platform, RomPlatform::XoChip);
+        assert_eq!(rom.bytes, vec![0x12, 0x00]);
+        assert!(archive.rom("readme.txt").is_none());
+    }
+
+    #[test]
+    fn read_errors() {
+        assert!(!is_zip(&[0x00, 0xe0]));
+        assert!(matches!(
+            RomArchive::read(b"PK\x03\x04"),
+            Err(ArchiveError::Zip(_))
+        ));
+        assert_eq!(
+            RomArchive::read(&zip_files(&[("readme.txt", b"hi")])).err(),
+            Some(ArchiveError::NoRoms)
+        );
+    }
+
+    #[test]
+    fn read_skips_roms_too_big_to_load() {
+        let too_big = vec![0; MAX_ROM_SIZE as usize + 1];
+        let largest = vec![0; MAX_ROM_SIZE as usize];
+        let zip_bytes = zip_files(&[("big.xo8", &too_big), ("largest.xo8", &largest)]);
+
+        let archive = RomArchive::read(&zip_bytes).unwrap();
+        assert_eq!(archive.names(), vec!["largest.xo8"]);
+        assert_eq!(
+            RomArchive::read(&zip_files(&[("big.xo8", &too_big)])).err(),
+            Some(ArchiveError::NoRoms)
+        );
+    }
+}
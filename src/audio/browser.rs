@@ -1,53 +1,205 @@
+use crate::audio::synth::{Synth, ToneSettings};
+use crate::audio::wav::encode_wav;
 use crate::audio::AudioSource;
-use web_sys::{AudioContext, OscillatorNode, OscillatorType};
+use js_sys::{Array, Float32Array};
+use std::cell::RefCell;
+use std::rc::Rc;
+use wasm_bindgen::prelude::*;
+use web_sys::{AudioContext, AudioWorkletNode, Blob, BlobPropertyBag, Url};
 
-pub struct BrowserAudioSource {
+const PROCESSOR_NAME: &str = "chip8-buzzer";
+
+// plays back the frames of samples posted to it in order, holding a few back before it
+// starts so a late frame doesn't cut the sound out, and dropping the oldest if the page
+// gets too far ahead so the latency can't build up. Runs dry into silence.
+const PROCESSOR_SOURCE: &str = r#"
+const PREBUFFER_FRAMES = 2;
+const MAX_FRAMES = 6;
+
+class Chip8BuzzerProcessor extends AudioWorkletProcessor {
+    constructor() {
+        super();
+        this.frames = [];
+        this.offset = 0;
+        this.buffering = true;
+        this.port.onmessage = (event) => {
+            this.frames.push(event.data);
+            while (this.frames.length > MAX_FRAMES) {
+                this.frames.shift();
+                this.offset = 0;
+            }
+        };
+    }
+
+    process(inputs, outputs) {
+        const channels = outputs[0];
+        const output = channels[0];
+        let written = 0;
+
+        if (this.buffering && this.frames.length >= PREBUFFER_FRAMES) {
+            this.buffering = false;
+        }
+        while (!this.buffering && written < output.length && this.frames.length > 0) {
+            const frame = this.frames[0];
+            const count = Math.min(output.length - written, frame.length - this.offset);
+            output.set(frame.subarray(this.offset, this.offset + count), written);
+            written += count;
+            this.offset += count;
+            if (this.offset === frame.length) {
+                this.frames.shift();
+                this.offset = 0;
+            }
+        }
+        if (written < output.length) {
+            output.fill(0, written);
+            this.buffering = true;
+        }
+
+        for (let channel = 1; channel < channels.length; channel++) {
+            channels[channel].set(output);
+        }
+        return true;
+    }
+}
+
+registerProcessor("chip8-buzzer", Chip8BuzzerProcessor);
+"#;
+
+struct WorkletOutput {
     context: AudioContext,
-    current_oscillator: Option<OscillatorNode>,
-    is_playing: bool,
+    // filled in once the processor's module has loaded
+    node: Rc<RefCell<Option<AudioWorkletNode>>>,
+}
+
+/// Generates the buzzer's samples with `Synth` and streams them a frame at a time to an
+/// AudioWorklet. Nothing is created until `initialize`, which has to come from a user
+/// gesture or the browser won't let the AudioContext start.
+pub struct BrowserAudioSource {
+    output: Option<WorkletOutput>,
+    pub synth: Synth,
+    // every frame's samples since recording started, silent ones included
+    recording: Option<Vec<f32>>,
+    // only keeps the samples from the speakers, a recording still gets them
+    muted: bool,
 }
 
 impl Default for BrowserAudioSource {
     fn default() -> Self {
-        // todo: delay this to later since browsers don't allow audiocontexts created prior
-        // to user action
-        let context = AudioContext::new().unwrap();
         Self {
-            context,
-            current_oscillator: None,
-            is_playing: false,
+            output: None,
+            // replaced by the context's own rate when it's created
+            synth: Synth::new(48000.0),
+            recording: None,
+            muted: false,
         }
     }
 }
 
 impl BrowserAudioSource {
-    fn play_oscillator(&mut self) {
-        let oscillator = self.context.create_oscillator().unwrap();
-        oscillator.connect_with_audio_node(&self.context.destination()).unwrap();
-        oscillator.set_type(OscillatorType::Sine);
-        oscillator.frequency().set_value(440.0);
-        oscillator.start().unwrap();
+    fn create_output(&mut self) -> Result<WorkletOutput, JsValue> {
+        let context = AudioContext::new()?;
+        self.synth.set_sample_rate(context.sample_rate());
+
+        // the processor has to be loaded from a url, so it's served out of a blob
+        let options = BlobPropertyBag::new();
+        options.set_type("application/javascript");
+        let blob = Blob::new_with_str_sequence_and_options(
+            &Array::of1(&JsValue::from_str(PROCESSOR_SOURCE)),
+            &options,
+        )?;
+        let url = Url::create_object_url_with_blob(&blob)?;
+        let module_loaded = context.audio_worklet()?.add_module(&url)?;
+
+        let node = Rc::new(RefCell::new(None));
+        let on_loaded = {
+            let context = context.clone();
+            let node = node.clone();
+            // a panic in here would take the whole emulator down with it
+            Closure::once(move |_: JsValue| match connect_worklet(&context) {
+                Ok(worklet_node) => *node.borrow_mut() = Some(worklet_node),
+                Err(e) => log::warn!("Couldn't start audio: {:?}", e),
+            })
+        };
+        let on_failed = Closure::once(|e: JsValue| {
+            log::warn!("Couldn't load the audio processor: {:?}", e);
+        });
+        let _ = module_loaded.then2(&on_loaded, &on_failed);
+        on_loaded.forget();
+        on_failed.forget();
 
-        self.current_oscillator = Some(oscillator);
+        Ok(WorkletOutput { context, node })
     }
 }
 
+/// The processor's node, playing through the speakers
+fn connect_worklet(context: &AudioContext) -> Result<AudioWorkletNode, JsValue> {
+    let worklet_node = AudioWorkletNode::new(context, PROCESSOR_NAME)?;
+    worklet_node.connect_with_audio_node(&context.destination())?;
+    Ok(worklet_node)
+}
+
 impl AudioSource for BrowserAudioSource {
     fn initialize(&mut self) {
-        self.context = AudioContext::new().unwrap();
+        match &self.output {
+            Some(output) => {
+                let _ = output.context.resume();
+            }
+            None => match self.create_output() {
+                Ok(output) => self.output = Some(output),
+                Err(e) => log::warn!("Couldn't start audio: {:?}", e),
+            },
+        }
     }
 
     fn start_sound(&mut self) {
-        if !self.is_playing {
-            self.play_oscillator();
-        }
-        self.is_playing = true;
+        self.synth.set_gate(true);
     }
 
     fn stop_sound(&mut self) {
-        if self.is_playing {
-            self.current_oscillator.as_ref().unwrap().stop().unwrap();
+        self.synth.set_gate(false);
+    }
+
+    fn set_pattern(&mut self, pattern: Option<[u8; 16]>) {
+        self.synth.set_pattern(pattern);
+    }
+
+    fn set_pitch(&mut self, pitch: u8) {
+        self.synth.set_pitch(pitch);
+    }
+
+    fn set_tone(&mut self, tone: &ToneSettings) {
+        self.synth.set_tone(tone);
+        self.muted = tone.muted;
+    }
+
+    fn end_frame(&mut self) {
+        // the processor plays silence on its own once it runs out, silent or muted frames
+        // only need rendering to keep a recording in time
+        let inaudible = self.synth.is_silent() || self.muted;
+        if inaudible && self.recording.is_none() {
+            return;
+        }
+        let samples = self.synth.render_frame();
+        if let Some(recording) = &mut self.recording {
+            recording.extend_from_slice(&samples);
+        }
+
+        if self.muted {
+            return;
         }
-        self.is_playing = false;
+        if let Some(node) = self.output.as_ref().and_then(|o| o.node.borrow().clone()) {
+            let _ = node
+                .port()
+                .and_then(|port| port.post_message(&Float32Array::from(&samples[..])));
+        }
+    }
+
+    fn start_recording(&mut self) {
+        self.recording = Some(Vec::new());
+    }
+
+    fn stop_recording(&mut self) -> Option<Vec<u8>> {
+        let samples = self.recording.take()?;
+        Some(encode_wav(&samples, self.synth.sample_rate() as u32))
     }
 }
@@ -1,20 +1,89 @@
+use crate::audio::synth::{ToneSettings, DEFAULT_PITCH};
 use crate::audio::AudioSource;
 
-#[derive(Default)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AudioEvent {
+    Start,
+    Stop,
+}
+
 pub struct MockAudioSource {
     pub is_playing: bool,
+    pub pattern: Option<[u8; 16]>,
+    pub pitch: u8,
+    pub tone: ToneSettings,
+    /// When the sound started and stopped, by the frame the timers were handled in
+    pub timeline: Vec<(u32, AudioEvent)>,
+    // frames ended since initialize
+    frame: u32,
+}
+
+impl Default for MockAudioSource {
+    fn default() -> Self {
+        Self {
+            is_playing: false,
+            pattern: None,
+            pitch: DEFAULT_PITCH,
+            tone: ToneSettings::default(),
+            timeline: Vec::new(),
+            frame: 0,
+        }
+    }
+}
+
+impl MockAudioSource {
+    /// How many frames the sound has played for in total
+    pub fn frames_of_sound(&self) -> u32 {
+        let mut frames = 0;
+        let mut started = None;
+        for &(frame, event) in &self.timeline {
+            match event {
+                AudioEvent::Start => started = Some(frame),
+                AudioEvent::Stop => frames += frame - started.take().unwrap(),
+            }
+        }
+        // still playing, count the frames that have ended since it started
+        if let Some(start) = started {
+            frames += self.frame - start;
+        }
+        frames
+    }
 }
 
 impl AudioSource for MockAudioSource {
     fn initialize(&mut self) {
         self.is_playing = false;
+        self.timeline.clear();
+        self.frame = 0;
     }
 
     fn start_sound(&mut self) {
+        if !self.is_playing {
+            self.timeline.push((self.frame, AudioEvent::Start));
+        }
         self.is_playing = true;
     }
 
     fn stop_sound(&mut self) {
+        if self.is_playing {
+            self.timeline.push((self.frame, AudioEvent::Stop));
+        }
         self.is_playing = false;
     }
+
+    fn set_pattern(&mut self, pattern: Option<[u8; 16]>) {
+        self.pattern = pattern;
+    }
+
+    fn set_pitch(&mut self, pitch: u8) {
+        self.pitch = pitch;
+    }
+
+    fn set_tone(&mut self, tone: &ToneSettings) {
+        self.tone = *tone;
+    }
+
+    fn end_frame(&mut self) {
+        self.frame += 1;
+    }
 }
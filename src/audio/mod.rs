@@ -1,8 +1,30 @@
 pub mod browser;
 pub mod mock;
+pub mod pcm;
+pub mod synth;
+pub mod wav;
 
-pub trait AudioSource {
+use crate::audio::synth::ToneSettings;
+use crate::display::AsAny;
+
+pub trait AudioSource: AsAny {
     fn initialize(&mut self);
     fn start_sound(&mut self);
     fn stop_sound(&mut self);
+    /// XO-CHIP's 16 byte 1-bit sample to play while the sound timer runs, `None` for the
+    /// plain buzzer tone
+    fn set_pattern(&mut self, pattern: Option<[u8; 16]>);
+    /// XO-CHIP's pitch register, the pattern plays at `4000 * 2^((pitch - 64) / 48)` bits
+    /// a second
+    fn set_pitch(&mut self, pitch: u8);
+    fn set_tone(&mut self, _tone: &ToneSettings) {}
+    /// Called at the end of every frame, after the sound timer has been handled
+    fn end_frame(&mut self) {}
+    /// Starts keeping the samples played from here on
+    fn start_recording(&mut self) {}
+    /// Everything played since `start_recording` as WAV bytes, `None` for a source that
+    /// doesn't generate samples
+    fn stop_recording(&mut self) -> Option<Vec<u8>> {
+        None
+    }
 }
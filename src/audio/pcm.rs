@@ -0,0 +1,159 @@
+use crate::audio::synth::{Synth, ToneSettings};
+use crate::audio::wav::encode_wav;
+use crate::audio::AudioSource;
+
+pub const PCM_SAMPLE_RATE: u32 = 44100;
+
+/// Renders the buzzer to a buffer in memory rather than playing it. While recording, a
+/// frame's worth of samples goes in at the end of every frame whether it's making a sound
+/// or not, so the buffer is a timeline of everything the rom played.
+pub struct PcmAudioSource {
+    pub synth: Synth,
+    // `None` unless recording, nothing is rendered then
+    recording: Option<Vec<f32>>,
+}
+
+impl Default for PcmAudioSource {
+    fn default() -> Self {
+        Self {
+            synth: Synth::new(PCM_SAMPLE_RATE as f32),
+            recording: None,
+        }
+    }
+}
+
+impl PcmAudioSource {
+    /// Everything rendered since recording started, empty if it isn't recording
+    pub fn samples(&self) -> &[f32] {
+        self.recording.as_deref().unwrap_or_default()
+    }
+}
+
+impl AudioSource for PcmAudioSource {
+    fn initialize(&mut self) {
+        if let Some(recording) = &mut self.recording {
+            recording.clear();
+        }
+    }
+
+    fn start_sound(&mut self) {
+        self.synth.set_gate(true);
+    }
+
+    fn stop_sound(&mut self) {
+        self.synth.set_gate(false);
+    }
+
+    fn set_pattern(&mut self, pattern: Option<[u8; 16]>) {
+        self.synth.set_pattern(pattern);
+    }
+
+    fn set_pitch(&mut self, pitch: u8) {
+        self.synth.set_pitch(pitch);
+    }
+
+    fn set_tone(&mut self, tone: &ToneSettings) {
+        self.synth.set_tone(tone);
+    }
+
+    fn end_frame(&mut self) {
+        if let Some(recording) = &mut self.recording {
+            recording.extend_from_slice(&self.synth.render_frame());
+        }
+    }
+
+    fn start_recording(&mut self) {
+        self.recording = Some(Vec::new());
+    }
+
+    fn stop_recording(&mut self) -> Option<Vec<u8>> {
+        let samples = self.recording.take()?;
+        Some(encode_wav(&samples, PCM_SAMPLE_RATE))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::chip8::{Chip8, Chip8Platform};
+    use crate::instruction::Instruction;
+
+    const SAMPLES_PER_FRAME: usize = PCM_SAMPLE_RATE as usize / 60;
+
+    fn pcm_audio(chip8: &Chip8) -> &PcmAudioSource {
+        chip8
+            .bus
+            .audio
+            .as_ref()
+            .as_any()
+            .downcast_ref::<PcmAudioSource>()
+            .unwrap()
+    }
+
+    /// Peak level of each frame's samples
+    fn frame_peaks(samples: &[f32]) -> Vec<f32> {
+        samples
+            .chunks(SAMPLES_PER_FRAME)
+            .map(|frame| frame.iter().fold(0.0, |peak: f32, s| peak.max(s.abs())))
+            .collect()
+    }
+
+    #[test]
+    fn sound_timer_timeline() {
+        let mut chip8 = Chip8::new(Chip8Platform::HEADLESS);
+        chip8.reset();
+        chip8.start_audio_recording();
+        chip8.end_frame();
+
+        chip8.cpu.v_registers[0] = 3;
+        chip8
+            .cpu
+            .execute_instruction(Instruction::new(0xf018), &mut chip8.bus);
+        for _ in 0..5 {
+            chip8.end_frame();
+        }
+
+        let samples = pcm_audio(&chip8).samples();
+        assert_eq!(samples.len(), 6 * SAMPLES_PER_FRAME);
+        let peaks = frame_peaks(samples);
+        // three frames of tone, then the release fades out inside the next frame
+        assert_eq!(peaks[0], 0.0);
+        assert!(peaks[1..4].iter().all(|&peak| peak > 0.2));
+        assert!(samples[4 * SAMPLES_PER_FRAME] > 0.0);
+        assert_eq!(samples[5 * SAMPLES_PER_FRAME - 1], 0.0);
+        assert_eq!(peaks[5], 0.0);
+    }
+
+    #[test]
+    fn stop_recording_as_wav() {
+        let mut audio = PcmAudioSource::default();
+        // nothing is rendered before recording starts
+        audio.end_frame();
+        assert!(audio.samples().is_empty());
+        audio.start_recording();
+        audio.start_sound();
+        audio.end_frame();
+        audio.end_frame();
+
+        let wav_bytes = audio.stop_recording().unwrap();
+        // only what was played after recording started
+        assert_eq!(wav_bytes.len(), 44 + 2 * SAMPLES_PER_FRAME * 2);
+        assert!(audio.samples().is_empty());
+        assert_eq!(audio.stop_recording(), None);
+    }
+
+    #[test]
+    fn recording_while_muted() {
+        let mut audio = PcmAudioSource::default();
+        audio.set_tone(&ToneSettings {
+            muted: true,
+            ..ToneSettings::default()
+        });
+        audio.start_recording();
+        audio.start_sound();
+        audio.end_frame();
+
+        // muting is for the speakers, the recording still has the tone
+        assert!(audio.samples().iter().any(|&s| s != 0.0));
+    }
+}
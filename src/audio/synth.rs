@@ -0,0 +1,376 @@
+use std::f32::consts::TAU;
+
+/// How many frames the emulator ends every second, each one gets its own block of samples
+const FRAMES_PER_SECOND: f32 = 60.0;
+
+// long enough to take the click out of switching the tone on and off without softening
+// the sound timer's shortest beeps
+const ATTACK_SECONDS: f32 = 0.002;
+const RELEASE_SECONDS: f32 = 0.01;
+
+pub const DEFAULT_FREQUENCY: f32 = 440.0;
+pub const DEFAULT_VOLUME: f32 = 0.25;
+/// XO-CHIP's pitch register starts out playing the pattern at 4000 bits a second
+pub const DEFAULT_PITCH: u8 = 64;
+
+// an XO-CHIP pattern is 16 bytes played a bit at a time, most significant bit first
+const PATTERN_BITS: f32 = 128.0;
+
+/// How many bits of the pattern XO-CHIP plays a second at `pitch`
+pub fn pattern_rate(pitch: u8) -> f32 {
+    4000.0 * 2f32.powf((pitch as f32 - 64.0) / 48.0)
+}
+
+/// Names of the waveforms `Waveform::from_name` knows about
+pub const WAVEFORM_NAMES: [&str; 3] = ["square", "sine", "triangle"];
+
+/// Shape of the buzzer's tone
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Waveform {
+    Square,
+    Sine,
+    Triangle,
+}
+
+impl Waveform {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "square" => Some(Waveform::Square),
+            "sine" => Some(Waveform::Sine),
+            "triangle" => Some(Waveform::Triangle),
+            _ => None,
+        }
+    }
+
+    /// The wave's level between -1 and 1, `phase` being how far through a cycle it is
+    fn sample(&self, phase: f32) -> f32 {
+        match self {
+            Waveform::Square => {
+                if phase < 0.5 {
+                    1.0
+                } else {
+                    -1.0
+                }
+            }
+            Waveform::Sine => (phase * TAU).sin(),
+            Waveform::Triangle => 4.0 * (phase - 0.5).abs() - 1.0,
+        }
+    }
+}
+
+/// What the player has picked for the buzzer. The pattern an XO-CHIP rom loads replaces
+/// the waveform and frequency but still plays at this volume.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ToneSettings {
+    /// 0.0 to 1.0
+    pub volume: f32,
+    pub muted: bool,
+    pub waveform: Waveform,
+    /// In hz, 20.0 to 20000.0
+    pub frequency: f32,
+}
+
+impl Default for ToneSettings {
+    fn default() -> Self {
+        Self {
+            volume: DEFAULT_VOLUME,
+            muted: false,
+            waveform: Waveform::Square,
+            frequency: DEFAULT_FREQUENCY,
+        }
+    }
+}
+
+impl ToneSettings {
+    /// Keeps the volume and frequency to something that can actually be played, anything
+    /// that isn't a number at all goes back to the default
+    pub fn clamped(self) -> Self {
+        Self {
+            volume: finite_or(self.volume, DEFAULT_VOLUME).clamp(0.0, 1.0),
+            frequency: finite_or(self.frequency, DEFAULT_FREQUENCY).clamp(20.0, 20000.0),
+            ..self
+        }
+    }
+}
+
+fn finite_or(value: f32, default: f32) -> f32 {
+    if value.is_finite() {
+        value
+    } else {
+        default
+    }
+}
+
+/// Generates the buzzer's samples a frame at a time. The tone fades in and out over a few
+/// milliseconds when the gate opens and closes rather than starting and stopping dead.
+pub struct Synth {
+    sample_rate: f32,
+    pub waveform: Waveform,
+    pub frequency: f32,
+    volume: f32,
+    gate: bool,
+    // 0 for silent up to 1 for full volume
+    envelope: f32,
+    // how far through the current cycle, 0 to 1
+    phase: f32,
+    // an XO-CHIP rom's own 1-bit sample, played in place of the waveform once it loads one
+    pattern: Option<[u8; 16]>,
+    pitch: u8,
+    // which bit of the pattern is playing, 0 to 128
+    pattern_position: f32,
+    // fraction of a sample left over from the last frame, so frames with a rate that
+    // doesn't divide by 60 still average out to the right length
+    frame_remainder: f32,
+}
+
+impl Synth {
+    pub fn new(sample_rate: f32) -> Self {
+        Self {
+            sample_rate,
+            waveform: Waveform::Square,
+            frequency: DEFAULT_FREQUENCY,
+            volume: DEFAULT_VOLUME,
+            gate: false,
+            envelope: 0.0,
+            phase: 0.0,
+            pattern: None,
+            pitch: DEFAULT_PITCH,
+            pattern_position: 0.0,
+            frame_remainder: 0.0,
+        }
+    }
+
+    pub fn set_sample_rate(&mut self, sample_rate: f32) {
+        self.sample_rate = sample_rate;
+        self.frame_remainder = 0.0;
+    }
+
+    pub fn sample_rate(&self) -> f32 {
+        self.sample_rate
+    }
+
+    /// Muting is left to the output, a recording still gets the sound
+    pub fn set_tone(&mut self, tone: &ToneSettings) {
+        let tone = tone.clamped();
+        self.set_volume(tone.volume);
+        self.waveform = tone.waveform;
+        self.frequency = tone.frequency;
+    }
+
+    pub fn set_volume(&mut self, volume: f32) {
+        self.volume = finite_or(volume, DEFAULT_VOLUME).clamp(0.0, 1.0);
+    }
+
+    pub fn volume(&self) -> f32 {
+        self.volume
+    }
+
+    /// Opens or closes the gate, the tone fades towards full or nothing from wherever it is
+    pub fn set_gate(&mut self, gate: bool) {
+        self.gate = gate;
+    }
+
+    /// Plays `pattern` instead of the waveform, or goes back to the waveform for `None`
+    pub fn set_pattern(&mut self, pattern: Option<[u8; 16]>) {
+        self.pattern = pattern;
+        self.pattern_position = 0.0;
+    }
+
+    pub fn set_pitch(&mut self, pitch: u8) {
+        self.pitch = pitch;
+    }
+
+    /// Whether the tone has finished fading out with nothing to play until the gate opens
+    pub fn is_silent(&self) -> bool {
+        !self.gate && self.envelope == 0.0
+    }
+
+    pub fn fill(&mut self, samples: &mut [f32]) {
+        let attack_step = 1.0 / (ATTACK_SECONDS * self.sample_rate);
+        let release_step = 1.0 / (RELEASE_SECONDS * self.sample_rate);
+        let phase_step = self.frequency / self.sample_rate;
+        let pattern_step = pattern_rate(self.pitch) / self.sample_rate;
+
+        for sample in samples {
+            self.envelope = if self.gate {
+                (self.envelope + attack_step).min(1.0)
+            } else {
+                (self.envelope - release_step).max(0.0)
+            };
+            let level = match &self.pattern {
+                Some(pattern) => {
+                    let bit = self.pattern_position as usize;
+                    self.pattern_position = (self.pattern_position + pattern_step) % PATTERN_BITS;
+                    if pattern[bit / 8] & (0x80 >> (bit % 8)) != 0 {
+                        1.0
+                    } else {
+                        -1.0
+                    }
+                }
+                None => {
+                    let level = self.waveform.sample(self.phase);
+                    self.phase = (self.phase + phase_step).fract();
+                    level
+                }
+            };
+            *sample = level * self.volume * self.envelope;
+        }
+    }
+
+    /// The samples for one 60hz frame
+    pub fn render_frame(&mut self) -> Vec<f32> {
+        let exact = self.sample_rate / FRAMES_PER_SECOND + self.frame_remainder;
+        let count = exact.floor();
+        self.frame_remainder = exact - count;
+
+        let mut samples = vec![0.0; count as usize];
+        self.fill(&mut samples);
+        samples
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn envelope_fades_in_and_out() {
+        let mut synth = Synth::new(48000.0);
+        synth.set_volume(1.0);
+        assert!(synth.is_silent());
+
+        synth.set_gate(true);
+        let mut samples = [0.0; 200];
+        synth.fill(&mut samples);
+        // the attack takes 96 samples, square wave so every sample is at the envelope
+        assert!(samples[0].abs() < 0.02);
+        assert!((samples[47].abs() - 0.5).abs() < 0.02);
+        assert_eq!(samples[150].abs(), 1.0);
+
+        synth.set_gate(false);
+        synth.fill(&mut samples);
+        assert!(!synth.is_silent());
+        assert!(samples[0].abs() > 0.99);
+        synth.fill(&mut samples);
+        synth.fill(&mut samples);
+        // 480 samples of release
+        assert!(synth.is_silent());
+        synth.fill(&mut samples);
+        assert!(samples.iter().all(|&s| s == 0.0));
+    }
+
+    #[test]
+    fn waveforms() {
+        for (waveform, quarter) in [
+            (Waveform::Square, 1.0),
+            (Waveform::Sine, 1.0),
+            (Waveform::Triangle, 0.0),
+        ] {
+            assert_eq!(waveform.sample(0.25), quarter, "{:?}", waveform);
+            for i in 0..100 {
+                let level = waveform.sample(i as f32 / 100.0);
+                assert!((-1.0..=1.0).contains(&level), "{:?}", waveform);
+            }
+        }
+        assert_eq!(Waveform::Triangle.sample(0.0), 1.0);
+        assert_eq!(Waveform::Triangle.sample(0.5), -1.0);
+    }
+
+    #[test]
+    fn frequency_and_volume() {
+        let mut synth = Synth::new(8000.0);
+        synth.set_volume(2.0);
+        assert_eq!(synth.volume(), 1.0);
+        synth.set_volume(0.5);
+        synth.set_gate(true);
+
+        // past the attack, 500hz at 8khz flips every 8 samples and 250hz every 16
+        let mut samples = [0.0; 64];
+        let flips = |samples: &[f32]| samples.windows(2).filter(|w| w[0] != w[1]).count();
+        synth.frequency = 500.0;
+        synth.fill(&mut samples);
+        synth.fill(&mut samples);
+        assert!((7..=8).contains(&flips(&samples)));
+        synth.frequency = 250.0;
+        synth.fill(&mut samples);
+        assert!((3..=4).contains(&flips(&samples)));
+        assert!(samples.iter().all(|&s| s.abs() == 0.5));
+    }
+
+    #[test]
+    fn set_tone() {
+        let mut synth = Synth::new(8000.0);
+        let tone = ToneSettings {
+            volume: 0.8,
+            muted: false,
+            waveform: Waveform::from_name("triangle").unwrap(),
+            frequency: 1.0,
+        };
+        synth.set_tone(&tone);
+        assert_eq!(synth.volume(), 0.8);
+        assert_eq!(synth.waveform, Waveform::Triangle);
+        assert_eq!(synth.frequency, 20.0);
+
+        // muting is up to the output, the synth keeps playing for recordings
+        synth.set_tone(&ToneSettings {
+            muted: true,
+            ..tone
+        });
+        assert_eq!(synth.volume(), 0.8);
+        assert_eq!(Waveform::from_name("sawtooth"), None);
+
+        synth.set_tone(&ToneSettings {
+            volume: f32::NAN,
+            frequency: f32::INFINITY,
+            ..tone
+        });
+        assert_eq!(synth.volume(), DEFAULT_VOLUME);
+        assert_eq!(synth.frequency, DEFAULT_FREQUENCY);
+        synth.set_volume(f32::NAN);
+        assert_eq!(synth.volume(), DEFAULT_VOLUME);
+    }
+
+    #[test]
+    fn pattern_playback() {
+        assert_eq!(pattern_rate(DEFAULT_PITCH), 4000.0);
+        assert!((pattern_rate(112) - 8000.0).abs() < 0.01);
+        assert!((pattern_rate(16) - 2000.0).abs() < 0.01);
+
+        // at 4000 bits a second and 8khz every bit is two samples long, so a byte of ones
+        // then a byte of zeroes is 16 samples high and 16 low
+        let mut synth = Synth::new(8000.0);
+        synth.set_volume(1.0);
+        let mut pattern = [0; 16];
+        pattern[0] = 0xff;
+        synth.set_pattern(Some(pattern));
+        synth.set_gate(true);
+
+        // two whole passes through the pattern to get past the attack
+        let mut samples = [0.0; 512];
+        synth.fill(&mut samples);
+        synth.fill(&mut samples[0..256]);
+        let levels: Vec<f32> = samples.iter().map(|s| s.signum()).collect();
+        assert!(levels[1..15].iter().all(|&l| l == 1.0));
+        assert!(levels[17..255].iter().all(|&l| l == -1.0));
+        assert!(levels[257..271].iter().all(|&l| l == 1.0));
+
+        // an octave up is a sample a bit
+        synth.set_pitch(112);
+        synth.set_pattern(Some(pattern));
+        synth.fill(&mut samples[0..32]);
+        let levels: Vec<f32> = samples.iter().map(|s| s.signum()).collect();
+        assert!(levels[1..7].iter().all(|&l| l == 1.0));
+        assert!(levels[9..31].iter().all(|&l| l == -1.0));
+    }
+
+    #[test]
+    fn render_frame_keeps_the_remainder() {
+        let mut synth = Synth::new(44100.0);
+        assert_eq!(synth.render_frame().len(), 735);
+
+        // 22050 / 60 = 367.5
+        synth.set_sample_rate(22050.0);
+        let lengths: Vec<usize> = (0..4).map(|_| synth.render_frame().len()).collect();
+        assert_eq!(lengths, vec![367, 368, 367, 368]);
+    }
+}
@@ -0,0 +1,60 @@
+/// Encodes samples between -1 and 1 as a mono 16-bit PCM WAV file
+pub fn encode_wav(samples: &[f32], sample_rate: u32) -> Vec<u8> {
+    const BYTES_PER_SAMPLE: u32 = 2;
+    let data_size = samples.len() as u32 * BYTES_PER_SAMPLE;
+
+    let mut wav_bytes = Vec::with_capacity(44 + data_size as usize);
+    wav_bytes.extend_from_slice(b"RIFF");
+    wav_bytes.extend_from_slice(&(36 + data_size).to_le_bytes());
+    wav_bytes.extend_from_slice(b"WAVE");
+
+    wav_bytes.extend_from_slice(b"fmt ");
+    wav_bytes.extend_from_slice(&16u32.to_le_bytes());
+    // uncompressed pcm, one channel
+    wav_bytes.extend_from_slice(&1u16.to_le_bytes());
+    wav_bytes.extend_from_slice(&1u16.to_le_bytes());
+    wav_bytes.extend_from_slice(&sample_rate.to_le_bytes());
+    wav_bytes.extend_from_slice(&(sample_rate * BYTES_PER_SAMPLE).to_le_bytes());
+    wav_bytes.extend_from_slice(&(BYTES_PER_SAMPLE as u16).to_le_bytes());
+    wav_bytes.extend_from_slice(&16u16.to_le_bytes());
+
+    wav_bytes.extend_from_slice(b"data");
+    wav_bytes.extend_from_slice(&data_size.to_le_bytes());
+    for sample in samples {
+        let level = (sample.clamp(-1.0, 1.0) * i16::MAX as f32).round() as i16;
+        wav_bytes.extend_from_slice(&level.to_le_bytes());
+    }
+
+    wav_bytes
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn u32_at(bytes: &[u8], offset: usize) -> u32 {
+        u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+    }
+
+    fn i16_at(bytes: &[u8], offset: usize) -> i16 {
+        i16::from_le_bytes(bytes[offset..offset + 2].try_into().unwrap())
+    }
+
+    #[test]
+    fn encode_wav_header_and_samples() {
+        let wav_bytes = encode_wav(&[0.0, 1.0, -1.0, 0.5, 2.0], 44100);
+
+        assert_eq!(wav_bytes.len(), 44 + 10);
+        assert_eq!(&wav_bytes[0..4], b"RIFF");
+        assert_eq!(u32_at(&wav_bytes, 4), 36 + 10);
+        assert_eq!(&wav_bytes[8..16], b"WAVEfmt ");
+        assert_eq!(u32_at(&wav_bytes, 24), 44100);
+        assert_eq!(u32_at(&wav_bytes, 28), 88200);
+        assert_eq!(&wav_bytes[36..40], b"data");
+        assert_eq!(u32_at(&wav_bytes, 40), 10);
+
+        let levels: Vec<i16> = (0..5).map(|i| i16_at(&wav_bytes, 44 + i * 2)).collect();
+        // anything out of range is clipped
+        assert_eq!(levels, vec![0, 32767, -32767, 16384, 32767]);
+    }
+}
@@ -0,0 +1,158 @@
+//! Runs a rom natively without a browser and saves what ended up on screen.
+//!
+//! cargo run --bin headless -- <rom> [--frames n] [--screenshot out.png] [--gif out.gif]
+//!     [--wav out.wav] [--scale n] [--database dir] [--rom name]
+//!     [--flags dir]
+//!
+//! A zip of roms runs the first one in it that loads, or the one `--rom` names. The rom's
+//! extension, or its entry's in the zip, says whether it's CHIP-8, SUPER-CHIP or XO-CHIP.
+//!
+//! SUPER-CHIP's RPL flags are saved in `rpl-flags/`, or wherever `--flags` says, with a
+//! file for each rom named after its hash.
+//!
+//! Roms found in the chip-8-database run with its settings, from `database/` unless
+//! `--database` says otherwise.
+
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::process;
+use tostiloco::chip8::{Chip8, Chip8Platform};
+use tostiloco::database::RomDatabase;
+use tostiloco::flags::file::FileFlagStore;
+use tostiloco::flags::RplFlags;
+
+const USAGE: &str = "usage: headless <rom> [--frames n] [--screenshot out.png] [--gif out.gif] \
+                     [--wav out.wav] [--scale n] [--database dir] [--rom name] [--flags dir]";
+const DEFAULT_DATABASE_DIR: &str = "database";
+const DEFAULT_FLAGS_DIR: &str = "rpl-flags";
+
+struct Options {
+    rom_path: String,
+    frames: u32,
+    screenshot_path: Option<String>,
+    // every frame that was run, e.g. a rom's attract mode
+    gif_path: Option<String>,
+    wav_path: Option<String>,
+    scale: usize,
+    database_dir: Option<String>,
+    // which rom to run out of a zip
+    archive_rom: Option<String>,
+    flags_dir: String,
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Options, String> {
+    let mut options = Options {
+        rom_path: String::new(),
+        frames: 60,
+        screenshot_path: None,
+        gif_path: None,
+        wav_path: None,
+        scale: 1,
+        database_dir: None,
+        archive_rom: None,
+        flags_dir: String::from(DEFAULT_FLAGS_DIR),
+    };
+
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| args.next().ok_or_else(|| format!("{} needs a value", name));
+        match arg.as_str() {
+            "--frames" => {
+                options.frames = value("--frames")?
+                    .parse()
+                    .map_err(|_| String::from("--frames must be a number"))?
+            }
+            "--screenshot" => options.screenshot_path = Some(value("--screenshot")?),
+            "--gif" => options.gif_path = Some(value("--gif")?),
+            "--wav" => options.wav_path = Some(value("--wav")?),
+            "--database" => options.database_dir = Some(value("--database")?),
+            "--rom" => options.archive_rom = Some(value("--rom")?),
+            "--flags" => options.flags_dir = value("--flags")?,
+            "--scale" => {
+                options.scale = value("--scale")?
+                    .parse()
+                    .map_err(|_| String::from("--scale must be a number"))?
+            }
+            _ if arg.starts_with("--") => return Err(format!("Unknown option {}", arg)),
+            _ => options.rom_path = arg,
+        }
+    }
+
+    if options.rom_path.is_empty() {
+        return Err(String::from(USAGE));
+    }
+    Ok(options)
+}
+
+fn run(options: Options) -> Result<(), String> {
+    let rom_bytes = fs::read(&options.rom_path)
+        .map_err(|e| format!("Couldn't read {}: {}", options.rom_path, e))?;
+
+    let mut chip8 = Chip8::new(Chip8Platform::HEADLESS);
+    chip8.bus.flags = RplFlags::new(Box::new(FileFlagStore::new(&options.flags_dir)));
+    match options.database_dir {
+        Some(dir) => chip8.database = load_database(Path::new(&dir))?,
+        // it's fine not to have downloaded the default one
+        None if Path::new(DEFAULT_DATABASE_DIR).is_dir() => {
+            chip8.database = load_database(Path::new(DEFAULT_DATABASE_DIR))?
+        }
+        None => {}
+    }
+    let loaded = chip8.load_rom_file(&options.rom_path, &rom_bytes);
+    let settings = match &options.archive_rom {
+        // the one asked for can load even if none before it in the zip would
+        Some(name) if !chip8.archive_rom_names().is_empty() => chip8
+            .load_archive_rom(name)
+            .map_err(|e| format!("Couldn't load {}: {}", name, e))?,
+        _ => loaded.map_err(|e| format!("Couldn't load {}: {}", options.rom_path, e))?,
+    };
+    if let Some(settings) = settings {
+        println!("{} ({})", settings.title, settings.platform);
+    }
+    if options.gif_path.is_some() {
+        chip8.start_recording();
+    }
+    if options.wav_path.is_some() {
+        chip8.start_audio_recording();
+    }
+    for _ in 0..options.frames {
+        chip8.step_frame();
+    }
+
+    if let Some(path) = options.screenshot_path {
+        let png_bytes = chip8.screenshot(options.scale).map_err(|e| e.to_string())?;
+        fs::write(&path, png_bytes).map_err(|e| format!("Couldn't write {}: {}", path, e))?;
+    }
+    if let Some(path) = options.gif_path {
+        let gif_bytes = chip8
+            .stop_recording(options.scale)
+            .map_err(|e| e.to_string())?;
+        fs::write(&path, gif_bytes).map_err(|e| format!("Couldn't write {}: {}", path, e))?;
+    }
+    if let Some(path) = options.wav_path {
+        // the headless platform renders its audio while recording
+        let wav_bytes = chip8.stop_audio_recording().unwrap();
+        fs::write(&path, wav_bytes).map_err(|e| format!("Couldn't write {}: {}", path, e))?;
+    }
+    Ok(())
+}
+
+fn load_database(dir: &Path) -> Result<RomDatabase, String> {
+    let read = |name: &str| {
+        let path = dir.join(name);
+        fs::read_to_string(&path).map_err(|e| format!("Couldn't read {}: {}", path.display(), e))
+    };
+    RomDatabase::from_json(
+        &read("sha1-hashes.json")?,
+        &read("programs.json")?,
+        &read("platforms.json")?,
+    )
+    .map_err(|e| format!("Couldn't read the ROM database in {}: {}", dir.display(), e))
+}
+
+fn main() {
+    if let Err(message) = parse_args(env::args().skip(1)).and_then(run) {
+        eprintln!("{}", message);
+        process::exit(1);
+    }
+}
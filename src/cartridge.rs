@@ -0,0 +1,284 @@
+//! Octo "cartridges", GIFs with a label drawn on them and the program hidden in the low two
+//! bits of every pixel. Octojam entries are shared this way. The payload is Octo's JSON:
+//! the program's source, compiled with `octo::compile`, and the options Octo ran it with.
+
+use crate::display::palette::{Color, Palette};
+use crate::octo::{self, CompileError};
+use crate::quirks::{MemoryIncrement, Quirks};
+use serde::Deserialize;
+use std::fmt;
+
+// every gif starts with "GIF87a" or "GIF89a"
+const GIF_SIGNATURE: &[u8] = b"GIF8";
+// the payload's length comes first as a 32 bit big endian number
+const LENGTH_BYTES: usize = 4;
+
+/// Why a cartridge couldn't be read
+#[derive(Clone, Debug, PartialEq)]
+pub enum CartridgeError {
+    /// Not a GIF the decoder could read
+    Image(String),
+    /// Says its payload is longer than what the image holds
+    Truncated,
+    /// The payload isn't Octo's JSON
+    Payload(String),
+    /// Its program didn't compile
+    Compile(CompileError),
+}
+
+impl fmt::Display for CartridgeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CartridgeError::Image(e) => write!(f, "Couldn't read the cartridge image: {}", e),
+            CartridgeError::Truncated => write!(f, "The cartridge image is truncated"),
+            CartridgeError::Payload(e) => write!(f, "Couldn't read the cartridge's program: {}", e),
+            CartridgeError::Compile(e) => {
+                write!(f, "Couldn't compile the cartridge's program, {}", e)
+            }
+        }
+    }
+}
+
+impl std::error::Error for CartridgeError {}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct OctoOptions {
+    tickrate: Option<u32>,
+    background_color: Option<String>,
+    fill_color: Option<String>,
+    fill_color2: Option<String>,
+    blend_color: Option<String>,
+    shift_quirks: Option<bool>,
+    load_store_quirks: Option<bool>,
+    clip_quirks: Option<bool>,
+    jump_quirks: Option<bool>,
+    logic_quirks: Option<bool>,
+    v_blank_quirks: Option<bool>,
+}
+
+impl OctoOptions {
+    /// Octo's quirks are all named after the behaviour that differs from the COSMAC VIP,
+    /// anything a cartridge leaves out keeps Octo's default, which is XO-CHIP's
+    fn to_quirks(&self) -> Quirks {
+        let defaults = Quirks::xo_chip();
+        Quirks {
+            display_wait: self.v_blank_quirks.unwrap_or(defaults.display_wait),
+            wrap_sprites: self.clip_quirks.map_or(defaults.wrap_sprites, |clip| !clip),
+            shift_vy: self.shift_quirks.map_or(defaults.shift_vy, |shift| !shift),
+            vf_reset: self.logic_quirks.unwrap_or(defaults.vf_reset),
+            memory_increment: match self.load_store_quirks {
+                Some(true) => MemoryIncrement::Unchanged,
+                Some(false) => MemoryIncrement::ByXPlusOne,
+                None => defaults.memory_increment,
+            },
+            jump_vx: self.jump_quirks.unwrap_or(defaults.jump_vx),
+        }
+    }
+
+    /// Octo's colours in the order of our palette's indexes
+    fn to_palette(&self) -> Option<Palette> {
+        let colors = [
+            &self.background_color,
+            &self.fill_color,
+            &self.fill_color2,
+            &self.blend_color,
+        ];
+        if colors.iter().all(|c| c.is_none()) {
+            return None;
+        }
+        // a colour left out falls back on the default palette's
+        let mut palette = Palette::default();
+        for (index, color) in colors.iter().enumerate() {
+            if let Some(hex) = color {
+                palette.colors[index] = Color::from_hex(hex)?;
+            }
+        }
+        Some(palette)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct OctoPayload {
+    program: String,
+    #[serde(default)]
+    options: OctoOptions,
+}
+
+/// What's stored in a cartridge
+#[derive(Clone, Debug, PartialEq)]
+pub struct Cartridge {
+    /// The program in Octo's assembly language
+    pub source: String,
+    pub quirks: Quirks,
+    /// Instructions per 60hz frame
+    pub tickrate: Option<u32>,
+    pub palette: Option<Palette>,
+}
+
+impl Cartridge {
+    pub fn decode(gif_bytes: &[u8]) -> Result<Self, CartridgeError> {
+        let payload = read_payload(gif_bytes)?;
+        let payload: OctoPayload = serde_json::from_str(&String::from_utf8_lossy(&payload))
+            .map_err(|e| CartridgeError::Payload(e.to_string()))?;
+
+        Ok(Self {
+            quirks: payload.options.to_quirks(),
+            tickrate: payload.options.tickrate,
+            palette: payload.options.to_palette(),
+            source: payload.program,
+        })
+    }
+
+    /// The program as a rom to load at 0x200
+    pub fn compile(&self) -> Result<Vec<u8>, CartridgeError> {
+        octo::compile(&self.source).map_err(CartridgeError::Compile)
+    }
+}
+
+/// Whether the bytes are a GIF, and so maybe a cartridge, rather than a rom
+pub fn is_gif(bytes: &[u8]) -> bool {
+    bytes.starts_with(GIF_SIGNATURE)
+}
+
+/// The bytes hidden in the first frame, four pixels to a byte with the most significant
+/// bits first
+fn read_payload(gif_bytes: &[u8]) -> Result<Vec<u8>, CartridgeError> {
+    let mut options = gif::DecodeOptions::new();
+    options.set_color_output(gif::ColorOutput::Indexed);
+    let mut decoder = options
+        .read_info(gif_bytes)
+        .map_err(|e| CartridgeError::Image(e.to_string()))?;
+    let frame = decoder
+        .read_next_frame()
+        .map_err(|e| CartridgeError::Image(e.to_string()))?
+        .ok_or_else(|| CartridgeError::Image("it has no frames".to_string()))?;
+
+    let bytes: Vec<u8> = frame
+        .buffer
+        .chunks_exact(4)
+        .map(|pixels| {
+            pixels
+                .iter()
+                .fold(0, |byte, &pixel| byte << 2 | (pixel & 3))
+        })
+        .collect();
+    if bytes.len() < LENGTH_BYTES {
+        return Err(CartridgeError::Truncated);
+    }
+    let (length, payload) = bytes.split_at(LENGTH_BYTES);
+    let length = u32::from_be_bytes(length.try_into().unwrap()) as usize;
+    if length > payload.len() {
+        return Err(CartridgeError::Truncated);
+    }
+    Ok(payload[..length].to_vec())
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use super::*;
+
+    const WIDTH: u16 = 64;
+
+    /// A cartridge the way Octo makes them, with a stripy label in the pixels' high bits
+    pub(crate) fn encode_cartridge(payload: &str) -> Vec<u8> {
+        encode_gif(payload.len() as u32, payload.as_bytes())
+    }
+
+    fn encode_gif(length: u32, payload: &[u8]) -> Vec<u8> {
+        let mut bytes = length.to_be_bytes().to_vec();
+        bytes.extend_from_slice(payload);
+        let mut pixels: Vec<u8> = bytes
+            .iter()
+            .flat_map(|&byte| [byte >> 6, byte >> 4, byte >> 2, byte].map(|bits| bits & 3))
+            .collect();
+        pixels.resize(pixels.len().next_multiple_of(WIDTH as usize), 0);
+        for (index, pixel) in pixels.iter_mut().enumerate() {
+            *pixel |= (index as u8 % 3) << 2;
+        }
+
+        let height = (pixels.len() / WIDTH as usize) as u16;
+        let mut gif_bytes = Vec::new();
+        {
+            let mut encoder =
+                gif::Encoder::new(&mut gif_bytes, WIDTH, height, &[0; 16 * 3]).unwrap();
+            let frame = gif::Frame::from_indexed_pixels(WIDTH, height, pixels, None);
+            encoder.write_frame(&frame).unwrap();
+        }
+        gif_bytes
+    }
+
+    #[test]
+    fn decode() {
+        let gif_bytes = encode_cartridge(
+            r##"{
+                "program": ": main\n  clear\n  loop again\n",
+                "options": {
+                    "tickrate": 100,
+                    "backgroundColor": "#000000",
+                    "fillColor": "#FF6600",
+                    "shiftQuirks": true,
+                    "loadStoreQuirks": false,
+                    "clipQuirks": true,
+                    "vBlankQuirks": false,
+                    "jumpQuirks": false,
+                    "logicQuirks": true,
+                    "screenRotation": 0
+                }
+            }"##,
+        );
+        assert!(is_gif(&gif_bytes));
+
+        let cartridge = Cartridge::decode(&gif_bytes).unwrap();
+        assert_eq!(cartridge.source, ": main\n  clear\n  loop again\n");
+        assert_eq!(cartridge.compile(), Ok(vec![0x00, 0xe0, 0x12, 0x02]));
+        assert_eq!(cartridge.tickrate, Some(100));
+        assert_eq!(
+            cartridge.quirks,
+            Quirks {
+                display_wait: false,
+                wrap_sprites: false,
+                shift_vy: false,
+                vf_reset: true,
+                memory_increment: MemoryIncrement::ByXPlusOne,
+                jump_vx: false,
+            }
+        );
+        let palette = cartridge.palette.unwrap();
+        assert_eq!(palette.colors[0], Color::rgb(0, 0, 0));
+        assert_eq!(palette.colors[1], Color::rgb(0xff, 0x66, 0));
+        assert_eq!(palette.colors[2], Palette::default().colors[2]);
+    }
+
+    #[test]
+    fn decode_without_options() {
+        let cartridge = Cartridge::decode(&encode_cartridge(r#"{ "program": "" }"#)).unwrap();
+        assert_eq!(cartridge.quirks, Quirks::xo_chip());
+        assert_eq!(cartridge.tickrate, None);
+        assert_eq!(cartridge.palette, None);
+        assert!(matches!(
+            cartridge.compile(),
+            Err(CartridgeError::Compile(CompileError { line: 1, .. }))
+        ));
+    }
+
+    #[test]
+    fn decode_errors() {
+        assert!(!is_gif(&[0x00, 0xe0]));
+        assert!(matches!(
+            Cartridge::decode(b"GIF89a"),
+            Err(CartridgeError::Image(_))
+        ));
+        assert!(matches!(
+            Cartridge::decode(&encode_cartridge("not json")),
+            Err(CartridgeError::Payload(_))
+        ));
+
+        // claims more bytes than the image has
+        let gif_bytes = encode_gif(100, b"{}");
+        assert_eq!(
+            Cartridge::decode(&gif_bytes),
+            Err(CartridgeError::Truncated)
+        );
+    }
+}
@@ -1,16 +1,48 @@
+use crate::archive::{self, ArchiveError, RomArchive, RomPlatform};
 use crate::audio::browser::BrowserAudioSource;
 use crate::audio::mock::MockAudioSource;
+use crate::audio::pcm::PcmAudioSource;
+use crate::audio::synth::{ToneSettings, DEFAULT_PITCH};
 use crate::audio::AudioSource;
+use crate::cartridge::{self, Cartridge, CartridgeError};
+use crate::cpu::InstructionSet;
+use crate::database::{sha1_hex, RomDatabase, RomSettings};
+use crate::display::browser_display;
+use crate::display::framebuffer::Framebuffer;
 use crate::display::mock::MockDisplay;
-use crate::display::webgl::WebGLDisplay;
+use crate::display::palette::Palette;
+use crate::display::recorder::{GifRecorder, RecordingError};
+use crate::display::screenshot::{encode_png, ScreenshotError};
+use crate::flags::browser::BrowserFlagStore;
+use crate::flags::mock::MockFlagStore;
+use crate::flags::RplFlags;
 use crate::instruction::Instruction;
 use crate::keyboard::browser::BrowserKeyboard;
 use crate::keyboard::mock::MockKeyboard;
+use crate::keyboard::Keymap;
+use crate::quirks::Quirks;
+use crate::timing::{vip_cycles, TimingMode, VIP_CYCLES_AVAILABLE};
 use crate::{Display, Keyboard, CPU};
+use std::fmt;
+use wasm_bindgen::prelude::*;
 
-// where in memory roms should start being read from
-const ROM_START_ADDRESS: usize = 0x200;
+/// Where in memory roms are loaded and start running from, unless they were written for a
+/// machine like the ETI-660 that starts at 0x600
+pub const DEFAULT_LOAD_ADDRESS: u16 = 0x200;
 const FONT_START_ADDRESS: usize = 0x0;
+/// Where SUPER-CHIP's 8x10 digits go, straight after the small ones
+pub const BIG_FONT_START_ADDRESS: usize = FONT_START_ADDRESS + FONT_MAP.len();
+// enough for XO-CHIP, the other instruction sets only get to see the first 4K
+const MEMORY_SIZE: usize = 0x10000;
+
+// timers tick at 60hz so everything else is scheduled in frames of the same length
+const FRAME_RATE: f64 = 60.0;
+const FRAME_DURATION_MS: f64 = 1000.0 / FRAME_RATE;
+// about the 400hz the emulator used to run at, rounded to whole instructions per frame
+const DEFAULT_INSTRUCTIONS_PER_FRAME: u32 = 7;
+// requestAnimationFrame stops firing in background tabs, don't try to catch up on
+// all of that time when the tab comes back
+const MAX_FRAMES_PER_UPDATE: u32 = 4;
 
 const FONT_MAP: [u8; 80] = [
     0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
@@ -31,47 +63,162 @@ const FONT_MAP: [u8; 80] = [
     0xF0, 0x80, 0xF0, 0x80, 0x80, // F
 ];
 
+// Octo's, SUPER-CHIP 1.1 only had 0 to 9
+const BIG_FONT_MAP: [u8; 160] = [
+    0xFF, 0xFF, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, // 0
+    0x18, 0x78, 0x78, 0x18, 0x18, 0x18, 0x18, 0x18, 0xFF, 0xFF, // 1
+    0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, // 2
+    0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 3
+    0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0x03, 0x03, // 4
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 5
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, // 6
+    0xFF, 0xFF, 0x03, 0x03, 0x06, 0x0C, 0x18, 0x18, 0x18, 0x18, // 7
+    0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, // 8
+    0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 9
+    0x7E, 0xFF, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xC3, // A
+    0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, // B
+    0x3C, 0xFF, 0xC3, 0xC0, 0xC0, 0xC0, 0xC0, 0xC3, 0xFF, 0x3C, // C
+    0xFC, 0xFE, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFE, 0xFC, // D
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, // E
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xC0, 0xC0, // F
+];
+
 #[allow(dead_code)]
 pub enum Chip8Platform {
     BROWSER,
     //DESKTOP,
+    /// Native runs with nothing to show or play on, uses the mock display and keyboard and
+    /// renders the audio to a buffer
+    HEADLESS,
     MOCK,
 }
 
+/// Why a rom couldn't be loaded
+#[derive(Clone, Debug, PartialEq)]
+pub enum RomError {
+    Empty,
+    /// Bigger than the memory left after the load address
+    TooLarge {
+        size: usize,
+        max_size: usize,
+    },
+    /// Would load over the fonts or leave no room for the rom
+    InvalidLoadAddress(u16),
+    /// An Octo cartridge rather than a rom
+    Cartridge(CartridgeError),
+    Archive(ArchiveError),
+    /// Asked for a rom that isn't in the archive that was loaded
+    NotInArchive(String),
+}
+
+impl fmt::Display for RomError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RomError::Empty => write!(f, "The ROM is empty"),
+            RomError::TooLarge { size, max_size } => write!(
+                f,
+                "The ROM is {} bytes, only {} bytes fit in memory",
+                size, max_size
+            ),
+            RomError::InvalidLoadAddress(address) => {
+                write!(f, "Can't load a ROM at {:#05x}", address)
+            }
+            RomError::Cartridge(e) => e.fmt(f),
+            RomError::Archive(e) => e.fmt(f),
+            RomError::NotInArchive(name) => write!(f, "There's no {} in the archive", name),
+        }
+    }
+}
+
+impl std::error::Error for RomError {}
+
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TickResult {
+    Executed,
+    /// A `DRW` is held back by the display wait quirk until the current frame ends
+    BlockedUntilVblank,
+}
+
 pub struct Chip8Bus {
-    pub memory: [u8; 4096],
+    pub memory: [u8; MEMORY_SIZE],
+    pub framebuffer: Framebuffer,
     pub display: Box<dyn Display>,
     pub keyboard: Box<dyn Keyboard>,
     pub audio: Box<dyn AudioSource>,
+    pub flags: RplFlags,
 }
 
 impl Chip8Bus {
     fn new(platform: Chip8Platform) -> Self {
         match platform {
             Chip8Platform::BROWSER => Self {
-                memory: [0; 4096],
-                display: Box::new(WebGLDisplay::default()),
+                memory: [0; MEMORY_SIZE],
+                framebuffer: Framebuffer::default(),
+                display: browser_display(),
                 keyboard: Box::new(BrowserKeyboard::default()),
                 audio: Box::new(BrowserAudioSource::default()),
+                flags: RplFlags::new(Box::new(BrowserFlagStore::default())),
             },
             /*Chip8Platform::DESKTOP => Self {
-                memory: [0; 4096],
+                memory: [0; MEMORY_SIZE],
+                framebuffer: Framebuffer::default(),
                 display: Box::new(WebGLDisplay::default()),
                 keyboard: Box::new(BrowserKeyboard::default()),
             },*/
+            Chip8Platform::HEADLESS => Self {
+                memory: [0; MEMORY_SIZE],
+                framebuffer: Framebuffer::default(),
+                display: Box::new(MockDisplay::default()),
+                keyboard: Box::new(MockKeyboard::default()),
+                audio: Box::new(PcmAudioSource::default()),
+                // the headless runner swaps in files when it has somewhere to put them
+                flags: RplFlags::new(Box::new(MockFlagStore::default())),
+            },
             Chip8Platform::MOCK => Self {
-                memory: [0; 4096],
+                memory: [0; MEMORY_SIZE],
+                framebuffer: Framebuffer::default(),
                 display: Box::new(MockDisplay::default()),
                 keyboard: Box::new(MockKeyboard::default()),
                 audio: Box::new(MockAudioSource::default()),
+                flags: RplFlags::new(Box::new(MockFlagStore::default())),
             },
         }
     }
 }
 
+/// What the player picked by hand, put aside while a rom runs with its own settings and
+/// put back for the next rom that has none
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct PlayerSettings {
+    quirks: Quirks,
+    instructions_per_frame: u32,
+    palette: Palette,
+}
+
 pub struct Chip8 {
     pub cpu: CPU,
     pub bus: Chip8Bus,
+    pub instructions_per_frame: u32,
+    palette: Palette,
+    // the player's volume and tone, kept across rom loads
+    tone: ToneSettings,
+    load_address: u16,
+    // where the database says the current rom loads, over `load_address`
+    rom_load_address: Option<u16>,
+    pub database: RomDatabase,
+    // the display wait quirk as picked, COSMAC VIP timing turns it on regardless
+    display_wait: bool,
+    // set aside while the current rom uses the database's or its platform's settings
+    player_settings: Option<PlayerSettings>,
+    // the zip the current rom came out of, if it did, and its name in there
+    archive: Option<RomArchive>,
+    archive_rom: Option<String>,
+    recorder: GifRecorder,
+    timing_mode: TimingMode,
+    frame_time_accumulator: f64,
+    // cycles an instruction ran over the end of the previous frame in COSMAC VIP mode
+    vip_cycle_debt: u32,
 }
 
 impl Chip8 {
@@ -79,34 +226,880 @@ impl Chip8 {
         Self {
             cpu: CPU::default(),
             bus: Chip8Bus::new(platform),
+            instructions_per_frame: DEFAULT_INSTRUCTIONS_PER_FRAME,
+            palette: Palette::default(),
+            tone: ToneSettings::default(),
+            load_address: DEFAULT_LOAD_ADDRESS,
+            rom_load_address: None,
+            database: RomDatabase::default(),
+            display_wait: Quirks::default().display_wait,
+            player_settings: None,
+            archive: None,
+            archive_rom: None,
+            recorder: GifRecorder::default(),
+            timing_mode: TimingMode::Fixed,
+            frame_time_accumulator: 0.0,
+            vip_cycle_debt: 0,
         }
     }
 }
 
 impl Chip8 {
+    /// Clears everything a previous rom left behind, memory included, ready to load the
+    /// next one
     pub fn reset(&mut self) {
-        self.cpu.reset(&mut self.bus.display);
+        let start_address = self.start_address();
+        self.cpu.reset(&mut self.bus.framebuffer, start_address);
+        self.bus.memory = [0; MEMORY_SIZE];
+        self.bus.audio.set_pattern(None);
+        self.bus.audio.set_pitch(DEFAULT_PITCH);
+        self.bus.audio.set_tone(&self.tone);
         self.load_font_into_memory();
+        self.frame_time_accumulator = 0.0;
+        self.vip_cycle_debt = 0;
+    }
+
+    /// The COSMAC VIP always waited for the vertical blank to draw, so its timing turns on
+    /// the display wait quirk for as long as it's used.
+    pub fn set_timing_mode(&mut self, timing_mode: TimingMode) {
+        self.timing_mode = timing_mode;
+        self.update_display_wait();
+    }
+
+    /// Only takes effect outside COSMAC VIP timing, which always waits
+    pub fn set_display_wait(&mut self, enabled: bool) {
+        self.display_wait = enabled;
+        self.update_display_wait();
+    }
+
+    fn update_display_wait(&mut self) {
+        self.cpu.quirks.display_wait =
+            self.display_wait || self.timing_mode == TimingMode::CosmacVip;
+    }
+
+    pub fn set_clock_speed(&mut self, hz: u32) {
+        self.instructions_per_frame = ((hz as f64 / FRAME_RATE).round() as u32).max(1);
+    }
+
+    /// Advances the emulator by `elapsed_ms` of wall clock time, running however many
+    /// whole 60hz frames fit into it. Leftover time is carried over to the next call.
+    /// Returns the number of frames that were run.
+    pub fn run_frame(&mut self, elapsed_ms: f64) -> u32 {
+        self.frame_time_accumulator += elapsed_ms;
+
+        let mut frames_run = 0;
+        while self.frame_time_accumulator >= FRAME_DURATION_MS {
+            if frames_run == MAX_FRAMES_PER_UPDATE {
+                self.frame_time_accumulator = 0.0;
+                break;
+            }
+            self.frame_time_accumulator -= FRAME_DURATION_MS;
+            self.step_frame();
+            frames_run += 1;
+        }
+
+        frames_run
+    }
+
+    /// Runs a single frame worth of instructions for the current `timing_mode` followed
+    /// by exactly one timer decrement. The frame ends early if a `DRW` has to wait for
+    /// the vertical blank.
+    pub fn step_frame(&mut self) {
+        match self.timing_mode {
+            TimingMode::Fixed => {
+                for _ in 0..self.instructions_per_frame {
+                    if self.fetch_and_execute_instruction() == TickResult::BlockedUntilVblank {
+                        break;
+                    }
+                }
+            }
+            TimingMode::CosmacVip => self.run_vip_frame(),
+        }
+        self.end_frame();
+    }
+
+    /// The vertical blank at the end of every frame
+    pub fn end_frame(&mut self) {
+        self.cpu.handler_timers(&mut self.bus.audio);
+        self.bus.audio.end_frame();
+        self.cpu.vblank();
+        self.present();
+    }
+
+    pub fn set_palette(&mut self, palette: Palette) {
+        self.palette = palette;
+        self.bus.display.set_palette(&palette);
+    }
+
+    pub fn palette(&self) -> Palette {
+        self.palette
+    }
+
+    pub fn tone(&self) -> ToneSettings {
+        self.tone
+    }
+
+    pub fn set_tone(&mut self, tone: ToneSettings) {
+        self.tone = tone.clamped();
+        self.bus.audio.set_tone(&self.tone);
+    }
+
+    pub fn present(&mut self) {
+        self.bus.display.present(&self.bus.framebuffer);
+        self.recorder.record(&self.bus.framebuffer);
+    }
+
+    /// The framebuffer as a PNG in the current palette, see `encode_png`
+    pub fn screenshot(&self, scale: usize) -> Result<Vec<u8>, ScreenshotError> {
+        encode_png(&self.bus.framebuffer, &self.palette, scale)
+    }
+
+    /// Starts keeping every presented frame for `stop_recording`
+    pub fn start_recording(&mut self) {
+        self.recorder.start();
+    }
+
+    /// Everything presented since `start_recording` as an animated GIF in the current
+    /// palette, see `GifRecorder::stop`
+    pub fn stop_recording(&mut self, scale: usize) -> Result<Vec<u8>, RecordingError> {
+        self.recorder.stop(&self.palette, scale)
+    }
+
+    pub fn start_audio_recording(&mut self) {
+        self.bus.audio.start_recording();
+    }
+
+    /// Everything played since `start_audio_recording` as a WAV file, `None` if the audio
+    /// backend doesn't generate any samples to record
+    pub fn stop_audio_recording(&mut self) -> Option<Vec<u8>> {
+        self.bus.audio.stop_recording()
+    }
+
+    fn run_vip_frame(&mut self) {
+        let mut cycles = self.vip_cycle_debt;
+        while cycles < VIP_CYCLES_AVAILABLE {
+            let v_registers = self.cpu.v_registers;
+            let program_counter = self.cpu.program_counter;
+            let instruction = self.fetch_instruction();
+            if self.execute_instruction(instruction) == TickResult::BlockedUntilVblank {
+                // the rest of the frame is spent waiting for the interrupt
+                self.vip_cycle_debt = 0;
+                return;
+            }
+
+            let skipped = self.cpu.program_counter == program_counter + 4;
+            cycles += vip_cycles(&instruction, &v_registers, skipped);
+        }
+        self.vip_cycle_debt = cycles - VIP_CYCLES_AVAILABLE;
+    }
+
+    /// Resets and loads a rom, or the first rom in a zip of them that loads, with whatever
+    /// settings the database has for it: quirks, speed, key bindings, colours and load
+    /// address. Returns those settings if it was in there. A rom that can't be loaded
+    /// leaves the running one alone.
+    pub fn load_rom(&mut self, rom_bytes: &[u8]) -> Result<Option<RomSettings>, RomError> {
+        self.load(rom_bytes, None)
+    }
+
+    /// Like `load_rom`, with the file's extension saying which platform a rom that isn't
+    /// in the database was written for
+    pub fn load_rom_file(
+        &mut self,
+        file_name: &str,
+        rom_bytes: &[u8],
+    ) -> Result<Option<RomSettings>, RomError> {
+        self.load(rom_bytes, RomPlatform::from_file_name(file_name))
+    }
+
+    fn load(
+        &mut self,
+        rom_bytes: &[u8],
+        platform: Option<RomPlatform>,
+    ) -> Result<Option<RomSettings>, RomError> {
+        if cartridge::is_gif(rom_bytes) {
+            self.load_cartridge(rom_bytes)?;
+            self.archive = None;
+            self.archive_rom = None;
+            return Ok(None);
+        }
+        if archive::is_zip(rom_bytes) {
+            // listed even if none of its roms load, so another can be picked
+            let archive = RomArchive::read(rom_bytes).map_err(RomError::Archive)?;
+            let names: Vec<String> = archive.names().into_iter().map(String::from).collect();
+            self.archive = Some(archive);
+            self.archive_rom = None;
+            let mut first_error = None;
+            for name in names {
+                match self.load_archive_rom(&name) {
+                    Ok(settings) => return Ok(settings),
+                    Err(e) => {
+                        first_error.get_or_insert(e);
+                    }
+                }
+            }
+            return Err(first_error.unwrap());
+        }
+
+        let settings = self.load_rom_bytes(rom_bytes, platform)?;
+        self.archive = None;
+        self.archive_rom = None;
+        Ok(settings)
+    }
+
+    /// Names of the roms in the zip the current rom came out of, empty if it didn't
+    pub fn archive_rom_names(&self) -> Vec<&str> {
+        self.archive
+            .as_ref()
+            .map(RomArchive::names)
+            .unwrap_or_default()
+    }
+
+    /// Which rom from the zip is running, `None` if none of them would load
+    pub fn archive_rom_name(&self) -> Option<&str> {
+        self.archive_rom.as_deref()
+    }
+
+    /// Switches to another rom from the zip that was loaded
+    pub fn load_archive_rom(&mut self, name: &str) -> Result<Option<RomSettings>, RomError> {
+        let rom = self
+            .archive
+            .as_ref()
+            .and_then(|archive| archive.rom(name))
+            .ok_or_else(|| RomError::NotInArchive(name.to_string()))?;
+        let (bytes, platform) = (rom.bytes.clone(), rom.platform);
+        let settings = self.load_rom_bytes(&bytes, Some(platform))?;
+        self.archive_rom = Some(name.to_string());
+        Ok(settings)
+    }
+
+    /// The database's platform and settings win over the file's, a rom that has neither
+    /// runs as plain CHIP-8 with the settings picked by hand
+    fn load_rom_bytes(
+        &mut self,
+        rom_bytes: &[u8],
+        platform: Option<RomPlatform>,
+    ) -> Result<Option<RomSettings>, RomError> {
+        let settings = self.database.lookup(rom_bytes);
+        let instruction_set = match &settings {
+            Some(settings) => settings.instruction_set(),
+            None => platform.map_or(InstructionSet::Chip8, RomPlatform::instruction_set),
+        };
+        let rom_load_address = settings.as_ref().and_then(|s| s.start_address);
+        if let Some(address) = rom_load_address {
+            check_load_address(address)?;
+        }
+        // a rom that isn't in there loses the previous rom's key bindings
+        let keymap = settings
+            .as_ref()
+            .map(|s| s.keymap.clone())
+            .unwrap_or_default();
+        self.start_rom(rom_bytes, instruction_set, rom_load_address, &keymap)?;
+
+        let rom_quirks = match &settings {
+            Some(settings) => Some(settings.quirks),
+            None => platform.and_then(RomPlatform::quirks),
+        };
+        match rom_quirks {
+            Some(rom_quirks) => self.apply_rom_settings(
+                rom_quirks,
+                settings.as_ref().and_then(|s| s.tickrate),
+                settings.as_ref().and_then(|s| s.palette),
+            ),
+            None => {
+                if let Some(player) = self.player_settings.take() {
+                    self.apply_player_settings(player);
+                }
+            }
+        }
+        Ok(settings)
+    }
+
+    /// Compiles the cartridge's program and runs it the way Octo would: at 0x200, with the
+    /// whole XO-CHIP instruction set and the cartridge's quirks, speed and colours
+    fn load_cartridge(&mut self, gif_bytes: &[u8]) -> Result<(), RomError> {
+        let cartridge = Cartridge::decode(gif_bytes).map_err(RomError::Cartridge)?;
+        let rom_bytes = cartridge.compile().map_err(RomError::Cartridge)?;
+        self.start_rom(
+            &rom_bytes,
+            InstructionSet::XoChip,
+            Some(DEFAULT_LOAD_ADDRESS),
+            &Keymap::default(),
+        )?;
+        self.apply_rom_settings(cartridge.quirks, cartridge.tickrate, cartridge.palette);
+        Ok(())
+    }
+
+    /// Resets for the rom and loads it, a rom that can't load leaves the running one alone
+    fn start_rom(
+        &mut self,
+        rom_bytes: &[u8],
+        instruction_set: InstructionSet,
+        rom_load_address: Option<u16>,
+        keymap: &Keymap,
+    ) -> Result<(), RomError> {
+        check_rom_size(
+            rom_bytes,
+            rom_load_address.unwrap_or(self.load_address),
+            instruction_set.memory_size(),
+        )?;
+        self.rom_load_address = rom_load_address;
+        self.cpu.instruction_set = instruction_set;
+
+        self.reset();
+        self.load_rom_into_memory(rom_bytes)?;
+        self.bus.flags.open(&sha1_hex(rom_bytes));
+        self.bus.keyboard.set_keymap(keymap);
+        Ok(())
+    }
+
+    /// The rom's own settings on top of what the player picked, not the last rom's
+    fn apply_rom_settings(
+        &mut self,
+        quirks: Quirks,
+        tickrate: Option<u32>,
+        palette: Option<Palette>,
+    ) {
+        let player = match self.player_settings {
+            Some(player) => player,
+            None => self.current_player_settings(),
+        };
+        self.player_settings = Some(player);
+        self.apply_player_settings(player);
+        self.apply_quirks(quirks);
+        if let Some(tickrate) = tickrate {
+            self.instructions_per_frame = tickrate.max(1);
+        }
+        if let Some(palette) = palette {
+            self.set_palette(palette);
+        }
+    }
+
+    fn apply_player_settings(&mut self, player: PlayerSettings) {
+        self.apply_quirks(player.quirks);
+        self.instructions_per_frame = player.instructions_per_frame;
+        self.set_palette(player.palette);
+    }
+
+    fn current_player_settings(&self) -> PlayerSettings {
+        PlayerSettings {
+            quirks: Quirks {
+                display_wait: self.display_wait,
+                ..self.cpu.quirks
+            },
+            instructions_per_frame: self.instructions_per_frame,
+            palette: self.palette,
+        }
+    }
+
+    fn apply_quirks(&mut self, quirks: Quirks) {
+        self.cpu.quirks = quirks;
+        self.set_display_wait(quirks.display_wait);
     }
 
-    pub fn load_rom_into_memory(&mut self, rom_bytes: &[u8]) {
-        self.bus.memory[ROM_START_ADDRESS..ROM_START_ADDRESS + rom_bytes.len()]
-            .copy_from_slice(&rom_bytes);
+    pub fn load_rom_into_memory(&mut self, rom_bytes: &[u8]) -> Result<(), RomError> {
+        let start = self.start_address();
+        check_rom_size(rom_bytes, start, self.cpu.instruction_set.memory_size())?;
+        let start = start as usize;
+        self.bus.memory[start..start + rom_bytes.len()].copy_from_slice(rom_bytes);
+        Ok(())
+    }
+
+    pub fn load_address(&self) -> u16 {
+        self.load_address
+    }
+
+    /// Where the next rom loads and starts running from after a `reset`
+    pub fn set_load_address(&mut self, address: u16) -> Result<(), RomError> {
+        check_load_address(address)?;
+        self.load_address = address;
+        Ok(())
+    }
+
+    fn start_address(&self) -> u16 {
+        self.rom_load_address.unwrap_or(self.load_address)
     }
 
     fn load_font_into_memory(&mut self) {
-        self.bus.memory[FONT_START_ADDRESS..FONT_MAP.len()].copy_from_slice(&FONT_MAP);
+        self.bus.memory[FONT_START_ADDRESS..BIG_FONT_START_ADDRESS].copy_from_slice(&FONT_MAP);
+        self.bus.memory[BIG_FONT_START_ADDRESS..BIG_FONT_START_ADDRESS + BIG_FONT_MAP.len()]
+            .copy_from_slice(&BIG_FONT_MAP);
     }
 
     fn fetch_instruction(&self) -> Instruction {
-        let bytes = (self.bus.memory[self.cpu.program_counter as usize] as u16) << 8
-            | (self.bus.memory[self.cpu.program_counter as usize + 1] as u16);
+        let memory_size = self.cpu.instruction_set.memory_size();
+        let address = self.cpu.program_counter as usize % memory_size;
+        let bytes = (self.bus.memory[address] as u16) << 8
+            | (self.bus.memory[(address + 1) % memory_size] as u16);
 
         Instruction::new(bytes)
     }
 
-    pub fn fetch_and_execute_instruction(&mut self) {
+    pub fn fetch_and_execute_instruction(&mut self) -> TickResult {
         let instruction = self.fetch_instruction();
+        self.execute_instruction(instruction)
+    }
+
+    fn execute_instruction(&mut self, instruction: Instruction) -> TickResult {
         self.cpu.execute_instruction(instruction, &mut self.bus);
+        if self.cpu.is_waiting_for_vblank() {
+            TickResult::BlockedUntilVblank
+        } else {
+            TickResult::Executed
+        }
+    }
+}
+
+/// Roms have to load past the fonts, with room for at least one instruction in the 4K
+/// every instruction set has
+fn check_load_address(address: u16) -> Result<(), RomError> {
+    if (address as usize) < BIG_FONT_START_ADDRESS + BIG_FONT_MAP.len()
+        || address as usize > InstructionSet::Chip8.memory_size() - 2
+    {
+        return Err(RomError::InvalidLoadAddress(address));
+    }
+    Ok(())
+}
+
+/// Whether there's a rom at all and it fits between `start` and the end of the
+/// `memory_size` its instruction set has
+fn check_rom_size(rom_bytes: &[u8], start: u16, memory_size: usize) -> Result<(), RomError> {
+    let max_size = memory_size - start as usize;
+    if rom_bytes.is_empty() {
+        return Err(RomError::Empty);
+    }
+    if rom_bytes.len() > max_size {
+        return Err(RomError::TooLarge {
+            size: rom_bytes.len(),
+            max_size,
+        });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::archive::test::zip_files;
+    use crate::audio::synth::Waveform;
+    use crate::cartridge::test::encode_cartridge;
+    use crate::database::sha1_hex;
+    use crate::display::palette::Color;
+    use crate::quirks::Quirks;
+    use crate::Chip8Platform::MOCK;
+
+    // LD V0, 0xa; LD DT, V0; loop: LD V1, DT; JP loop
+    const DELAY_TIMER_ROM: [u8; 8] = [0x60, 0x0a, 0xf0, 0x15, 0xf1, 0x07, 0x12, 0x04];
+
+    fn boot(rom: &[u8]) -> Chip8 {
+        let mut chip8 = Chip8::new(MOCK);
+        chip8.reset();
+        chip8.load_rom_into_memory(rom).unwrap();
+        chip8
+    }
+
+    #[test]
+    fn run_frame() {
+        let mut chip8 = boot(&DELAY_TIMER_ROM);
+
+        assert_eq!(chip8.run_frame(16.0), 0);
+        assert_eq!(chip8.run_frame(1.0), 1);
+        assert_eq!(chip8.run_frame(FRAME_DURATION_MS * 2.0), 2);
+        assert_eq!(chip8.cpu.v_registers[0x1], 0x8);
+    }
+
+    #[test]
+    fn run_frame_caps_catch_up() {
+        let mut chip8 = boot(&DELAY_TIMER_ROM);
+
+        assert_eq!(chip8.run_frame(10_000.0), MAX_FRAMES_PER_UPDATE);
+        assert_eq!(chip8.run_frame(0.0), 0);
+    }
+
+    #[test]
+    fn step_frame() {
+        let mut chip8 = boot(&DELAY_TIMER_ROM);
+
+        chip8.step_frame();
+        assert_eq!(chip8.cpu.program_counter, 0x206);
+        assert_eq!(chip8.cpu.v_registers[0x1], 0xa);
+
+        chip8.step_frame();
+        assert_eq!(chip8.cpu.v_registers[0x1], 0x9);
+    }
+
+    #[test]
+    fn step_frame_cosmac_vip() {
+        // loop: ADD V0, 0x1; JP loop
+        let mut chip8 = boot(&[0x70, 0x01, 0x12, 0x00]);
+        chip8.set_timing_mode(TimingMode::CosmacVip);
+
+        chip8.step_frame();
+        let add_cycles = vip_cycles(&Instruction::new(0x7001), &[0; 16], false);
+        let jp_cycles = vip_cycles(&Instruction::new(0x1200), &[0; 16], false);
+        let loops = chip8.cpu.v_registers[0x0] as u32;
+        assert!(loops * (add_cycles + jp_cycles) >= VIP_CYCLES_AVAILABLE - jp_cycles);
+        assert!((loops - 1) * (add_cycles + jp_cycles) < VIP_CYCLES_AVAILABLE);
+    }
+
+    #[test]
+    fn step_frame_cosmac_vip_drw_waits_for_vblank() {
+        // loop: DRW V0, V0, 0x1; JP loop
+        let mut chip8 = boot(&[0xd0, 0x01, 0x12, 0x00]);
+        chip8.set_timing_mode(TimingMode::CosmacVip);
+        assert!(chip8.cpu.quirks.display_wait);
+
+        chip8.step_frame();
+        assert_eq!(chip8.cpu.program_counter, 0x200);
+        assert!(!chip8.bus.framebuffer.get_pixel(0, 0));
+
+        chip8.step_frame();
+        assert_eq!(chip8.cpu.program_counter, 0x200);
+        assert!(chip8.bus.framebuffer.get_pixel(0, 0));
+
+        chip8.step_frame();
+        assert!(!chip8.bus.framebuffer.get_pixel(0, 0));
+    }
+
+    #[test]
+    fn cosmac_vip_timing_restores_the_display_wait_quirk() {
+        let mut chip8 = boot(&[0x12, 0x00]);
+        chip8.set_display_wait(false);
+        chip8.set_timing_mode(TimingMode::CosmacVip);
+        assert!(chip8.cpu.quirks.display_wait);
+        // can't be turned off while the COSMAC VIP is waiting anyway
+        chip8.set_display_wait(false);
+        assert!(chip8.cpu.quirks.display_wait);
+        chip8.set_timing_mode(TimingMode::Fixed);
+        assert!(!chip8.cpu.quirks.display_wait);
+
+        chip8.set_display_wait(true);
+        chip8.set_timing_mode(TimingMode::CosmacVip);
+        chip8.set_timing_mode(TimingMode::Fixed);
+        assert!(chip8.cpu.quirks.display_wait);
+    }
+
+    #[test]
+    fn step_frame_display_wait() {
+        // loop: DRW V0, V0, 0x1; ADD V1, 0x1; JP loop
+        let mut chip8 = boot(&[0xd0, 0x01, 0x71, 0x01, 0x12, 0x00]);
+        chip8.cpu.quirks.display_wait = true;
+
+        assert_eq!(
+            chip8.fetch_and_execute_instruction(),
+            TickResult::BlockedUntilVblank
+        );
+        chip8.end_frame();
+
+        // one sprite per frame however many instructions the frame would fit
+        chip8.instructions_per_frame = 20;
+        chip8.step_frame();
+        chip8.step_frame();
+        chip8.step_frame();
+        assert_eq!(chip8.cpu.v_registers[0x1], 3);
+        assert!(chip8.bus.framebuffer.get_pixel(0, 0));
+    }
+
+    #[test]
+    fn set_clock_speed() {
+        let mut chip8 = Chip8::new(MOCK);
+
+        chip8.set_clock_speed(600);
+        assert_eq!(chip8.instructions_per_frame, 10);
+        chip8.set_clock_speed(400);
+        assert_eq!(chip8.instructions_per_frame, 7);
+        chip8.set_clock_speed(0);
+        assert_eq!(chip8.instructions_per_frame, 1);
+    }
+
+    #[test]
+    fn load_rom_into_memory_checks_the_size() {
+        let mut chip8 = Chip8::new(MOCK);
+        chip8.reset();
+
+        assert_eq!(chip8.load_rom_into_memory(&[]), Err(RomError::Empty));
+        assert_eq!(
+            chip8.load_rom_into_memory(&[0xaa; 3585]),
+            Err(RomError::TooLarge {
+                size: 3585,
+                max_size: 3584
+            })
+        );
+        assert_eq!(chip8.load_rom_into_memory(&[0xaa; 3584]), Ok(()));
+        assert_eq!(chip8.bus.memory[0xfff], 0xaa);
+    }
+
+    #[test]
+    fn load_rom_rejected_keeps_the_running_rom() {
+        let mut chip8 = boot(&DELAY_TIMER_ROM);
+        chip8.step_frame();
+        let memory = chip8.bus.memory;
+        let program_counter = chip8.cpu.program_counter;
+
+        assert_eq!(chip8.load_rom(&[]), Err(RomError::Empty));
+        assert!(matches!(
+            chip8.load_rom(&[0xaa; 3585]),
+            Err(RomError::TooLarge { .. })
+        ));
+        assert_eq!(chip8.bus.memory, memory);
+        assert_eq!(chip8.cpu.program_counter, program_counter);
+    }
+
+    #[test]
+    fn reset_clears_the_previous_rom() {
+        let mut chip8 = boot(&[0xbb; 0x100]);
+        chip8.bus.memory[0xf00] = 0xcc;
+
+        chip8.reset();
+        chip8.load_rom_into_memory(&DELAY_TIMER_ROM).unwrap();
+        assert_eq!(&chip8.bus.memory[0x200..0x208], &DELAY_TIMER_ROM);
+        assert!(chip8.bus.memory[0x208..].iter().all(|&byte| byte == 0));
+        // the font goes back in
+        assert_eq!(&chip8.bus.memory[0..5], &[0xf0, 0x90, 0x90, 0x90, 0xf0]);
+    }
+
+    #[test]
+    fn set_load_address() {
+        let mut chip8 = Chip8::new(MOCK);
+        assert_eq!(
+            chip8.set_load_address(0x40),
+            Err(RomError::InvalidLoadAddress(0x40))
+        );
+        assert_eq!(
+            chip8.set_load_address(0xfff),
+            Err(RomError::InvalidLoadAddress(0xfff))
+        );
+        assert_eq!(chip8.load_address(), DEFAULT_LOAD_ADDRESS);
+
+        chip8.set_load_address(0x600).unwrap();
+        chip8.reset();
+        chip8.load_rom_into_memory(&DELAY_TIMER_ROM).unwrap();
+        assert_eq!(chip8.cpu.program_counter, 0x600);
+        assert_eq!(&chip8.bus.memory[0x600..0x608], &DELAY_TIMER_ROM);
+        assert!(chip8.load_rom_into_memory(&[0; 2561]).is_err());
+    }
+
+    #[test]
+    fn load_rom_applies_the_database_settings() {
+        let hash = sha1_hex(&DELAY_TIMER_ROM);
+        let hashes = format!(r#"{{ "{}": 0 }}"#, hash);
+        let programs = format!(
+            r##"[{{ "title": "Delay", "roms": {{ "{}": {{
+                "platforms": ["superchip"], "startAddress": 768, "keys": {{ "up": 2 }},
+                "colors": {{ "pixels": ["#000000", "#ff0000"] }}
+            }} }} }}]"##,
+            hash
+        );
+        let platforms = r#"[{ "id": "superchip", "defaultTickrate": 30,
+            "quirks": { "shift": true, "jump": true, "vblank": false } }]"#;
+
+        let mut chip8 = Chip8::new(MOCK);
+        chip8.database = RomDatabase::from_json(&hashes, &programs, platforms).unwrap();
+        chip8.set_display_wait(true);
+        let amber = Palette::preset("amber").unwrap();
+        chip8.set_palette(amber);
+
+        let settings = chip8.load_rom(&DELAY_TIMER_ROM).unwrap().unwrap();
+        assert_eq!(settings.title, "Delay");
+        assert_eq!(chip8.instructions_per_frame, 30);
+        assert!(chip8.cpu.quirks.jump_vx);
+        assert!(!chip8.cpu.quirks.display_wait);
+        assert_eq!(chip8.palette().color(1), Color::rgb(0xff, 0, 0));
+        assert_eq!(chip8.cpu.program_counter, 0x300);
+        assert_eq!(&chip8.bus.memory[0x300..0x308], &DELAY_TIMER_ROM);
+        let keyboard = chip8.bus.keyboard.as_ref().as_any();
+        let keyboard = keyboard.downcast_ref::<MockKeyboard>().unwrap();
+        assert_eq!(keyboard.keymap.bindings, vec![(38, 2)]);
+
+        // another rom from the database doesn't put its settings aside as the player's
+        chip8.load_rom(&DELAY_TIMER_ROM).unwrap();
+
+        // anything else goes back to the chosen load address and loses the key bindings,
+        // and gets the quirks and speed picked before the database's
+        assert_eq!(chip8.load_rom(&[0x12, 0x00]), Ok(None));
+        assert_eq!(chip8.cpu.program_counter, 0x200);
+        assert_eq!(chip8.instructions_per_frame, DEFAULT_INSTRUCTIONS_PER_FRAME);
+        assert!(!chip8.cpu.quirks.jump_vx);
+        assert!(chip8.cpu.quirks.display_wait);
+        assert_eq!(chip8.palette(), amber);
+        let keyboard = chip8.bus.keyboard.as_ref().as_any();
+        let keyboard = keyboard.downcast_ref::<MockKeyboard>().unwrap();
+        assert!(keyboard.keymap.bindings.is_empty());
+    }
+
+    #[test]
+    fn load_rom_compiles_cartridges() {
+        let gif_bytes = encode_cartridge(
+            r##"{
+                "program": ": main\n  i := long data\n  loop again\n: data 0xAB",
+                "options": {
+                    "tickrate": 500,
+                    "fillColor": "#FF6600",
+                    "shiftQuirks": true,
+                    "vBlankQuirks": false
+                }
+            }"##,
+        );
+        let mut chip8 = boot(&DELAY_TIMER_ROM);
+        chip8.set_load_address(0x600).unwrap();
+
+        assert_eq!(chip8.load_rom(&gif_bytes), Ok(None));
+        // Octo compiles for 0x200 whatever the load address
+        assert_eq!(chip8.cpu.program_counter, 0x200);
+        assert_eq!(
+            &chip8.bus.memory[0x200..0x207],
+            &[0xf0, 0x00, 0x02, 0x06, 0x12, 0x04, 0xab]
+        );
+        assert_eq!(chip8.cpu.instruction_set, InstructionSet::XoChip);
+        assert!(!chip8.cpu.quirks.shift_vy);
+        assert!(!chip8.cpu.quirks.display_wait);
+        assert_eq!(chip8.instructions_per_frame, 500);
+        assert_eq!(chip8.palette().colors[1], Color::rgb(0xff, 0x66, 0));
+
+        // and none of it sticks to the next rom
+        chip8.load_rom(&DELAY_TIMER_ROM).unwrap();
+        assert_eq!(chip8.cpu.program_counter, 0x600);
+        assert_eq!(chip8.cpu.instruction_set, InstructionSet::Chip8);
+        assert_eq!(chip8.instructions_per_frame, DEFAULT_INSTRUCTIONS_PER_FRAME);
+        assert_eq!(chip8.cpu.quirks, Quirks::default());
+    }
+
+    #[test]
+    fn load_rom_refuses_broken_cartridges() {
+        let mut chip8 = boot(&DELAY_TIMER_ROM);
+        assert!(matches!(
+            chip8.load_rom(b"GIF89a"),
+            Err(RomError::Cartridge(CartridgeError::Image(_)))
+        ));
+        assert!(matches!(
+            chip8.load_rom(&encode_cartridge(r#"{ "program": ": main v0 := 300" }"#)),
+            Err(RomError::Cartridge(CartridgeError::Compile(_)))
+        ));
+        // the rom that was running is left alone
+        assert_eq!(&chip8.bus.memory[0x200..0x208], &DELAY_TIMER_ROM);
+    }
+
+    #[test]
+    fn load_rom_from_an_archive() {
+        let zip_bytes = zip_files(&[
+            ("delay.sc8", &DELAY_TIMER_ROM),
+            ("clear.ch8", &[0x00, 0xe0, 0x12, 0x02]),
+        ]);
+        let mut chip8 = Chip8::new(MOCK);
+
+        // the first by name loads, a plain chip8 rom keeps the quirks it had
+        assert_eq!(chip8.load_rom(&zip_bytes), Ok(None));
+        assert_eq!(chip8.archive_rom_names(), vec!["clear.ch8", "delay.sc8"]);
+        assert_eq!(&chip8.bus.memory[0x200..0x204], &[0x00, 0xe0, 0x12, 0x02]);
+        assert_eq!(chip8.cpu.quirks, Quirks::default());
+
+        assert_eq!(chip8.load_archive_rom("delay.sc8"), Ok(None));
+        assert_eq!(&chip8.bus.memory[0x200..0x208], &DELAY_TIMER_ROM);
+        assert_eq!(chip8.cpu.quirks, Quirks::superchip());
+        assert_eq!(
+            chip8.load_archive_rom("missing.ch8"),
+            Err(RomError::NotInArchive(String::from("missing.ch8")))
+        );
+
+        // loading anything else forgets the archive
+        chip8.load_rom(&DELAY_TIMER_ROM).unwrap();
+        assert!(chip8.archive_rom_names().is_empty());
+        assert_eq!(chip8.archive_rom_name(), None);
+        assert_eq!(
+            chip8.load_archive_rom("clear.ch8"),
+            Err(RomError::NotInArchive(String::from("clear.ch8")))
+        );
+    }
+
+    #[test]
+    fn load_rom_from_an_archive_skips_roms_that_dont_load() {
+        let zip_bytes = zip_files(&[("a.ch8", &[]), ("b.ch8", &DELAY_TIMER_ROM)]);
+        let mut chip8 = boot(&[0x12, 0x00]);
+
+        assert_eq!(chip8.load_rom(&zip_bytes), Ok(None));
+        assert_eq!(chip8.archive_rom_name(), Some("b.ch8"));
+        assert_eq!(&chip8.bus.memory[0x200..0x208], &DELAY_TIMER_ROM);
+
+        // with nothing that loads the listing is still there to pick from
+        let zip_bytes = zip_files(&[("a.ch8", &[]), ("b.xo8", &[])]);
+        assert_eq!(chip8.load_rom(&zip_bytes), Err(RomError::Empty));
+        assert_eq!(chip8.archive_rom_names(), vec!["a.ch8", "b.xo8"]);
+        assert_eq!(chip8.archive_rom_name(), None);
+        assert_eq!(&chip8.bus.memory[0x200..0x208], &DELAY_TIMER_ROM);
+    }
+
+    #[test]
+    fn load_rom_file_picks_the_platform_from_the_extension() {
+        let mut chip8 = Chip8::new(MOCK);
+        chip8.apply_quirks(Quirks {
+            jump_vx: false,
+            ..Quirks::superchip()
+        });
+        let picked = chip8.cpu.quirks;
+
+        assert_eq!(chip8.load_rom_file("delay.sc8", &DELAY_TIMER_ROM), Ok(None));
+        assert_eq!(chip8.cpu.instruction_set, InstructionSet::Superchip);
+        assert_eq!(chip8.cpu.quirks, Quirks::superchip());
+
+        // none of it sticks to a plain CHIP-8 rom
+        assert_eq!(chip8.load_rom_file("clear.ch8", &[0x00, 0xe0]), Ok(None));
+        assert_eq!(chip8.cpu.instruction_set, InstructionSet::Chip8);
+        assert_eq!(chip8.cpu.quirks, picked);
+
+        assert_eq!(chip8.load_rom_file("big.xo8", &[0x12, 0x00]), Ok(None));
+        assert_eq!(chip8.cpu.instruction_set, InstructionSet::XoChip);
+        assert_eq!(chip8.cpu.quirks, Quirks::xo_chip());
+        assert_eq!(chip8.load_rom_file("readme.txt", &[0x12, 0x00]), Ok(None));
+        assert_eq!(chip8.cpu.instruction_set, InstructionSet::Chip8);
+        assert_eq!(chip8.cpu.quirks, picked);
+    }
+
+    #[test]
+    fn xo_chip_roms_can_fill_64k() {
+        let rom = vec![0x12; 0x10000 - 0x200];
+        let mut chip8 = Chip8::new(MOCK);
+
+        assert!(matches!(
+            chip8.load_rom_file("big.ch8", &rom),
+            Err(RomError::TooLarge { .. })
+        ));
+        assert_eq!(chip8.load_rom_file("big.xo8", &rom), Ok(None));
+        assert_eq!(chip8.bus.memory[0xffff], 0x12);
+    }
+
+    #[test]
+    fn rpl_flags_are_kept_per_rom() {
+        let mut chip8 = Chip8::new(MOCK);
+        chip8.load_rom_file("delay.sc8", &DELAY_TIMER_ROM).unwrap();
+        chip8.cpu.v_registers[0] = 0x42;
+        chip8
+            .cpu
+            .execute_instruction(Instruction::new(0xf075), &mut chip8.bus);
+
+        chip8.load_rom(&[0x12, 0x00]).unwrap();
+        assert_eq!(chip8.bus.flags.get()[0], 0);
+        chip8.load_rom(&DELAY_TIMER_ROM).unwrap();
+        assert_eq!(chip8.bus.flags.get()[0], 0x42);
+    }
+
+    #[test]
+    fn set_tone_survives_loading_a_rom() {
+        let mut chip8 = boot(&DELAY_TIMER_ROM);
+        let tone = ToneSettings {
+            volume: 0.5,
+            muted: true,
+            waveform: Waveform::Sine,
+            frequency: 880.0,
+        };
+        chip8.set_tone(tone);
+
+        // the mock stands in for a fresh audio backend that lost the settings
+        chip8.bus.audio = Box::new(MockAudioSource::default());
+        chip8.reset();
+        chip8.load_rom_into_memory(&DELAY_TIMER_ROM).unwrap();
+
+        let audio = chip8.bus.audio.as_ref().as_any();
+        let audio = audio.downcast_ref::<MockAudioSource>().unwrap();
+        assert_eq!(audio.tone, tone);
+        assert_eq!(chip8.tone(), tone);
     }
 }
@@ -1,12 +1,57 @@
 use crate::audio::AudioSource;
-use crate::chip8::Chip8Bus;
-use crate::display::Display;
+use crate::chip8::{Chip8Bus, BIG_FONT_START_ADDRESS};
+use crate::display::framebuffer::{Framebuffer, HIRES_HEIGHT, HIRES_WIDTH};
+use crate::flags::RplFlags;
 use crate::instruction::Instruction;
 use crate::keyboard::Keyboard;
+use crate::quirks::{MemoryIncrement, Quirks};
 use log::debug;
 use rand::{thread_rng, Rng};
 
+// SUPER-CHIP's big digits are 8x10
+const BIG_FONT_BYTES_PER_DIGIT: u16 = 10;
+// XO-CHIP's `i := long nnnn`, the address is in the two bytes after it
+const LONG_I: u16 = 0xf000;
+
+/// Which machine's instructions the CPU runs. Each one adds to the one before, and an
+/// instruction from a later one is as unknown as any other.
+#[derive(Clone, Copy, Debug, Default, PartialEq, PartialOrd)]
+pub enum InstructionSet {
+    #[default]
+    Chip8,
+    /// SUPER-CHIP 1.1: the 128x64 hires mode, scrolling, 16x16 sprites, the big font and
+    /// the RPL user flags
+    Superchip,
+    /// Octo's XO-CHIP: 64K of memory, a second bitplane, more scrolling, saving and loading
+    /// register ranges and the audio pattern
+    XoChip,
+}
+
+impl InstructionSet {
+    /// Addresses wrap round to the start past this
+    pub fn memory_size(self) -> usize {
+        match self {
+            InstructionSet::Chip8 | InstructionSet::Superchip => 0x1000,
+            InstructionSet::XoChip => 0x10000,
+        }
+    }
+
+    fn address_mask(self) -> u16 {
+        (self.memory_size() - 1) as u16
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum VblankWait {
+    Idle,
+    Waiting,
+    // the vblank a DRW was waiting on has happened, it can draw now
+    Released,
+}
+
 pub struct CPU {
+    pub quirks: Quirks,
+    pub instruction_set: InstructionSet,
     pub address_i: u16,
     pub program_counter: u16,
     pub stack_pointer: u8,
@@ -17,11 +62,16 @@ pub struct CPU {
     sound_timer: u8,
 
     key_pressed: u8,
+    vblank_wait: VblankWait,
+    // XO-CHIP's bitplanes that drawing, clearing and scrolling work on, plane 1 otherwise
+    planes: u8,
 }
 
 impl Default for CPU {
     fn default() -> Self {
         Self {
+            quirks: Quirks::default(),
+            instruction_set: InstructionSet::default(),
             address_i: 0,
             program_counter: 0x200,
             stack_pointer: 0,
@@ -30,22 +80,38 @@ impl Default for CPU {
             delay_timer: 0,
             sound_timer: 0,
             key_pressed: 0,
+            vblank_wait: VblankWait::Idle,
+            planes: 1,
         }
     }
 }
 
 impl CPU {
-    pub fn reset(&mut self, display: &mut Box<dyn Display>) {
+    pub fn reset(&mut self, framebuffer: &mut Framebuffer, program_start: u16) {
         self.address_i = 0;
-        self.program_counter = 0x200;
+        self.program_counter = program_start;
         self.stack_pointer = 0;
         self.stack = [0; 16];
         self.v_registers = [0; 16];
         self.delay_timer = 0;
         self.sound_timer = 0;
         self.key_pressed = 0;
+        self.vblank_wait = VblankWait::Idle;
+        self.planes = 1;
 
-        self.cls(display);
+        // back to lores, which clears it as well
+        *framebuffer = Framebuffer::default();
+    }
+
+    /// Lets a `DRW` blocked by the display wait quirk go ahead
+    pub fn vblank(&mut self) {
+        if self.vblank_wait == VblankWait::Waiting {
+            self.vblank_wait = VblankWait::Released;
+        }
+    }
+
+    pub fn is_waiting_for_vblank(&self) -> bool {
+        self.vblank_wait == VblankWait::Waiting
     }
 
     pub fn handler_timers(&mut self, audio: &mut Box<dyn AudioSource>) {
@@ -62,40 +128,62 @@ impl CPU {
 
     pub fn add_vx(&mut self, x: usize, byte: u8) {
         debug!("ADD V{}, {:#01x}", x, byte);
-        self.v_registers[x] += byte;
+        self.v_registers[x] = self.v_registers[x].wrapping_add(byte);
     }
 
     pub fn add_i_vx(&mut self, x: usize) {
         debug!("ADD I, V{}", x);
-        self.address_i += self.v_registers[x] as u16;
+        self.address_i =
+            self.address_i.wrapping_add(self.v_registers[x] as u16) & self.address_mask();
     }
 
     pub fn add_vx_vy(&mut self, x: usize, y: usize) {
         debug!("ADD V{}, V{}", x, y);
-        let buffer = self.v_registers[x] as u16 + self.v_registers[y] as u16;
-        if buffer > 0xff {
-            self.v_registers[0xf] = 0x1;
-        } else {
-            self.v_registers[0xf] = 0x0;
-        }
-        self.v_registers[x] = *buffer.to_be_bytes().last().unwrap();
+        let (result, carry) = self.v_registers[x].overflowing_add(self.v_registers[y]);
+        self.set_vx_and_flag(x, result, carry);
     }
 
     pub fn and_vx_vy(&mut self, x: usize, y: usize) {
         debug!("AND V{}, V{}", x, y);
-        self.v_registers[x] = self.v_registers[x] & self.v_registers[y];
+        self.v_registers[x] &= self.v_registers[y];
+        self.logic_vf_reset();
     }
 
     pub fn call(&mut self, addr: u16) {
         debug!("CALL {:#02x}", addr);
-        self.stack[self.stack_pointer as usize] = self.program_counter + 2;
-        self.stack_pointer += 1;
+        self.stack[self.stack_pointer as usize] =
+            self.program_counter.wrapping_add(2) & self.address_mask();
+        // the stack wraps rather than running off either end
+        self.stack_pointer = (self.stack_pointer + 1) % self.stack.len() as u8;
         self.program_counter = addr;
     }
 
-    pub fn cls(&mut self, display: &mut Box<dyn Display>) {
+    pub fn cls(&mut self, framebuffer: &mut Framebuffer) {
         debug!("CLS");
-        display.clear();
+        framebuffer.clear_planes(self.planes);
+    }
+
+    /// SUPER-CHIP's `exit`, the interpreter stops here for good
+    pub fn exit(&mut self) {
+        debug!("EXIT");
+        self.rewind();
+    }
+
+    /// SUPER-CHIP's `hires` and `lores`, switching clears the screen
+    pub fn high_low(&mut self, hires: bool, framebuffer: &mut Framebuffer) {
+        debug!("{}", if hires { "HIGH" } else { "LOW" });
+        *framebuffer = if hires {
+            Framebuffer::new(HIRES_WIDTH, HIRES_HEIGHT)
+        } else {
+            Framebuffer::default()
+        };
+    }
+
+    /// `00Cn` and XO-CHIP's `00Dn` scroll down and up, `00FB` and `00FC` 4 pixels right
+    /// and left
+    pub fn scroll(&mut self, dx: isize, dy: isize, framebuffer: &mut Framebuffer) {
+        debug!("SCROLL {}, {}", dx, dy);
+        framebuffer.scroll(dx, dy, self.planes);
     }
 
     pub fn jp(&mut self, addr: u16) {
@@ -103,11 +191,43 @@ impl CPU {
         self.program_counter = addr;
     }
 
+    pub fn jp_v0(&mut self, addr: u16) {
+        // with the quirk the top nibble of the address picks the register as well
+        let x = if self.quirks.jump_vx {
+            (addr >> 8) as usize
+        } else {
+            0
+        };
+        debug!("JP V{}, {:#02x}", x, addr);
+        self.program_counter = (addr + self.v_registers[x] as u16) & self.address_mask();
+    }
+
     pub fn ld_bcd_vx(&mut self, x: usize, memory: &mut [u8]) {
         debug!("LD BCD, V{}", x);
-        memory[self.address_i as usize] = self.v_registers[x] / 100;
-        memory[self.address_i as usize + 1] = self.v_registers[x] % 100 / 10;
-        memory[self.address_i as usize + 2] = self.v_registers[x] % 10;
+        let digits = [
+            self.v_registers[x] / 100,
+            self.v_registers[x] % 100 / 10,
+            self.v_registers[x] % 10,
+        ];
+        for (offset, digit) in digits.into_iter().enumerate() {
+            memory[self.memory_address(offset)] = digit;
+        }
+    }
+
+    /// XO-CHIP's `audio`, the 16 bytes at I become the pattern the buzzer plays
+    pub fn ld_pattern_i(&mut self, memory: &[u8], audio: &mut Box<dyn AudioSource>) {
+        debug!("LD PATTERN, [I]");
+        let mut pattern = [0; 16];
+        for (offset, byte) in pattern.iter_mut().enumerate() {
+            *byte = memory[self.memory_address(offset)];
+        }
+        audio.set_pattern(Some(pattern));
+    }
+
+    /// XO-CHIP's `pitch := vx`
+    pub fn ld_pitch_vx(&mut self, x: usize, audio: &mut Box<dyn AudioSource>) {
+        debug!("LD PITCH, V{}", x);
+        audio.set_pitch(self.v_registers[x]);
     }
 
     pub fn ld_dt_vx(&mut self, x: usize) {
@@ -117,7 +237,15 @@ impl CPU {
 
     pub fn ld_f_vx(&mut self, x: usize) {
         debug!("LD F, V{}", x);
-        self.address_i = (self.v_registers[x] * 5) as u16;
+        // there are only sprites for a single hex digit
+        self.address_i = (self.v_registers[x] & 0xf) as u16 * 5;
+    }
+
+    /// SUPER-CHIP's `LD HF, Vx`, the 8x10 digit
+    pub fn ld_hf_vx(&mut self, x: usize) {
+        debug!("LD HF, V{}", x);
+        self.address_i = BIG_FONT_START_ADDRESS as u16
+            + (self.v_registers[x] & 0xf) as u16 * BIG_FONT_BYTES_PER_DIGIT;
     }
 
     pub fn ld_i(&mut self, addr: u16) {
@@ -125,11 +253,49 @@ impl CPU {
         self.address_i = addr;
     }
 
+    /// XO-CHIP's `i := long nnnn`, a four byte instruction
+    pub fn ld_i_long(&mut self, memory: &[u8]) {
+        let mask = self.address_mask() as usize;
+        let address = self.program_counter as usize + 2;
+        self.address_i = u16::from_be_bytes([memory[address & mask], memory[(address + 1) & mask]]);
+        debug!("LD I, LONG {:#04x}", self.address_i);
+        self.advance();
+    }
+
+    /// XO-CHIP's `save vx - vy`, I stays where it is
+    pub fn ld_i_vx_vy(&mut self, x: usize, y: usize, memory: &mut [u8]) {
+        debug!("LD [I], V{} - V{}", x, y);
+        for (offset, register) in register_range(x, y).enumerate() {
+            memory[self.memory_address(offset)] = self.v_registers[register];
+        }
+    }
+
+    /// XO-CHIP's `load vx - vy`, I stays where it is
+    pub fn ld_vx_vy_i(&mut self, x: usize, y: usize, memory: &[u8]) {
+        debug!("LD V{} - V{}, [I]", x, y);
+        for (offset, register) in register_range(x, y).enumerate() {
+            self.v_registers[register] = memory[self.memory_address(offset)];
+        }
+    }
+
+    /// XO-CHIP's `plane n`, which of the two bitplanes the display instructions work on
+    pub fn ld_planes(&mut self, planes: u8) {
+        debug!("PLANE {}", planes);
+        self.planes = planes & 0x3;
+    }
+
     pub fn ld_i_vx(&mut self, x: usize, memory: &mut [u8]) {
         debug!("LD [I], V{}", x);
         for index in 0..=x {
-            memory[self.address_i as usize + index] = self.v_registers[index];
+            memory[self.memory_address(index)] = self.v_registers[index];
         }
+        self.memory_increment(x);
+    }
+
+    /// SUPER-CHIP's `LD R, Vx`, V0 to VX go into the RPL user flags
+    pub fn ld_r_vx(&mut self, x: usize, flags: &mut RplFlags) {
+        debug!("LD R, V{}", x);
+        flags.set(&self.v_registers[..=x]);
     }
 
     pub fn ld_st_vx(&mut self, x: usize) {
@@ -150,8 +316,15 @@ impl CPU {
     pub fn ld_vx_i(&mut self, x: usize, memory: &[u8]) {
         debug!("LD V{}, I", x);
         for n in 0..=x {
-            self.v_registers[n] = memory[self.address_i as usize + n]
+            self.v_registers[n] = memory[self.memory_address(n)]
         }
+        self.memory_increment(x);
+    }
+
+    /// SUPER-CHIP's `LD Vx, R`, V0 to VX come back from the RPL user flags
+    pub fn ld_vx_r(&mut self, x: usize, flags: &RplFlags) {
+        debug!("LD V{}, R", x);
+        self.v_registers[..=x].copy_from_slice(&flags.get()[..=x]);
     }
 
     pub fn ld_vx_k(&mut self, x: usize, keyboard: &mut Box<dyn Keyboard>) {
@@ -159,7 +332,7 @@ impl CPU {
         let key_down = keyboard.get_key();
 
         if key_down == 0 {
-            self.program_counter -= 2;
+            self.rewind();
         } else {
             self.v_registers[x] = key_down;
         }
@@ -173,11 +346,15 @@ impl CPU {
     pub fn or_vx_vy(&mut self, x: usize, y: usize) {
         debug!("OR V{}, V{}", x, y);
         self.v_registers[x] |= self.v_registers[y];
+        self.logic_vf_reset();
     }
 
     pub fn ret(&mut self) {
         debug!("RET");
-        self.stack_pointer -= 1;
+        self.stack_pointer = self
+            .stack_pointer
+            .checked_sub(1)
+            .unwrap_or(self.stack.len() as u8 - 1);
         self.program_counter = self.stack[self.stack_pointer as usize];
     }
 
@@ -190,7 +367,7 @@ impl CPU {
     pub fn se_vx(&mut self, x: usize, byte: u8) {
         debug!("SE V{}, {:#01x}", x, byte);
         if self.v_registers[x] == byte {
-            self.program_counter += 2;
+            self.advance();
         }
     }
 
@@ -198,50 +375,41 @@ impl CPU {
         debug!("SE V{}, V{}", x, y);
 
         if self.v_registers[x] == self.v_registers[y] {
-            self.program_counter += 2;
+            self.advance();
         }
     }
 
     pub fn shl_vx_vy(&mut self, x: usize, y: usize) {
         debug!("SHL V{}, V{}", x, y);
-
-        if (self.v_registers[x] >> 7) & 0xf == 0x1 {
-            // shifting after this would be overflow, dunno if I'm supposed to wrap?
-            self.v_registers[0xf] = 0x1;
-        } else {
-            self.v_registers[0xf] = 0x0;
-        }
-        self.v_registers[x] = self.v_registers[x] << 1;
+        // VF gets the bit shifted out the top
+        let source = self.shift_source(x, y);
+        self.set_vx_and_flag(x, source << 1, source & 0x80 != 0);
     }
 
     pub fn shr_vx_vy(&mut self, x: usize, y: usize) {
         debug!("SHR V{}, V{}", x, y);
-        if self.v_registers[x] & 0x1 == 0x1 {
-            self.v_registers[0xf] = 0x1;
-        } else {
-            self.v_registers[0xf] = 0x0;
-        }
-        self.v_registers[x] = self.v_registers[x] >> 1;
+        let source = self.shift_source(x, y);
+        self.set_vx_and_flag(x, source >> 1, source & 0x1 != 0);
     }
 
     pub fn skp_vx(&mut self, x: usize, keyboard: &mut Box<dyn Keyboard>) {
         debug!("SKP V{}", x);
         if keyboard.get_key() == self.v_registers[x] {
-            self.program_counter += 2;
+            self.advance();
         }
     }
 
     pub fn sknp_vx(&mut self, x: usize, keyboard: &mut Box<dyn Keyboard>) {
         debug!("SKNP V{}", x);
         if keyboard.get_key() != self.v_registers[x] {
-            self.program_counter += 2;
+            self.advance();
         }
     }
 
     pub fn sne_vx(&mut self, vx: usize, byte: u8) {
         debug!("SNE V{}, {:#01x}", vx, byte);
         if self.v_registers[vx] != byte {
-            self.program_counter += 2;
+            self.advance();
         }
     }
 
@@ -249,23 +417,27 @@ impl CPU {
         debug!("SNE V{}, V{}", x, y);
 
         if self.v_registers[x] != self.v_registers[y] {
-            self.program_counter += 2;
+            self.advance();
         }
     }
 
     pub fn sub_vx_vy(&mut self, x: usize, y: usize) {
         debug!("SUB V{}, V{}", x, y);
-        if self.v_registers[x] > self.v_registers[y] {
-            self.v_registers[0xf] = 0x1;
-        } else {
-            self.v_registers[0xf] = 0x0;
-        }
-        self.v_registers[x] = self.v_registers[x].wrapping_sub(self.v_registers[y]);
+        // VF is set when there's no borrow
+        let (vx, vy) = (self.v_registers[x], self.v_registers[y]);
+        self.set_vx_and_flag(x, vx.wrapping_sub(vy), vx >= vy);
+    }
+
+    pub fn subn_vx_vy(&mut self, x: usize, y: usize) {
+        debug!("SUBN V{}, V{}", x, y);
+        let (vx, vy) = (self.v_registers[x], self.v_registers[y]);
+        self.set_vx_and_flag(x, vy.wrapping_sub(vx), vy >= vx);
     }
 
     pub fn xor_vx_vy(&mut self, x: usize, y: usize) {
         debug!("XOR V{}, V{}", x, y);
         self.v_registers[x] ^= self.v_registers[y];
+        self.logic_vf_reset();
     }
 
     pub fn drw(
@@ -274,41 +446,100 @@ impl CPU {
         y: usize,
         n: usize,
         memory: &[u8],
-        display: &mut Box<dyn Display>,
+        framebuffer: &mut Framebuffer,
     ) {
         debug!("DRW V{}, V{}, {:#01x}", x, y, n);
+        if self.quirks.display_wait {
+            if self.vblank_wait != VblankWait::Released {
+                // try again once the frame is over
+                self.vblank_wait = VblankWait::Waiting;
+                self.rewind();
+                return;
+            }
+            self.vblank_wait = VblankWait::Idle;
+        }
         self.v_registers[0xf] = 0x0;
 
-        let pixel_size = 20.0;
-        for row in 0..n {
-            let pixel = memory[self.address_i as usize + row];
-            for col in 0..8 {
-                if (pixel & (0x80 >> col)) != 0 {
-                    let x_coord = (self.v_registers[x] + col) as usize;
-                    let y_coord = (self.v_registers[y] + row as u8) as usize;
+        // SUPER-CHIP's Dxy0 is a 16x16 sprite, two bytes a row
+        let (rows, sprite_width) = if n == 0 && self.instruction_set >= InstructionSet::Superchip {
+            (16, 16)
+        } else {
+            (n, 8)
+        };
+        let bytes_per_row = sprite_width / 8;
+        let width = framebuffer.get_width();
+        let height = framebuffer.get_height();
+        // the starting position always wraps, the rest of the sprite depends on the quirk
+        let start_x = self.v_registers[x] as usize % width;
+        let start_y = self.v_registers[y] as usize % height;
+
+        // with both XO-CHIP planes selected the second plane's sprite follows the first's
+        let mut sprite_start = 0;
+        for plane in [1, 2].into_iter().filter(|plane| self.planes & plane != 0) {
+            for row in 0..rows {
+                let mut y_coord = start_y + row;
+                if y_coord >= height {
+                    if !self.quirks.wrap_sprites {
+                        break;
+                    }
+                    y_coord %= height;
+                }
+
+                let row_start = sprite_start + row * bytes_per_row;
+                let bits = (0..bytes_per_row).fold(0u16, |bits, byte| {
+                    bits << 8 | memory[self.memory_address(row_start + byte)] as u16
+                });
+                for col in 0..sprite_width {
+                    let mut x_coord = start_x + col;
+                    if x_coord >= width {
+                        if !self.quirks.wrap_sprites {
+                            break;
+                        }
+                        x_coord %= width;
+                    }
 
-                    let cur_pixel = display.get_pixel(x_coord, y_coord);
-                    if cur_pixel {
+                    if bits & (1 << (sprite_width - 1 - col)) != 0
+                        && framebuffer.toggle_pixel(x_coord, y_coord, plane)
+                    {
                         self.v_registers[0xf] = 0x1;
                     }
-                    display.draw_pixel(x_coord, y_coord, pixel_size, cur_pixel ^ true);
                 }
             }
+            sprite_start += rows * bytes_per_row;
         }
     }
 
     pub fn execute_instruction(&mut self, instruction: Instruction, bus: &mut Chip8Bus) {
+        let superchip = self.instruction_set >= InstructionSet::Superchip;
+        let xo_chip = self.instruction_set == InstructionSet::XoChip;
+        let program_counter = self.program_counter;
+
         match instruction.first {
             0x0 => match instruction.kk {
-                0xe0 => self.cls(&mut bus.display),
+                0xc0..=0xcf if superchip => {
+                    self.scroll(0, instruction.n as isize, &mut bus.framebuffer)
+                }
+                0xd0..=0xdf if xo_chip => {
+                    self.scroll(0, -(instruction.n as isize), &mut bus.framebuffer)
+                }
+                0xe0 => self.cls(&mut bus.framebuffer),
                 0xee => self.ret(),
+                0xfb if superchip => self.scroll(4, 0, &mut bus.framebuffer),
+                0xfc if superchip => self.scroll(-4, 0, &mut bus.framebuffer),
+                0xfd if superchip => self.exit(),
+                0xfe if superchip => self.high_low(false, &mut bus.framebuffer),
+                0xff if superchip => self.high_low(true, &mut bus.framebuffer),
                 _ => self.unknown_instruction(&instruction),
             },
             0x1 => self.jp(instruction.nnn),
             0x2 => self.call(instruction.nnn),
             0x3 => self.se_vx(instruction.x, instruction.kk),
             0x4 => self.sne_vx(instruction.x, instruction.kk),
-            0x5 => self.se_vx_vy(instruction.x, instruction.y),
+            0x5 => match instruction.n {
+                0x2 if xo_chip => self.ld_i_vx_vy(instruction.x, instruction.y, &mut bus.memory),
+                0x3 if xo_chip => self.ld_vx_vy_i(instruction.x, instruction.y, &bus.memory),
+                _ => self.se_vx_vy(instruction.x, instruction.y),
+            },
             0x6 => self.ld_vx(instruction.x, instruction.kk),
             0x7 => self.add_vx(instruction.x, instruction.kk),
             0x8 => match instruction.n {
@@ -319,18 +550,20 @@ impl CPU {
                 0x4 => self.add_vx_vy(instruction.x, instruction.y),
                 0x5 => self.sub_vx_vy(instruction.x, instruction.y),
                 0x6 => self.shr_vx_vy(instruction.x, instruction.y),
+                0x7 => self.subn_vx_vy(instruction.x, instruction.y),
                 0xe => self.shl_vx_vy(instruction.x, instruction.y),
                 _ => self.unknown_instruction(&instruction),
             },
             0x9 => self.sne_vx_vy(instruction.x, instruction.y),
             0xa => self.ld_i(instruction.nnn),
+            0xb => self.jp_v0(instruction.nnn),
             0xc => self.rnd(instruction.x, instruction.kk),
             0xd => self.drw(
                 instruction.x,
                 instruction.y,
                 instruction.n as usize,
-                &mut bus.memory,
-                &mut bus.display,
+                &bus.memory,
+                &mut bus.framebuffer,
             ),
             0xe => match instruction.kk {
                 0x9e => self.skp_vx(instruction.x, &mut bus.keyboard),
@@ -338,26 +571,100 @@ impl CPU {
                 _ => self.unknown_instruction(&instruction),
             },
             0xf => match instruction.kk {
+                0x00 if xo_chip && instruction.x == 0 => self.ld_i_long(&bus.memory),
+                0x01 if xo_chip => self.ld_planes(instruction.x as u8),
+                0x02 if xo_chip && instruction.x == 0 => {
+                    self.ld_pattern_i(&bus.memory, &mut bus.audio)
+                }
                 0x07 => self.ld_vx_dt(instruction.x),
                 0x0a => self.ld_vx_k(instruction.x, &mut bus.keyboard),
                 0x15 => self.ld_dt_vx(instruction.x),
                 0x18 => self.ld_st_vx(instruction.x),
                 0x1e => self.add_i_vx(instruction.x),
                 0x29 => self.ld_f_vx(instruction.x),
+                0x30 if superchip => self.ld_hf_vx(instruction.x),
                 0x33 => self.ld_bcd_vx(instruction.x, &mut bus.memory),
+                0x3a if xo_chip => self.ld_pitch_vx(instruction.x, &mut bus.audio),
                 0x55 => self.ld_i_vx(instruction.x, &mut bus.memory),
-                0x65 => self.ld_vx_i(instruction.x, &mut bus.memory),
+                0x65 => self.ld_vx_i(instruction.x, &bus.memory),
+                0x75 if superchip => self.ld_r_vx(instruction.x, &mut bus.flags),
+                0x85 if superchip => self.ld_vx_r(instruction.x, &bus.flags),
                 _ => self.unknown_instruction(&instruction),
             },
             _ => self.unknown_instruction(&instruction),
         }
 
-        if instruction.first != 0x2 && instruction.first != 0x1 && instruction.raw_bytes != 0x00ee {
+        if !matches!(instruction.first, 0x1 | 0x2 | 0xb) && instruction.raw_bytes != 0x00ee {
             // dont move the pc with JP, CALL, or RET instructions
-            self.program_counter += 2;
+            self.advance();
+        }
+        // skipping XO-CHIP's four byte instruction skips all of it
+        let skipped = self.program_counter == program_counter.wrapping_add(4) & self.address_mask();
+        if xo_chip && skipped && is_skip(&instruction) {
+            let skipped_address = program_counter.wrapping_add(2) & self.address_mask();
+            if self.instruction_at(skipped_address, &bus.memory) == LONG_I {
+                self.advance();
+            }
+        }
+    }
+
+    /// The flag goes in last so an 8xyN with VF as its destination is left holding the
+    /// flag rather than the result
+    fn set_vx_and_flag(&mut self, x: usize, result: u8, flag: bool) {
+        self.v_registers[x] = result;
+        self.v_registers[0xf] = flag as u8;
+    }
+
+    /// What `8xy6` and `8xyE` shift, see `Quirks::shift_vy`
+    fn shift_source(&self, x: usize, y: usize) -> u8 {
+        if self.quirks.shift_vy {
+            self.v_registers[y]
+        } else {
+            self.v_registers[x]
         }
     }
 
+    fn logic_vf_reset(&mut self) {
+        if self.quirks.vf_reset {
+            self.v_registers[0xf] = 0;
+        }
+    }
+
+    /// Moves I on after `Fx55` or `Fx65` stored or loaded V0 to VX
+    fn memory_increment(&mut self, x: usize) {
+        let increment = match self.quirks.memory_increment {
+            MemoryIncrement::Unchanged => return,
+            MemoryIncrement::ByX => x,
+            MemoryIncrement::ByXPlusOne => x + 1,
+        };
+        self.address_i = self.memory_address(increment) as u16;
+    }
+
+    /// Anything past the end of the instruction set's memory wraps back round to the start
+    fn address_mask(&self) -> u16 {
+        self.instruction_set.address_mask()
+    }
+
+    fn instruction_at(&self, address: u16, memory: &[u8]) -> u16 {
+        let next = address.wrapping_add(1) & self.address_mask();
+        u16::from_be_bytes([memory[address as usize], memory[next as usize]])
+    }
+
+    /// Moves the program counter on by one instruction
+    fn advance(&mut self) {
+        self.program_counter = self.program_counter.wrapping_add(2) & self.address_mask();
+    }
+
+    /// Goes back to run the current instruction again
+    fn rewind(&mut self) {
+        self.program_counter = self.program_counter.wrapping_sub(2) & self.address_mask();
+    }
+
+    /// `I` plus `offset`, wrapped to stay in memory
+    fn memory_address(&self, offset: usize) -> usize {
+        (self.address_i as usize + offset) & self.address_mask() as usize
+    }
+
     fn unknown_instruction(&self, instruction: &Instruction) {
         panic!(
             "Encountered unknown instruction {:#02x}",
@@ -366,11 +673,28 @@ impl CPU {
     }
 }
 
+/// VX to VY for XO-CHIP's `save` and `load`, backwards if Y comes first
+fn register_range(x: usize, y: usize) -> Box<dyn Iterator<Item = usize>> {
+    if x <= y {
+        Box::new(x..=y)
+    } else {
+        Box::new((y..=x).rev())
+    }
+}
+
+fn is_skip(instruction: &Instruction) -> bool {
+    matches!(instruction.first, 0x3 | 0x4 | 0x5 | 0x9 | 0xe)
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::audio::mock::{AudioEvent, MockAudioSource};
+    use crate::reference::{execute_and_check, is_known_instruction};
     use crate::Chip8;
     use crate::Chip8Platform::MOCK;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
 
     #[test]
     fn add_vx() {
@@ -435,22 +759,39 @@ mod test {
         assert_eq!(chip8.cpu.stack[chip8.cpu.stack_pointer as usize - 1], 0xcbf);
     }
 
+    #[test]
+    fn call_and_ret_wrap_the_stack() {
+        let mut chip8 = Chip8::new(MOCK);
+
+        chip8.cpu.stack_pointer = 15;
+        chip8
+            .cpu
+            .execute_instruction(Instruction::new(0x2123), &mut chip8.bus);
+        assert_eq!(chip8.cpu.stack_pointer, 0);
+
+        chip8
+            .cpu
+            .execute_instruction(Instruction::new(0x00ee), &mut chip8.bus);
+        assert_eq!(chip8.cpu.stack_pointer, 15);
+        assert_eq!(chip8.cpu.program_counter, 0x202);
+    }
+
     #[test]
     fn cls() {
         let mut chip8 = Chip8::new(MOCK);
         let instruction = Instruction::new(0x00e0);
 
-        chip8.bus.display.draw_pixel(1, 0, 1.0, true);
-        chip8.bus.display.draw_pixel(1, 3, 1.0, true);
-        chip8.bus.display.draw_pixel(4, 1, 1.0, true);
-        assert!(chip8.bus.display.get_pixel(1, 0));
-        assert!(chip8.bus.display.get_pixel(1, 3));
-        assert!(chip8.bus.display.get_pixel(4, 1));
+        chip8.bus.framebuffer.set_pixel(1, 0, true);
+        chip8.bus.framebuffer.set_pixel(1, 3, true);
+        chip8.bus.framebuffer.set_pixel(4, 1, true);
+        assert!(chip8.bus.framebuffer.get_pixel(1, 0));
+        assert!(chip8.bus.framebuffer.get_pixel(1, 3));
+        assert!(chip8.bus.framebuffer.get_pixel(4, 1));
 
         chip8.cpu.execute_instruction(instruction, &mut chip8.bus);
-        assert!(!chip8.bus.display.get_pixel(1, 0));
-        assert!(!chip8.bus.display.get_pixel(1, 3));
-        assert!(!chip8.bus.display.get_pixel(4, 1));
+        assert!(!chip8.bus.framebuffer.get_pixel(1, 0));
+        assert!(!chip8.bus.framebuffer.get_pixel(1, 3));
+        assert!(!chip8.bus.framebuffer.get_pixel(4, 1));
     }
 
     #[test]
@@ -462,6 +803,28 @@ mod test {
         assert_eq!(chip8.cpu.program_counter, 0xaba);
     }
 
+    #[test]
+    fn jp_v0() {
+        let mut chip8 = Chip8::new(MOCK);
+        let instruction = Instruction::new(0xb3f0);
+
+        chip8.cpu.v_registers[0x0] = 0x12;
+        chip8.cpu.v_registers[0x3] = 0x34;
+        chip8.cpu.execute_instruction(instruction, &mut chip8.bus);
+        assert_eq!(chip8.cpu.program_counter, 0x402);
+
+        chip8.cpu.quirks.jump_vx = true;
+        chip8.cpu.execute_instruction(instruction, &mut chip8.bus);
+        assert_eq!(chip8.cpu.program_counter, 0x424);
+
+        // wraps rather than running off the end of memory
+        chip8.cpu.v_registers[0xf] = 0x20;
+        chip8
+            .cpu
+            .execute_instruction(Instruction::new(0xbff0), &mut chip8.bus);
+        assert_eq!(chip8.cpu.program_counter, 0x010);
+    }
+
     #[test]
     fn ld_bcd_vx() {
         let mut chip8 = Chip8::new(MOCK);
@@ -501,6 +864,109 @@ mod test {
         assert_eq!(chip8.bus.memory[0x4], 0x9);
     }
 
+    #[test]
+    fn memory_increment_quirk() {
+        for (memory_increment, expected) in [
+            (MemoryIncrement::Unchanged, 0x300),
+            (MemoryIncrement::ByX, 0x303),
+            (MemoryIncrement::ByXPlusOne, 0x304),
+        ] {
+            for opcode in [0xf355, 0xf365] {
+                let mut chip8 = Chip8::new(MOCK);
+                chip8.cpu.quirks.memory_increment = memory_increment;
+                chip8.cpu.address_i = 0x300;
+                chip8
+                    .cpu
+                    .execute_instruction(Instruction::new(opcode), &mut chip8.bus);
+                assert_eq!(
+                    chip8.cpu.address_i, expected,
+                    "{:#06x} {:?}",
+                    opcode, memory_increment
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn ld_i_vx_wraps_memory() {
+        let mut chip8 = Chip8::new(MOCK);
+        let instruction = Instruction::new(0xf155);
+
+        chip8.cpu.address_i = 0xfff;
+        chip8.cpu.v_registers[0x0] = 0xb;
+        chip8.cpu.v_registers[0x1] = 0xa;
+        chip8.cpu.execute_instruction(instruction, &mut chip8.bus);
+
+        assert_eq!(chip8.bus.memory[0xfff], 0xb);
+        assert_eq!(chip8.bus.memory[0x0], 0xa);
+    }
+
+    fn mock_audio(chip8: &Chip8) -> &MockAudioSource {
+        chip8
+            .bus
+            .audio
+            .as_ref()
+            .as_any()
+            .downcast_ref::<MockAudioSource>()
+            .unwrap()
+    }
+
+    #[test]
+    fn ld_pattern_i() {
+        let mut chip8 = Chip8::new(MOCK);
+        let instruction = Instruction::new(0xf002);
+
+        chip8.cpu.instruction_set = InstructionSet::XoChip;
+        chip8.cpu.address_i = 0xfff8;
+        for offset in 0..16 {
+            chip8.bus.memory[(0xfff8 + offset) % 0x10000] = offset as u8 + 1;
+        }
+        chip8.cpu.execute_instruction(instruction, &mut chip8.bus);
+
+        // wraps round to the start of memory like the other loads from I
+        let expected: Vec<u8> = (1..=16).collect();
+        assert_eq!(mock_audio(&chip8).pattern.unwrap().to_vec(), expected);
+        assert_eq!(chip8.cpu.address_i, 0xfff8);
+        assert_eq!(chip8.cpu.program_counter, 0x202);
+    }
+
+    #[test]
+    fn ld_pitch_vx() {
+        let mut chip8 = Chip8::new(MOCK);
+        let instruction = Instruction::new(0xf53a);
+
+        chip8.cpu.instruction_set = InstructionSet::XoChip;
+        chip8.cpu.v_registers[0x5] = 0x70;
+        chip8.cpu.execute_instruction(instruction, &mut chip8.bus);
+        assert_eq!(mock_audio(&chip8).pitch, 0x70);
+
+        // a new rom starts back on the plain tone
+        chip8
+            .cpu
+            .execute_instruction(Instruction::new(0xf002), &mut chip8.bus);
+        chip8.reset();
+        assert_eq!(mock_audio(&chip8).pitch, 64);
+        assert_eq!(mock_audio(&chip8).pattern, None);
+    }
+
+    #[test]
+    fn ld_r_vx_and_ld_vx_r() {
+        let mut chip8 = Chip8::new(MOCK);
+        chip8.cpu.instruction_set = InstructionSet::Superchip;
+        chip8.cpu.v_registers[..4].copy_from_slice(&[1, 2, 3, 4]);
+        chip8
+            .cpu
+            .execute_instruction(Instruction::new(0xf275), &mut chip8.bus);
+        assert_eq!(&chip8.bus.flags.get()[..4], &[1, 2, 3, 0]);
+
+        chip8.cpu.v_registers = [0xff; 16];
+        chip8
+            .cpu
+            .execute_instruction(Instruction::new(0xf185), &mut chip8.bus);
+        assert_eq!(&chip8.cpu.v_registers[..3], &[1, 2, 0xff]);
+        assert_eq!(chip8.cpu.address_i, 0);
+    }
+
     #[test]
     fn ld_st_vx() {
         let mut chip8 = Chip8::new(MOCK);
@@ -511,6 +977,67 @@ mod test {
         assert_eq!(chip8.cpu.sound_timer, 0x7);
     }
 
+    #[test]
+    fn ld_st_vx_sounds_for_n_frames() {
+        for n in [0, 1, 2, 5, 0x10, 0xff] {
+            let mut chip8 = Chip8::new(MOCK);
+            let instruction = Instruction::new(0xf318);
+
+            chip8.cpu.v_registers[0x3] = n;
+            chip8.cpu.execute_instruction(instruction, &mut chip8.bus);
+            for _ in 0..n as u32 + 3 {
+                chip8.end_frame();
+            }
+
+            let audio = mock_audio(&chip8);
+            assert_eq!(audio.frames_of_sound(), n as u32, "ST {}", n);
+            let expected = if n == 0 {
+                vec![]
+            } else {
+                vec![(0, AudioEvent::Start), (n as u32, AudioEvent::Stop)]
+            };
+            assert_eq!(audio.timeline, expected, "ST {}", n);
+        }
+    }
+
+    #[test]
+    fn ld_st_vx_restarts_the_sound() {
+        let mut chip8 = Chip8::new(MOCK);
+        chip8.cpu.v_registers[0x0] = 3;
+
+        // topping the timer up while it's running carries on the same sound
+        chip8
+            .cpu
+            .execute_instruction(Instruction::new(0xf018), &mut chip8.bus);
+        chip8.end_frame();
+        chip8.end_frame();
+        chip8
+            .cpu
+            .execute_instruction(Instruction::new(0xf018), &mut chip8.bus);
+        for _ in 0..5 {
+            chip8.end_frame();
+        }
+        // and setting it again once it has stopped starts another
+        chip8
+            .cpu
+            .execute_instruction(Instruction::new(0xf018), &mut chip8.bus);
+        for _ in 0..5 {
+            chip8.end_frame();
+        }
+
+        let audio = mock_audio(&chip8);
+        assert_eq!(
+            audio.timeline,
+            vec![
+                (0, AudioEvent::Start),
+                (5, AudioEvent::Stop),
+                (7, AudioEvent::Start),
+                (10, AudioEvent::Stop)
+            ]
+        );
+        assert_eq!(audio.frames_of_sound(), 8);
+    }
+
     #[test]
     fn ld_vx_dt() {
         let mut chip8 = Chip8::new(MOCK);
@@ -657,12 +1184,11 @@ mod test {
         assert_eq!(chip8.cpu.v_registers[0xf], 0x0);
         assert_eq!(chip8.cpu.v_registers[0x7], 0b10111010); // 186
 
-        // TODO: check if this instruction should wrap around overflows
-        /*let instruction = Instruction::new(0x872e);
-        chip8.cpu.v_registers[0x7] = 0b1;
+        // the top bit is shifted out into VF
+        chip8.cpu.v_registers[0x7] = 0b10000001;
         chip8.cpu.execute_instruction(instruction, &mut chip8.bus);
-        assert_eq!(chip8.cpu.v_registers[0xf], 0x0);
-        assert_eq!(chip8.cpu.v_registers[0x1], 0b10);*/
+        assert_eq!(chip8.cpu.v_registers[0xf], 0x1);
+        assert_eq!(chip8.cpu.v_registers[0x7], 0b10);
     }
 
     #[test]
@@ -682,6 +1208,45 @@ mod test {
         assert_eq!(chip8.cpu.v_registers[0x1], 0b1111101);
     }
 
+    #[test]
+    fn shift_vy_quirk() {
+        let mut chip8 = Chip8::new(MOCK);
+        chip8.cpu.quirks.shift_vy = true;
+
+        chip8.cpu.v_registers[0x1] = 0xff;
+        chip8.cpu.v_registers[0x2] = 0b11;
+        chip8
+            .cpu
+            .execute_instruction(Instruction::new(0x8126), &mut chip8.bus);
+        assert_eq!(chip8.cpu.v_registers[0x1], 0b1);
+        assert_eq!(chip8.cpu.v_registers[0xf], 0x1);
+
+        chip8
+            .cpu
+            .execute_instruction(Instruction::new(0x812e), &mut chip8.bus);
+        assert_eq!(chip8.cpu.v_registers[0x1], 0b110);
+        assert_eq!(chip8.cpu.v_registers[0xf], 0x0);
+        assert_eq!(chip8.cpu.v_registers[0x2], 0b11);
+    }
+
+    #[test]
+    fn vf_reset_quirk() {
+        for opcode in [0x8011, 0x8012, 0x8013] {
+            let mut chip8 = Chip8::new(MOCK);
+            chip8.cpu.v_registers[0xf] = 0x5;
+            chip8
+                .cpu
+                .execute_instruction(Instruction::new(opcode), &mut chip8.bus);
+            assert_eq!(chip8.cpu.v_registers[0xf], 0x5, "{:#06x}", opcode);
+
+            chip8.cpu.quirks.vf_reset = true;
+            chip8
+                .cpu
+                .execute_instruction(Instruction::new(opcode), &mut chip8.bus);
+            assert_eq!(chip8.cpu.v_registers[0xf], 0x0, "{:#06x}", opcode);
+        }
+    }
+
     #[test]
     fn skp_vx() {
         let mut chip8 = Chip8::new(MOCK);
@@ -760,6 +1325,24 @@ mod test {
         assert_eq!(chip8.cpu.v_registers[0x3], 0xfa);
     }
 
+    #[test]
+    fn subn_vx_vy() {
+        let mut chip8 = Chip8::new(MOCK);
+        let instruction = Instruction::new(0x83b7);
+
+        chip8.cpu.v_registers[0x3] = 0x4;
+        chip8.cpu.v_registers[0xb] = 0x9;
+        chip8.cpu.execute_instruction(instruction, &mut chip8.bus);
+        assert_eq!(chip8.cpu.v_registers[0xf], 0x1);
+        assert_eq!(chip8.cpu.v_registers[0x3], 0x5);
+
+        chip8.cpu.v_registers[0x3] = 0xf;
+        chip8.cpu.v_registers[0xb] = 0x9;
+        chip8.cpu.execute_instruction(instruction, &mut chip8.bus);
+        assert_eq!(chip8.cpu.v_registers[0xf], 0x0);
+        assert_eq!(chip8.cpu.v_registers[0x3], 0xfa);
+    }
+
     #[test]
     fn xor_vx_vy() {
         let mut chip8 = Chip8::new(MOCK);
@@ -787,27 +1370,423 @@ mod test {
         chip8.cpu.execute_instruction(instruction, &mut chip8.bus);
 
         // row 1
-        assert!(!chip8.bus.display.get_pixel(0, 0));
-        assert!(!chip8.bus.display.get_pixel(1, 0));
-        assert!(!chip8.bus.display.get_pixel(2, 0));
-        assert!(chip8.bus.display.get_pixel(3, 0));
-        assert!(!chip8.bus.display.get_pixel(4, 0));
-        assert!(!chip8.bus.display.get_pixel(5, 0));
+        assert!(!chip8.bus.framebuffer.get_pixel(0, 0));
+        assert!(!chip8.bus.framebuffer.get_pixel(1, 0));
+        assert!(!chip8.bus.framebuffer.get_pixel(2, 0));
+        assert!(chip8.bus.framebuffer.get_pixel(3, 0));
+        assert!(!chip8.bus.framebuffer.get_pixel(4, 0));
+        assert!(!chip8.bus.framebuffer.get_pixel(5, 0));
 
         // row 2
-        assert!(!chip8.bus.display.get_pixel(0, 1));
-        assert!(!chip8.bus.display.get_pixel(1, 1));
-        assert!(chip8.bus.display.get_pixel(2, 1));
-        assert!(!chip8.bus.display.get_pixel(3, 1));
-        assert!(chip8.bus.display.get_pixel(4, 1));
-        assert!(!chip8.bus.display.get_pixel(5, 1));
+        assert!(!chip8.bus.framebuffer.get_pixel(0, 1));
+        assert!(!chip8.bus.framebuffer.get_pixel(1, 1));
+        assert!(chip8.bus.framebuffer.get_pixel(2, 1));
+        assert!(!chip8.bus.framebuffer.get_pixel(3, 1));
+        assert!(chip8.bus.framebuffer.get_pixel(4, 1));
+        assert!(!chip8.bus.framebuffer.get_pixel(5, 1));
 
         // row 3
-        assert!(!chip8.bus.display.get_pixel(0, 2));
-        assert!(chip8.bus.display.get_pixel(1, 2));
-        assert!(!chip8.bus.display.get_pixel(2, 2));
-        assert!(!chip8.bus.display.get_pixel(3, 2));
-        assert!(chip8.bus.display.get_pixel(4, 2));
-        assert!(!chip8.bus.display.get_pixel(5, 2));
+        assert!(!chip8.bus.framebuffer.get_pixel(0, 2));
+        assert!(chip8.bus.framebuffer.get_pixel(1, 2));
+        assert!(!chip8.bus.framebuffer.get_pixel(2, 2));
+        assert!(!chip8.bus.framebuffer.get_pixel(3, 2));
+        assert!(chip8.bus.framebuffer.get_pixel(4, 2));
+        assert!(!chip8.bus.framebuffer.get_pixel(5, 2));
+    }
+
+    #[test]
+    fn drw_display_wait() {
+        let mut chip8 = Chip8::new(MOCK);
+        let instruction = Instruction::new(0xd001);
+
+        chip8.cpu.quirks.display_wait = true;
+        chip8.cpu.program_counter = 0x2;
+        chip8.bus.memory[0x0] = 0b10000000;
+        chip8.cpu.execute_instruction(instruction, &mut chip8.bus);
+        assert!(chip8.cpu.is_waiting_for_vblank());
+        assert_eq!(chip8.cpu.program_counter, 0x2);
+        assert!(!chip8.bus.framebuffer.get_pixel(0, 0));
+
+        chip8.cpu.vblank();
+        assert!(!chip8.cpu.is_waiting_for_vblank());
+        chip8.cpu.execute_instruction(instruction, &mut chip8.bus);
+        assert_eq!(chip8.cpu.program_counter, 0x4);
+        assert!(chip8.bus.framebuffer.get_pixel(0, 0));
+
+        // every draw waits for its own vblank
+        chip8.cpu.execute_instruction(instruction, &mut chip8.bus);
+        assert!(chip8.cpu.is_waiting_for_vblank());
+        assert_eq!(chip8.cpu.program_counter, 0x4);
+    }
+
+    #[test]
+    fn drw_wraps_starting_position() {
+        let mut chip8 = Chip8::new(MOCK);
+        let instruction = Instruction::new(0xd012);
+
+        // a 2 pixel wide, 2 tall block starting off screen at (66, 35)
+        let sprite_data: [u8; 2] = [0b11000000, 0b11000000];
+        chip8.cpu.address_i = 0x500;
+        chip8.cpu.v_registers[0x0] = 66;
+        chip8.cpu.v_registers[0x1] = 35;
+        chip8.bus.memory[0x500..0x500 + sprite_data.len()].copy_from_slice(&sprite_data);
+        chip8.cpu.execute_instruction(instruction, &mut chip8.bus);
+
+        assert!(chip8.bus.framebuffer.get_pixel(2, 3));
+        assert!(chip8.bus.framebuffer.get_pixel(3, 3));
+        assert!(chip8.bus.framebuffer.get_pixel(2, 4));
+        assert!(chip8.bus.framebuffer.get_pixel(3, 4));
+        assert!(!chip8.bus.framebuffer.get_pixel(1, 3));
+        assert!(!chip8.bus.framebuffer.get_pixel(4, 4));
+    }
+
+    #[test]
+    fn drw_clips_at_edges() {
+        let mut chip8 = Chip8::new(MOCK);
+        let instruction = Instruction::new(0xd012);
+
+        // a 2 pixel wide, 2 tall block in the bottom right corner
+        let sprite_data: [u8; 2] = [0b11000000, 0b11000000];
+        chip8.cpu.address_i = 0x500;
+        chip8.cpu.v_registers[0x0] = 63;
+        chip8.cpu.v_registers[0x1] = 31;
+        chip8.bus.memory[0x500..0x500 + sprite_data.len()].copy_from_slice(&sprite_data);
+        chip8.cpu.execute_instruction(instruction, &mut chip8.bus);
+
+        assert!(chip8.bus.framebuffer.get_pixel(63, 31));
+        assert!(!chip8.bus.framebuffer.get_pixel(0, 31));
+        assert!(!chip8.bus.framebuffer.get_pixel(63, 0));
+        assert!(!chip8.bus.framebuffer.get_pixel(0, 0));
+    }
+
+    #[test]
+    fn drw_wraps_at_edges() {
+        let mut chip8 = Chip8::new(MOCK);
+        let instruction = Instruction::new(0xd012);
+
+        // a 2 pixel wide, 2 tall block in the bottom right corner
+        let sprite_data: [u8; 2] = [0b11000000, 0b11000000];
+        chip8.cpu.quirks.wrap_sprites = true;
+        chip8.cpu.address_i = 0x500;
+        chip8.cpu.v_registers[0x0] = 63;
+        chip8.cpu.v_registers[0x1] = 31;
+        chip8.bus.memory[0x500..0x500 + sprite_data.len()].copy_from_slice(&sprite_data);
+        chip8.cpu.execute_instruction(instruction, &mut chip8.bus);
+
+        assert!(chip8.bus.framebuffer.get_pixel(63, 31));
+        assert!(chip8.bus.framebuffer.get_pixel(0, 31));
+        assert!(chip8.bus.framebuffer.get_pixel(63, 0));
+        assert!(chip8.bus.framebuffer.get_pixel(0, 0));
+        assert_eq!(chip8.cpu.v_registers[0xf], 0x0);
+    }
+
+    #[test]
+    #[should_panic(expected = "unknown instruction")]
+    fn superchip_instructions_are_unknown_to_chip8() {
+        let mut chip8 = Chip8::new(MOCK);
+        chip8
+            .cpu
+            .execute_instruction(Instruction::new(0x00ff), &mut chip8.bus);
+    }
+
+    #[test]
+    #[should_panic(expected = "unknown instruction")]
+    fn xo_chip_instructions_are_unknown_to_superchip() {
+        let mut chip8 = Chip8::new(MOCK);
+        chip8.cpu.instruction_set = InstructionSet::Superchip;
+        chip8
+            .cpu
+            .execute_instruction(Instruction::new(0xf000), &mut chip8.bus);
+    }
+
+    #[test]
+    fn high_low() {
+        let mut chip8 = Chip8::new(MOCK);
+        chip8.cpu.instruction_set = InstructionSet::Superchip;
+
+        chip8.bus.framebuffer.set_pixel(3, 3, true);
+        chip8
+            .cpu
+            .execute_instruction(Instruction::new(0x00ff), &mut chip8.bus);
+        assert_eq!(
+            chip8.bus.framebuffer,
+            Framebuffer::new(HIRES_WIDTH, HIRES_HEIGHT)
+        );
+
+        chip8
+            .cpu
+            .execute_instruction(Instruction::new(0x00fe), &mut chip8.bus);
+        assert_eq!(chip8.bus.framebuffer, Framebuffer::default());
+    }
+
+    #[test]
+    fn scroll() {
+        let mut chip8 = Chip8::new(MOCK);
+        chip8.cpu.instruction_set = InstructionSet::XoChip;
+        chip8.bus.framebuffer = Framebuffer::new(HIRES_WIDTH, HIRES_HEIGHT);
+        chip8.bus.framebuffer.set_pixel(10, 10, true);
+
+        // down 2, right 4, left 4 and back up 2
+        for (opcode, x, y) in [
+            (0x00c2, 10, 12),
+            (0x00fb, 14, 12),
+            (0x00fc, 10, 12),
+            (0x00d2, 10, 10),
+        ] {
+            chip8
+                .cpu
+                .execute_instruction(Instruction::new(opcode), &mut chip8.bus);
+            assert!(chip8.bus.framebuffer.get_pixel(x, y), "{:#06x}", opcode);
+            assert_eq!(
+                chip8
+                    .bus
+                    .framebuffer
+                    .pixels()
+                    .iter()
+                    .filter(|&&p| p != 0)
+                    .count(),
+                1
+            );
+        }
+    }
+
+    #[test]
+    fn drw_16x16() {
+        let mut chip8 = Chip8::new(MOCK);
+        let instruction = Instruction::new(0xd010);
+
+        chip8.cpu.address_i = 0x500;
+        chip8.bus.memory[0x500..0x520].copy_from_slice(&[0xff; 32]);
+        // nothing at all for plain CHIP-8
+        chip8.cpu.execute_instruction(instruction, &mut chip8.bus);
+        assert!(chip8.bus.framebuffer.pixels().iter().all(|&p| p == 0));
+
+        chip8.cpu.instruction_set = InstructionSet::Superchip;
+        chip8.cpu.execute_instruction(instruction, &mut chip8.bus);
+        assert!(chip8.bus.framebuffer.get_pixel(0, 0));
+        assert!(chip8.bus.framebuffer.get_pixel(15, 15));
+        assert!(!chip8.bus.framebuffer.get_pixel(16, 0));
+        assert!(!chip8.bus.framebuffer.get_pixel(0, 16));
+        assert_eq!(chip8.cpu.v_registers[0xf], 0x0);
+    }
+
+    #[test]
+    fn drw_planes() {
+        let mut chip8 = Chip8::new(MOCK);
+        chip8.cpu.instruction_set = InstructionSet::XoChip;
+        chip8.cpu.address_i = 0x500;
+        // plane 1's sprite, then plane 2's
+        chip8.bus.memory[0x500..0x502].copy_from_slice(&[0x80, 0xc0]);
+
+        chip8
+            .cpu
+            .execute_instruction(Instruction::new(0xf301), &mut chip8.bus);
+        chip8
+            .cpu
+            .execute_instruction(Instruction::new(0xd011), &mut chip8.bus);
+        assert_eq!(&chip8.bus.framebuffer.pixels()[..2], &[3, 2]);
+
+        // only plane 2 collides and gets cleared
+        chip8
+            .cpu
+            .execute_instruction(Instruction::new(0xf201), &mut chip8.bus);
+        chip8
+            .cpu
+            .execute_instruction(Instruction::new(0xd011), &mut chip8.bus);
+        assert_eq!(&chip8.bus.framebuffer.pixels()[..2], &[1, 2]);
+        assert_eq!(chip8.cpu.v_registers[0xf], 0x1);
+        chip8
+            .cpu
+            .execute_instruction(Instruction::new(0x00e0), &mut chip8.bus);
+        assert_eq!(&chip8.bus.framebuffer.pixels()[..2], &[1, 0]);
+    }
+
+    #[test]
+    fn ld_hf_vx() {
+        let mut chip8 = Chip8::new(MOCK);
+        chip8.cpu.instruction_set = InstructionSet::Superchip;
+        chip8.reset();
+
+        chip8.cpu.v_registers[0x3] = 0x7;
+        chip8
+            .cpu
+            .execute_instruction(Instruction::new(0xf330), &mut chip8.bus);
+        let address = chip8.cpu.address_i as usize;
+        assert_eq!(
+            &chip8.bus.memory[address..address + 10],
+            &[0xff, 0xff, 0x03, 0x03, 0x06, 0x0c, 0x18, 0x18, 0x18, 0x18]
+        );
+    }
+
+    #[test]
+    fn exit() {
+        let mut chip8 = Chip8::new(MOCK);
+        chip8.cpu.instruction_set = InstructionSet::Superchip;
+
+        for _ in 0..3 {
+            chip8
+                .cpu
+                .execute_instruction(Instruction::new(0x00fd), &mut chip8.bus);
+        }
+        assert_eq!(chip8.cpu.program_counter, 0x200);
+    }
+
+    #[test]
+    fn ld_i_vx_vy_and_ld_vx_vy_i() {
+        let mut chip8 = Chip8::new(MOCK);
+        chip8.cpu.instruction_set = InstructionSet::XoChip;
+        chip8.cpu.address_i = 0x500;
+        chip8.cpu.v_registers[1..4].copy_from_slice(&[1, 2, 3]);
+
+        chip8
+            .cpu
+            .execute_instruction(Instruction::new(0x5132), &mut chip8.bus);
+        assert_eq!(&chip8.bus.memory[0x500..0x503], &[1, 2, 3]);
+        // backwards when Y comes first
+        chip8
+            .cpu
+            .execute_instruction(Instruction::new(0x5312), &mut chip8.bus);
+        assert_eq!(&chip8.bus.memory[0x500..0x503], &[3, 2, 1]);
+        assert_eq!(chip8.cpu.address_i, 0x500);
+
+        chip8
+            .cpu
+            .execute_instruction(Instruction::new(0x5463), &mut chip8.bus);
+        assert_eq!(&chip8.cpu.v_registers[4..7], &[3, 2, 1]);
+        assert_eq!(chip8.cpu.address_i, 0x500);
+        assert_eq!(chip8.cpu.program_counter, 0x206);
+    }
+
+    #[test]
+    fn ld_i_long() {
+        let mut chip8 = Chip8::new(MOCK);
+        chip8.cpu.instruction_set = InstructionSet::XoChip;
+        chip8.bus.memory[0x200..0x204].copy_from_slice(&[0xf0, 0x00, 0xab, 0xcd]);
+
+        chip8
+            .cpu
+            .execute_instruction(Instruction::new(0xf000), &mut chip8.bus);
+        assert_eq!(chip8.cpu.address_i, 0xabcd);
+        assert_eq!(chip8.cpu.program_counter, 0x204);
+
+        // a skip takes all four bytes with it
+        chip8.cpu.program_counter = 0x1fe;
+        chip8
+            .cpu
+            .execute_instruction(Instruction::new(0x3000), &mut chip8.bus);
+        assert_eq!(chip8.cpu.program_counter, 0x204);
+    }
+
+    #[test]
+    fn xo_chip_addresses_wrap_at_64k() {
+        let mut chip8 = Chip8::new(MOCK);
+        chip8.cpu.instruction_set = InstructionSet::XoChip;
+
+        chip8.cpu.address_i = 0xffff;
+        chip8.cpu.v_registers[0x0] = 0x2;
+        chip8
+            .cpu
+            .execute_instruction(Instruction::new(0xf01e), &mut chip8.bus);
+        assert_eq!(chip8.cpu.address_i, 0x1);
+
+        chip8.cpu.program_counter = 0xfffe;
+        chip8
+            .cpu
+            .execute_instruction(Instruction::new(0x6000), &mut chip8.bus);
+        assert_eq!(chip8.cpu.program_counter, 0x0);
+    }
+
+    fn random_instructions(
+        seed: u64,
+        count: usize,
+        instruction_set: InstructionSet,
+    ) -> Vec<Instruction> {
+        let mut rng = StdRng::seed_from_u64(seed);
+        std::iter::repeat_with(|| Instruction::new(rng.gen()))
+            .filter(|instruction| is_known_instruction(instruction, instruction_set))
+            .take(count)
+            .collect()
+    }
+
+    #[test]
+    fn random_instruction_streams() {
+        let instruction_sets = [
+            InstructionSet::Chip8,
+            InstructionSet::Superchip,
+            InstructionSet::XoChip,
+        ];
+        for seed in 0..100 {
+            let instruction_set = instruction_sets[seed as usize % instruction_sets.len()];
+            let mut chip8 = Chip8::new(MOCK);
+            chip8.cpu.instruction_set = instruction_set;
+            chip8.reset();
+            for instruction in random_instructions(seed, 1000, instruction_set) {
+                execute_and_check(&mut chip8, instruction);
+            }
+        }
+    }
+
+    #[test]
+    fn alu_every_register_pair() {
+        // (N of 8xyN, VX, VY, result, VF)
+        let table = [
+            (0x4, 0x12, 0x34, 0x46, 0x0),
+            (0x4, 0xf0, 0x20, 0x10, 0x1),
+            (0x4, 0x80, 0x80, 0x00, 0x1),
+            (0x5, 0x34, 0x12, 0x22, 0x1),
+            (0x5, 0x12, 0x34, 0xde, 0x0),
+            (0x5, 0x42, 0x42, 0x00, 0x1),
+            (0x6, 0x42, 0x13, 0x21, 0x0),
+            (0x6, 0x05, 0x05, 0x02, 0x1),
+            (0x7, 0x12, 0x34, 0x22, 0x1),
+            (0x7, 0x34, 0x12, 0xde, 0x0),
+            (0x7, 0x42, 0x42, 0x00, 0x1),
+            (0xe, 0x42, 0x99, 0x84, 0x0),
+            (0xe, 0x81, 0x81, 0x02, 0x1),
+        ];
+
+        for (n, vx, vy, result, flag) in table {
+            for x in 0..16 {
+                for y in 0..16 {
+                    // one register can't hold two different values
+                    if x == y && vx != vy {
+                        continue;
+                    }
+                    let mut chip8 = Chip8::new(MOCK);
+                    let opcode = 0x8000 | (x << 8 | y << 4) as u16 | n;
+
+                    chip8.cpu.v_registers[x] = vx;
+                    chip8.cpu.v_registers[y] = vy;
+                    chip8
+                        .cpu
+                        .execute_instruction(Instruction::new(opcode), &mut chip8.bus);
+
+                    // VF as the destination ends up with the flag
+                    let expected_vx = if x == 0xf { flag } else { result };
+                    assert_eq!(
+                        (chip8.cpu.v_registers[x], chip8.cpu.v_registers[0xf]),
+                        (expected_vx, flag),
+                        "{:#06x} with VX {:#04x} and VY {:#04x}",
+                        opcode,
+                        vx,
+                        vy
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn alu_matches_reference() {
+        let mut chip8 = Chip8::new(MOCK);
+        for op in [0x8014, 0x8015, 0x8016, 0x8017, 0x801e, 0x8f14, 0x80f5] {
+            for vx in 0..=0xff {
+                for vy in 0..=0xff {
+                    chip8.cpu.v_registers[0x0] = vx;
+                    chip8.cpu.v_registers[0x1] = vy;
+                    execute_and_check(&mut chip8, Instruction::new(op));
+                }
+            }
+        }
     }
 }
@@ -0,0 +1,333 @@
+//! Per-rom settings from a local copy of the chip-8-database
+//! (https://github.com/chip-8/chip-8-database), looked up by the SHA-1 of the rom's bytes.
+//! `make database` downloads the three files it's made of into `database/`.
+
+use crate::cpu::InstructionSet;
+use crate::display::palette::Palette;
+use crate::keyboard::Keymap;
+use crate::quirks::{MemoryIncrement, Quirks};
+use serde::Deserialize;
+use std::collections::HashMap;
+
+// what the database's controls are bound to in the browser: the arrow keys, space and enter
+const KEY_CODES: [(&str, u8); 6] = [
+    ("left", 37),
+    ("up", 38),
+    ("right", 39),
+    ("down", 40),
+    ("a", 32),
+    ("b", 13),
+];
+
+#[derive(Clone, Copy, Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct DatabaseQuirks {
+    shift: Option<bool>,
+    memory_increment_by_x: Option<bool>,
+    memory_leave_i_unchanged: Option<bool>,
+    wrap: Option<bool>,
+    jump: Option<bool>,
+    vblank: Option<bool>,
+    logic: Option<bool>,
+}
+
+impl DatabaseQuirks {
+    /// These quirks with anything `overrides` sets taking priority
+    fn merged(self, overrides: &DatabaseQuirks) -> Self {
+        Self {
+            shift: overrides.shift.or(self.shift),
+            memory_increment_by_x: overrides
+                .memory_increment_by_x
+                .or(self.memory_increment_by_x),
+            memory_leave_i_unchanged: overrides
+                .memory_leave_i_unchanged
+                .or(self.memory_leave_i_unchanged),
+            wrap: overrides.wrap.or(self.wrap),
+            jump: overrides.jump.or(self.jump),
+            vblank: overrides.vblank.or(self.vblank),
+            logic: overrides.logic.or(self.logic),
+        }
+    }
+
+    /// The database's `shift` and `jump` are SUPER-CHIP's behaviours and `logic` and
+    /// `vblank` the COSMAC VIP's, anything it leaves out keeps our default
+    fn to_quirks(self) -> Quirks {
+        let defaults = Quirks::default();
+        let memory_increment = if self.memory_leave_i_unchanged == Some(true) {
+            MemoryIncrement::Unchanged
+        } else if self.memory_increment_by_x == Some(true) {
+            MemoryIncrement::ByX
+        } else if self.memory_leave_i_unchanged == Some(false) {
+            MemoryIncrement::ByXPlusOne
+        } else {
+            defaults.memory_increment
+        };
+        Quirks {
+            display_wait: self.vblank.unwrap_or(defaults.display_wait),
+            wrap_sprites: self.wrap.unwrap_or(defaults.wrap_sprites),
+            shift_vy: self.shift.map_or(defaults.shift_vy, |shift| !shift),
+            vf_reset: self.logic.unwrap_or(defaults.vf_reset),
+            memory_increment,
+            jump_vx: self.jump.unwrap_or(defaults.jump_vx),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct DatabasePlatform {
+    id: String,
+    default_tickrate: Option<u32>,
+    #[serde(default)]
+    quirks: DatabaseQuirks,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct DatabaseColors {
+    pixels: Option<Vec<String>>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct DatabaseRom {
+    #[serde(default)]
+    platforms: Vec<String>,
+    tickrate: Option<u32>,
+    start_address: Option<u16>,
+    #[serde(default)]
+    keys: HashMap<String, u8>,
+    #[serde(default)]
+    colors: DatabaseColors,
+    #[serde(default)]
+    quirky_platforms: HashMap<String, DatabaseQuirks>,
+}
+
+#[derive(Debug, Deserialize)]
+struct DatabaseProgram {
+    title: String,
+    #[serde(default)]
+    authors: Vec<String>,
+    #[serde(default)]
+    roms: HashMap<String, DatabaseRom>,
+}
+
+/// Everything the database knows about how to run a rom
+#[derive(Clone, Debug, PartialEq)]
+pub struct RomSettings {
+    pub title: String,
+    pub authors: Vec<String>,
+    /// The database's id for the platform it was written for, e.g. `originalChip8`
+    pub platform: String,
+    pub quirks: Quirks,
+    /// Instructions per 60hz frame
+    pub tickrate: Option<u32>,
+    pub start_address: Option<u16>,
+    pub keymap: Keymap,
+    pub palette: Option<Palette>,
+}
+
+/// The chip-8-database's `sha1-hashes.json`, `programs.json` and `platforms.json`. Empty
+/// until they're loaded, so every lookup misses.
+#[derive(Debug, Default)]
+pub struct RomDatabase {
+    hashes: HashMap<String, usize>,
+    programs: Vec<DatabaseProgram>,
+    platforms: Vec<DatabasePlatform>,
+}
+
+impl RomDatabase {
+    pub fn from_json(
+        hashes_json: &str,
+        programs_json: &str,
+        platforms_json: &str,
+    ) -> Result<Self, serde_json::Error> {
+        Ok(Self {
+            hashes: serde_json::from_str(hashes_json)?,
+            programs: serde_json::from_str(programs_json)?,
+            platforms: serde_json::from_str(platforms_json)?,
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.hashes.is_empty()
+    }
+
+    pub fn lookup(&self, rom_bytes: &[u8]) -> Option<RomSettings> {
+        let hash = sha1_hex(rom_bytes);
+        let program = self.programs.get(*self.hashes.get(&hash)?)?;
+        let rom = program.roms.get(&hash)?;
+
+        // roms that run on more than one platform list the one they were written for first
+        let platform_id = rom.platforms.first()?;
+        let platform = self.platforms.iter().find(|p| &p.id == platform_id);
+        let platform_quirks = platform.map(|p| p.quirks).unwrap_or_default();
+        let quirks = match rom.quirky_platforms.get(platform_id) {
+            Some(overrides) => platform_quirks.merged(overrides),
+            None => platform_quirks,
+        };
+
+        let mut bindings: Vec<(u8, u8)> = KEY_CODES
+            .iter()
+            .filter_map(|&(name, key_code)| Some((key_code, *rom.keys.get(name)?)))
+            .collect();
+        bindings.sort();
+
+        let palette = rom.colors.pixels.as_ref().and_then(|pixels| {
+            let hex_colors: Vec<&str> = pixels.iter().map(String::as_str).collect();
+            Palette::from_hex_colors(&hex_colors)
+        });
+
+        Some(RomSettings {
+            title: program.title.clone(),
+            authors: program.authors.clone(),
+            platform: platform_id.clone(),
+            quirks: quirks.to_quirks(),
+            tickrate: rom
+                .tickrate
+                .or_else(|| platform.and_then(|p| p.default_tickrate)),
+            start_address: rom.start_address,
+            keymap: Keymap { bindings },
+            palette,
+        })
+    }
+}
+
+impl RomSettings {
+    /// What the platform's roms can use, CHIP-8 for the ones that aren't SUPER-CHIP or
+    /// XO-CHIP
+    pub fn instruction_set(&self) -> InstructionSet {
+        match self.platform.as_str() {
+            "superchip" | "superchip1" | "megachip8" => InstructionSet::Superchip,
+            "xochip" => InstructionSet::XoChip,
+            _ => InstructionSet::Chip8,
+        }
+    }
+}
+
+/// Lowercase hex SHA-1 of the bytes, how the database keys its roms
+pub fn sha1_hex(bytes: &[u8]) -> String {
+    sha1_smol::Sha1::from(bytes).digest().to_string()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // sha1 of "abc" and of "xyz"
+    const ABC_HASH: &str = "a9993e364706816aba3e25717850c26c9cd0d89d";
+    const XYZ_HASH: &str = "66b27417d37e024c46526c2f6d358a754fc552f3";
+
+    fn database() -> RomDatabase {
+        let hashes = format!(r#"{{ "{}": 0, "{}": 1 }}"#, ABC_HASH, XYZ_HASH);
+        let programs = format!(
+            r##"[
+                {{
+                    "title": "Alphabet",
+                    "authors": ["Someone"],
+                    "roms": {{
+                        "{}": {{
+                            "file": "abc.ch8",
+                            "platforms": ["superchip", "originalChip8"],
+                            "tickrate": 30,
+                            "keys": {{ "up": 5, "down": 8, "a": 6 }},
+                            "colors": {{ "pixels": ["#000000", "#ff0000"] }},
+                            "quirkyPlatforms": {{ "superchip": {{ "vblank": true }} }}
+                        }}
+                    }}
+                }},
+                {{
+                    "title": "Eti Game",
+                    "roms": {{
+                        "{}": {{ "platforms": ["originalChip8"], "startAddress": 1536 }}
+                    }}
+                }}
+            ]"##,
+            ABC_HASH, XYZ_HASH
+        );
+        let platforms = r#"[
+            {
+                "id": "originalChip8",
+                "name": "Cosmac VIP",
+                "defaultTickrate": 15,
+                "quirks": {
+                    "shift": false, "memoryIncrementByX": false, "memoryLeaveIUnchanged": false,
+                    "wrap": false, "jump": false, "vblank": true, "logic": true
+                }
+            },
+            {
+                "id": "superchip",
+                "name": "Superchip",
+                "defaultTickrate": 30,
+                "quirks": {
+                    "shift": true, "memoryIncrementByX": false, "memoryLeaveIUnchanged": true,
+                    "wrap": false, "jump": true, "vblank": false, "logic": false
+                }
+            }
+        ]"#;
+        RomDatabase::from_json(&hashes, &programs, platforms).unwrap()
+    }
+
+    #[test]
+    fn sha1_hex_matches_the_database() {
+        assert_eq!(sha1_hex(b"abc"), ABC_HASH);
+    }
+
+    #[test]
+    fn lookup() {
+        let settings = database().lookup(b"abc").unwrap();
+
+        assert_eq!(settings.title, "Alphabet");
+        assert_eq!(settings.authors, vec!["Someone"]);
+        assert_eq!(settings.platform, "superchip");
+        assert_eq!(settings.instruction_set(), InstructionSet::Superchip);
+        assert_eq!(settings.tickrate, Some(30));
+        assert_eq!(settings.start_address, None);
+        // superchip's quirks with the rom's own vblank on top
+        assert_eq!(
+            settings.quirks,
+            Quirks {
+                display_wait: true,
+                wrap_sprites: false,
+                shift_vy: false,
+                vf_reset: false,
+                memory_increment: MemoryIncrement::Unchanged,
+                jump_vx: true,
+            }
+        );
+        assert_eq!(settings.keymap.bindings, vec![(32, 6), (38, 5), (40, 8)]);
+        assert_eq!(
+            settings.palette,
+            Palette::from_hex_colors(&["#000000", "#ff0000"])
+        );
+    }
+
+    #[test]
+    fn lookup_platform_defaults() {
+        let settings = database().lookup(b"xyz").unwrap();
+
+        assert_eq!(settings.tickrate, Some(15));
+        assert_eq!(settings.instruction_set(), InstructionSet::Chip8);
+        assert_eq!(settings.start_address, Some(0x600));
+        assert_eq!(settings.palette, None);
+        assert!(settings.keymap.bindings.is_empty());
+        assert_eq!(
+            settings.quirks,
+            Quirks {
+                display_wait: true,
+                wrap_sprites: false,
+                shift_vy: true,
+                vf_reset: true,
+                memory_increment: MemoryIncrement::ByXPlusOne,
+                jump_vx: false,
+            }
+        );
+    }
+
+    #[test]
+    fn lookup_misses() {
+        assert_eq!(database().lookup(b"not in there"), None);
+        assert!(RomDatabase::default().is_empty());
+        assert_eq!(RomDatabase::default().lookup(b"abc"), None);
+        assert!(RomDatabase::from_json("{", "[]", "[]").is_err());
+    }
+}
@@ -0,0 +1,295 @@
+use crate::display::framebuffer::Framebuffer;
+use crate::display::palette::Palette;
+use std::collections::VecDeque;
+
+pub const MAX_BLEND_FRAMES: usize = 4;
+
+/// How the last few presented frames are combined to hide the flicker from games
+/// XOR-erasing and redrawing their sprites every frame
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BlendMode {
+    Off,
+    /// A pixel is lit if it was lit in any of the last n frames
+    Or(usize),
+    /// Each pixel's colour is averaged over the last n frames
+    Average(usize),
+    /// A pixel that switches off stays lit in its old colour for one extra frame
+    KeepOneFrame,
+}
+
+impl BlendMode {
+    /// `off`, `or`, `average` or `keep`. `frames` only matters for `or` and `average`
+    /// and is clamped to 2 - `MAX_BLEND_FRAMES`.
+    pub fn from_name(name: &str, frames: usize) -> Option<Self> {
+        let frames = frames.clamp(2, MAX_BLEND_FRAMES);
+        match name {
+            "off" => Some(BlendMode::Off),
+            "or" => Some(BlendMode::Or(frames)),
+            "average" => Some(BlendMode::Average(frames)),
+            "keep" => Some(BlendMode::KeepOneFrame),
+            _ => None,
+        }
+    }
+
+    /// How many frames, including the newest, go into a blended frame
+    pub fn frame_count(&self) -> usize {
+        match *self {
+            BlendMode::Off => 1,
+            BlendMode::Or(frames) | BlendMode::Average(frames) => frames.clamp(1, MAX_BLEND_FRAMES),
+            BlendMode::KeepOneFrame => 2,
+        }
+    }
+}
+
+/// Keeps the history of presented frames and composites them for backends that output
+/// a plain framebuffer or RGBA pixels
+pub struct FrameBlender {
+    mode: BlendMode,
+    // newest first
+    history: VecDeque<Framebuffer>,
+}
+
+impl Default for FrameBlender {
+    fn default() -> Self {
+        Self::new(BlendMode::Off)
+    }
+}
+
+impl FrameBlender {
+    pub fn new(mode: BlendMode) -> Self {
+        Self {
+            mode,
+            history: VecDeque::with_capacity(MAX_BLEND_FRAMES),
+        }
+    }
+
+    pub fn mode(&self) -> BlendMode {
+        self.mode
+    }
+
+    pub fn set_mode(&mut self, mode: BlendMode) {
+        self.mode = mode;
+        self.history.truncate(mode.frame_count());
+    }
+
+    pub fn push(&mut self, framebuffer: &Framebuffer) {
+        // a resolution change makes the older frames meaningless
+        if let Some(newest) = self.history.front() {
+            if newest.get_width() != framebuffer.get_width()
+                || newest.get_height() != framebuffer.get_height()
+            {
+                self.history.clear();
+            }
+        }
+        self.history.truncate(self.mode.frame_count() - 1);
+        self.history.push_front(framebuffer.clone());
+    }
+
+    /// Frames going into the blend, newest first
+    pub fn frames(&self) -> impl Iterator<Item = &Framebuffer> {
+        self.history.iter()
+    }
+
+    /// The blended frame as pixel values. `Average` can't mix colours here, so a plane's
+    /// bit is lit if it was in at least half the frames, use `blend_rgba` for the real thing.
+    pub fn blend(&self) -> Framebuffer {
+        let mut frames = self.history.iter();
+        let mut blended = frames.next().cloned().unwrap_or_default();
+        match self.mode {
+            BlendMode::Off => {}
+            BlendMode::Or(_) => {
+                for frame in frames {
+                    blended
+                        .pixels_mut()
+                        .iter_mut()
+                        .zip(frame.pixels())
+                        .for_each(|(pixel, older)| *pixel |= older);
+                }
+            }
+            BlendMode::Average(_) => {
+                let count = self.history.len();
+                for (index, pixel) in blended.pixels_mut().iter_mut().enumerate() {
+                    let lit_in = |plane: u8| {
+                        self.history
+                            .iter()
+                            .filter(|frame| frame.pixels()[index] & plane != 0)
+                            .count()
+                    };
+                    *pixel = [1, 2]
+                        .into_iter()
+                        .filter(|&plane| lit_in(plane) * 2 >= count)
+                        .fold(0, |pixel, plane| pixel | plane);
+                }
+            }
+            BlendMode::KeepOneFrame => {
+                // only a pixel that went dark shows the previous frame, one that changed
+                // colour shows the new colour rather than a mix of both
+                if let Some(previous) = frames.next() {
+                    blended
+                        .pixels_mut()
+                        .iter_mut()
+                        .zip(previous.pixels())
+                        .filter(|(pixel, _)| **pixel == 0)
+                        .for_each(|(pixel, &older)| *pixel = older);
+                }
+            }
+        }
+        blended
+    }
+
+    /// The blended frame as RGBA bytes, row by row
+    pub fn blend_rgba(&self, palette: &Palette) -> Vec<u8> {
+        let average = matches!(self.mode, BlendMode::Average(_));
+        if !average {
+            return self.blend().to_rgba(palette);
+        }
+
+        let mut sums = vec![0u32; self.history.front().map_or(0, |f| f.pixels().len() * 3)];
+        for frame in &self.history {
+            for (sum, &pixel) in sums.chunks_mut(3).zip(frame.pixels()) {
+                let color = palette.color(pixel);
+                sum[0] += color.r as u32;
+                sum[1] += color.g as u32;
+                sum[2] += color.b as u32;
+            }
+        }
+
+        let count = self.history.len().max(1) as u32;
+        sums.chunks(3)
+            .flat_map(|sum| {
+                [
+                    (sum[0] / count) as u8,
+                    (sum[1] / count) as u8,
+                    (sum[2] / count) as u8,
+                    0xff,
+                ]
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::display::palette::Color;
+
+    fn frame_with_pixel(x: usize, y: usize) -> Framebuffer {
+        let mut framebuffer = Framebuffer::default();
+        framebuffer.set_pixel(x, y, true);
+        framebuffer
+    }
+
+    #[test]
+    fn blend_off() {
+        let mut blender = FrameBlender::new(BlendMode::Off);
+
+        blender.push(&frame_with_pixel(0, 0));
+        blender.push(&frame_with_pixel(1, 0));
+        let blended = blender.blend();
+        assert!(!blended.get_pixel(0, 0));
+        assert!(blended.get_pixel(1, 0));
+    }
+
+    #[test]
+    fn blend_or() {
+        let mut blender = FrameBlender::new(BlendMode::Or(3));
+
+        blender.push(&frame_with_pixel(0, 0));
+        blender.push(&frame_with_pixel(1, 0));
+        blender.push(&frame_with_pixel(2, 0));
+        let blended = blender.blend();
+        assert!(blended.get_pixel(0, 0));
+        assert!(blended.get_pixel(1, 0));
+        assert!(blended.get_pixel(2, 0));
+
+        // the first frame drops out of the window
+        blender.push(&Framebuffer::default());
+        let blended = blender.blend();
+        assert!(!blended.get_pixel(0, 0));
+        assert!(blended.get_pixel(1, 0));
+        assert!(blended.get_pixel(2, 0));
+    }
+
+    #[test]
+    fn blend_keep_one_frame() {
+        let mut blender = FrameBlender::new(BlendMode::KeepOneFrame);
+
+        blender.push(&frame_with_pixel(0, 0));
+        blender.push(&Framebuffer::default());
+        assert!(blender.blend().get_pixel(0, 0));
+
+        blender.push(&Framebuffer::default());
+        assert!(!blender.blend().get_pixel(0, 0));
+    }
+
+    #[test]
+    fn blend_keep_one_frame_shows_the_new_colour() {
+        let mut blender = FrameBlender::new(BlendMode::KeepOneFrame);
+        let mut first = Framebuffer::default();
+        first.pixels_mut()[0] = 1;
+        let mut second = Framebuffer::default();
+        second.pixels_mut()[0] = 2;
+
+        blender.push(&first);
+        blender.push(&second);
+        assert_eq!(blender.blend().pixels()[0], 2);
+
+        blender.set_mode(BlendMode::Or(2));
+        blender.push(&first);
+        assert_eq!(blender.blend().pixels()[0], 3);
+    }
+
+    #[test]
+    fn blend_average() {
+        let mut blender = FrameBlender::new(BlendMode::Average(3));
+
+        blender.push(&frame_with_pixel(0, 0));
+        blender.push(&frame_with_pixel(0, 0));
+        blender.push(&frame_with_pixel(1, 0));
+        let blended = blender.blend();
+        assert!(blended.get_pixel(0, 0));
+        assert!(!blended.get_pixel(1, 0));
+    }
+
+    #[test]
+    fn blend_rgba_average() {
+        let palette = Palette::from_hex_colors(&["#000000", "#c8c8c8"]).unwrap();
+        let mut blender = FrameBlender::new(BlendMode::Average(4));
+
+        blender.push(&frame_with_pixel(0, 0));
+        blender.push(&Framebuffer::default());
+        blender.push(&Framebuffer::default());
+        blender.push(&Framebuffer::default());
+        let rgba = blender.blend_rgba(&palette);
+        assert_eq!(rgba.len(), 64 * 32 * 4);
+        assert_eq!(&rgba[0..4], &[50, 50, 50, 0xff]);
+        assert_eq!(&rgba[4..8], &[0, 0, 0, 0xff]);
+    }
+
+    #[test]
+    fn blend_rgba_or() {
+        let palette = Palette::from_hex_colors(&["#000000", "#ffffff"]).unwrap();
+        let mut blender = FrameBlender::new(BlendMode::Or(2));
+
+        blender.push(&frame_with_pixel(0, 0));
+        blender.push(&Framebuffer::default());
+        let rgba = blender.blend_rgba(&palette);
+        let white = palette.color(1);
+        assert_eq!(&rgba[0..4], &[white.r, white.g, white.b, 0xff]);
+        assert_eq!(palette.color(0), Color::rgb(0, 0, 0));
+    }
+
+    #[test]
+    fn from_name() {
+        assert_eq!(BlendMode::from_name("or", 3), Some(BlendMode::Or(3)));
+        assert_eq!(
+            BlendMode::from_name("average", 10),
+            Some(BlendMode::Average(4))
+        );
+        assert_eq!(
+            BlendMode::from_name("keep", 0),
+            Some(BlendMode::KeepOneFrame)
+        );
+        assert_eq!(BlendMode::from_name("xor", 2), None);
+    }
+}
@@ -0,0 +1,65 @@
+use crate::display::blend::{BlendMode, FrameBlender};
+use crate::display::framebuffer::Framebuffer;
+use crate::display::palette::Palette;
+use crate::display::Display;
+use wasm_bindgen::{Clamped, JsCast};
+use web_sys::{CanvasRenderingContext2d, HtmlCanvasElement, ImageData};
+
+/// Fallback for browsers without WebGL2, the framebuffer is coloured on the CPU and put
+/// straight onto a canvas the size of the chip8 screen which the page scales up
+pub struct Canvas2dDisplay {
+    context: CanvasRenderingContext2d,
+    canvas: HtmlCanvasElement,
+    blender: FrameBlender,
+    palette: Palette,
+}
+
+impl Canvas2dDisplay {
+    pub fn new(canvas: HtmlCanvasElement) -> Self {
+        let context = canvas
+            .get_context("2d")
+            .unwrap()
+            .unwrap()
+            .dyn_into::<CanvasRenderingContext2d>()
+            .unwrap();
+        Self {
+            context,
+            canvas,
+            blender: FrameBlender::default(),
+            palette: Palette::default(),
+        }
+    }
+}
+
+impl Display for Canvas2dDisplay {
+    fn initialize(&mut self) {
+        // the browser does the scaling, keep chip8 pixels crisp squares while it does
+        self.canvas
+            .style()
+            .set_property("image-rendering", "pixelated")
+            .unwrap();
+    }
+
+    fn present(&mut self, framebuffer: &Framebuffer) {
+        let width = framebuffer.get_width() as u32;
+        let height = framebuffer.get_height() as u32;
+        if self.canvas.width() != width || self.canvas.height() != height {
+            self.canvas.set_width(width);
+            self.canvas.set_height(height);
+        }
+
+        self.blender.push(framebuffer);
+        let rgba = self.blender.blend_rgba(&self.palette);
+        let image_data =
+            ImageData::new_with_u8_clamped_array_and_sh(Clamped(&rgba), width, height).unwrap();
+        self.context.put_image_data(&image_data, 0.0, 0.0).unwrap();
+    }
+
+    fn set_palette(&mut self, palette: &Palette) {
+        self.palette = *palette;
+    }
+
+    fn set_blend_mode(&mut self, mode: BlendMode) {
+        self.blender.set_mode(mode);
+    }
+}
@@ -0,0 +1,58 @@
+/// Tunables for the optional CRT look. Every effect is off at 0.0.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct CrtSettings {
+    /// How much of a switched off pixel is still glowing one frame later, 0.0 to 0.95.
+    /// Besides looking like phosphor this hides most of the flicker from XOR drawing.
+    pub persistence: f32,
+    /// How dark the gaps between rows of pixels get, 0.0 to 1.0
+    pub scanlines: f32,
+    /// How much light bleeds into neighbouring pixels, 0.0 to 1.0
+    pub bloom: f32,
+    /// Barrel distortion of the picture, 0.0 (flat) to 0.5
+    pub curvature: f32,
+}
+
+impl CrtSettings {
+    /// A reasonable looking set of everything turned on
+    pub fn crt() -> Self {
+        Self {
+            persistence: 0.6,
+            scanlines: 0.4,
+            bloom: 0.3,
+            curvature: 0.08,
+        }
+    }
+
+    /// Keeps every parameter within the range the shaders can cope with. Persistence
+    /// stops short of 1.0 so pixels do eventually fade out.
+    pub fn clamped(self) -> Self {
+        Self {
+            persistence: self.persistence.clamp(0.0, 0.95),
+            scanlines: self.scanlines.clamp(0.0, 1.0),
+            bloom: self.bloom.clamp(0.0, 1.0),
+            curvature: self.curvature.clamp(0.0, 0.5),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn clamped() {
+        let settings = CrtSettings {
+            persistence: 1.0,
+            scanlines: -0.5,
+            bloom: 0.3,
+            curvature: 2.0,
+        }
+        .clamped();
+
+        assert_eq!(settings.persistence, 0.95);
+        assert_eq!(settings.scanlines, 0.0);
+        assert_eq!(settings.bloom, 0.3);
+        assert_eq!(settings.curvature, 0.5);
+        assert_eq!(CrtSettings::crt().clamped(), CrtSettings::crt());
+    }
+}
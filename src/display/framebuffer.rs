@@ -0,0 +1,168 @@
+use crate::display::palette::Palette;
+
+pub const CHIP8_WIDTH: usize = 64;
+pub const CHIP8_HEIGHT: usize = 32;
+
+/// SUPER-CHIP and XO-CHIP's hires mode
+pub const HIRES_WIDTH: usize = 128;
+pub const HIRES_HEIGHT: usize = 64;
+
+/// The emulated screen, one byte per pixel. The CPU draws into this and the `Display`
+/// backends only ever get to see it once a frame.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Framebuffer {
+    width: usize,
+    height: usize,
+    pixels: Vec<u8>,
+}
+
+impl Default for Framebuffer {
+    fn default() -> Self {
+        Self::new(CHIP8_WIDTH, CHIP8_HEIGHT)
+    }
+}
+
+impl Framebuffer {
+    pub fn new(width: usize, height: usize) -> Self {
+        Self {
+            width,
+            height,
+            pixels: vec![0; width * height],
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.pixels.iter_mut().for_each(|p| *p = 0);
+    }
+
+    /// Turns off the `planes` bits of every pixel, the others are left alone
+    pub fn clear_planes(&mut self, planes: u8) {
+        self.pixels.iter_mut().for_each(|p| *p &= !planes);
+    }
+
+    pub fn get_width(&self) -> usize {
+        self.width
+    }
+
+    pub fn get_height(&self) -> usize {
+        self.height
+    }
+
+    pub fn get_pixel(&self, x: usize, y: usize) -> bool {
+        self.pixels[y * self.width + x] != 0
+    }
+
+    pub fn set_pixel(&mut self, x: usize, y: usize, turn_on: bool) {
+        self.pixels[y * self.width + x] = turn_on as u8;
+    }
+
+    /// Flips one bitplane's bit of the pixel, returning whether it was on
+    pub fn toggle_pixel(&mut self, x: usize, y: usize, plane: u8) -> bool {
+        let pixel = &mut self.pixels[y * self.width + x];
+        let was_on = *pixel & plane != 0;
+        *pixel ^= plane;
+        was_on
+    }
+
+    /// Moves the `planes` bits of every pixel `dx` pixels right and `dy` down, whatever
+    /// goes off one edge is lost and comes in blank from the other
+    pub fn scroll(&mut self, dx: isize, dy: isize, planes: u8) {
+        let previous = self.pixels.clone();
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let from_x = x as isize - dx;
+                let from_y = y as isize - dy;
+                let moved = if (0..self.width as isize).contains(&from_x)
+                    && (0..self.height as isize).contains(&from_y)
+                {
+                    previous[from_y as usize * self.width + from_x as usize] & planes
+                } else {
+                    0
+                };
+                let pixel = &mut self.pixels[y * self.width + x];
+                *pixel = *pixel & !planes | moved;
+            }
+        }
+    }
+
+    /// Row major pixels, 0 for off
+    pub fn pixels(&self) -> &[u8] {
+        &self.pixels
+    }
+
+    pub fn pixels_mut(&mut self) -> &mut [u8] {
+        &mut self.pixels
+    }
+
+    /// The pixels in `palette`'s colours as RGBA bytes, row by row
+    pub fn to_rgba(&self, palette: &Palette) -> Vec<u8> {
+        self.pixels
+            .iter()
+            .flat_map(|&pixel| {
+                let color = palette.color(pixel);
+                [color.r, color.g, color.b, 0xff]
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn set_pixel() {
+        let mut framebuffer = Framebuffer::default();
+
+        framebuffer.set_pixel(3, 1, true);
+        assert!(framebuffer.get_pixel(3, 1));
+        assert_eq!(framebuffer.pixels()[CHIP8_WIDTH + 3], 1);
+
+        framebuffer.set_pixel(3, 1, false);
+        assert!(!framebuffer.get_pixel(3, 1));
+    }
+
+    #[test]
+    fn clear() {
+        let mut framebuffer = Framebuffer::default();
+
+        framebuffer.set_pixel(0, 0, true);
+        framebuffer.set_pixel(63, 31, true);
+        framebuffer.clear();
+        assert!(framebuffer.pixels().iter().all(|&p| p == 0));
+    }
+
+    #[test]
+    fn planes() {
+        let mut framebuffer = Framebuffer::default();
+
+        assert!(!framebuffer.toggle_pixel(1, 1, 2));
+        assert!(!framebuffer.toggle_pixel(1, 1, 1));
+        assert_eq!(framebuffer.pixels()[CHIP8_WIDTH + 1], 3);
+        assert!(framebuffer.toggle_pixel(1, 1, 2));
+        assert_eq!(framebuffer.pixels()[CHIP8_WIDTH + 1], 1);
+
+        framebuffer.toggle_pixel(2, 1, 2);
+        framebuffer.clear_planes(1);
+        assert!(!framebuffer.get_pixel(1, 1));
+        assert!(framebuffer.get_pixel(2, 1));
+    }
+
+    #[test]
+    fn scroll() {
+        let mut framebuffer = Framebuffer::default();
+        framebuffer.set_pixel(0, 0, true);
+        framebuffer.toggle_pixel(63, 31, 2);
+
+        framebuffer.scroll(4, 2, 1);
+        assert!(framebuffer.get_pixel(4, 2));
+        assert!(!framebuffer.get_pixel(0, 0));
+        // the other plane stays put
+        assert_eq!(framebuffer.pixels()[31 * CHIP8_WIDTH + 63], 2);
+
+        // and off the edge is gone
+        framebuffer.scroll(-4, -3, 3);
+        assert_eq!(framebuffer.pixels().iter().filter(|&&p| p != 0).count(), 1);
+        assert_eq!(framebuffer.pixels()[28 * CHIP8_WIDTH + 59], 2);
+    }
+}
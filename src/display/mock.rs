@@ -1,39 +1,33 @@
+use crate::display::blend::{BlendMode, FrameBlender};
+use crate::display::framebuffer::Framebuffer;
+use crate::display::palette::Palette;
 use crate::Display;
 
+#[derive(Default)]
 pub struct MockDisplay {
-    pub vram: [u8; 64 * 32],
-}
-
-impl Default for MockDisplay {
-    fn default() -> Self {
-        Self { vram: [0; 64 * 32] }
-    }
+    pub vram: Vec<u8>,
+    /// What a framebuffer based backend would put on screen, RGBA8 in the palette colours
+    pub rgba: Vec<u8>,
+    pub frames_presented: usize,
+    pub palette: Palette,
+    blender: FrameBlender,
 }
 
 impl Display for MockDisplay {
-    fn clear(&mut self) {
-        self.vram = [0; 64 * 32];
-    }
-
-    fn get_width(&self) -> usize {
-        64
-    }
+    fn initialize(&mut self) {}
 
-    fn get_height(&self) -> usize {
-        32
+    fn present(&mut self, framebuffer: &Framebuffer) {
+        self.blender.push(framebuffer);
+        self.vram = self.blender.blend().pixels().to_vec();
+        self.rgba = self.blender.blend_rgba(&self.palette);
+        self.frames_presented += 1;
     }
 
-    fn draw_pixel(&mut self, x: usize, y: usize, _pixel_size: f32, turn_on: bool) {
-        if turn_on {
-            self.vram[y * self.get_width() + x] = 1;
-        } else {
-            self.vram[y * self.get_width() + x] = 0;
-        }
+    fn set_palette(&mut self, palette: &Palette) {
+        self.palette = *palette;
     }
 
-    fn get_pixel(&self, x: usize, y: usize) -> bool {
-        self.vram[y * self.get_width() + x] == 1
+    fn set_blend_mode(&mut self, mode: BlendMode) {
+        self.blender.set_mode(mode);
     }
-
-    fn initialize(&mut self) {}
 }
@@ -1,11 +1,58 @@
+pub mod blend;
+pub mod canvas;
+pub mod crt;
+pub mod framebuffer;
 pub mod mock;
+pub mod palette;
+pub mod recorder;
+pub mod screenshot;
 pub mod webgl;
 
-pub trait Display {
-    fn clear(&mut self);
-    fn get_width(&self) -> usize;
-    fn get_height(&self) -> usize;
-    fn draw_pixel(&mut self, x: usize, y: usize, pixel_size: f32, turn_on: bool);
-    fn get_pixel(&self, x: usize, y: usize) -> bool;
+use crate::display::blend::BlendMode;
+use crate::display::canvas::Canvas2dDisplay;
+use crate::display::crt::CrtSettings;
+use crate::display::framebuffer::Framebuffer;
+use crate::display::palette::Palette;
+use crate::display::webgl::WebGLDisplay;
+use std::any::Any;
+use wasm_bindgen::JsCast;
+
+/// Gets at the backend behind a `Box<dyn Display>`, e.g. the mock's vram in tests. Call it
+/// on the display itself (`bus.display.as_ref().as_any()`) rather than on the box.
+pub trait AsAny {
+    fn as_any(&self) -> &dyn Any;
+}
+
+impl<T: Any> AsAny for T {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+pub trait Display: AsAny {
     fn initialize(&mut self);
+    /// Shows the finished frame, called once per frame rather than per pixel
+    fn present(&mut self, framebuffer: &Framebuffer);
+    fn set_palette(&mut self, palette: &Palette);
+    /// Backends without a shader pipeline just ignore the CRT effects
+    fn set_crt_settings(&mut self, _settings: &CrtSettings) {}
+    /// Anti-flicker blending of the last few presented frames before they're shown,
+    /// backends that can't blend just show the newest frame
+    fn set_blend_mode(&mut self, _mode: BlendMode) {}
+}
+
+/// WebGL2 where the browser supports it, a plain 2D canvas everywhere else
+pub fn browser_display() -> Box<dyn Display> {
+    let document = web_sys::window().unwrap().document().unwrap();
+    let canvas = document
+        .query_selector("#glCanvas")
+        .unwrap()
+        .unwrap()
+        .dyn_into::<web_sys::HtmlCanvasElement>()
+        .unwrap();
+
+    match WebGLDisplay::new(canvas.clone()) {
+        Some(display) => Box::new(display),
+        None => Box::new(Canvas2dDisplay::new(canvas)),
+    }
 }
@@ -0,0 +1,171 @@
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Color {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+}
+
+impl Color {
+    pub const fn rgb(r: u8, g: u8, b: u8) -> Self {
+        Self { r, g, b }
+    }
+
+    /// Parses `#rrggbb`, the leading `#` is optional
+    pub fn from_hex(hex: &str) -> Option<Self> {
+        let hex = hex.trim().trim_start_matches('#');
+        // from_str_radix would also take a sign, `#+fffff` isn't a colour
+        if hex.len() != 6 || !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return None;
+        }
+        let value = u32::from_str_radix(hex, 16).ok()?;
+        Some(Self::rgb(
+            (value >> 16) as u8,
+            (value >> 8) as u8,
+            value as u8,
+        ))
+    }
+
+    pub fn to_f32(self) -> [f32; 3] {
+        [
+            self.r as f32 / 255.0,
+            self.g as f32 / 255.0,
+            self.b as f32 / 255.0,
+        ]
+    }
+}
+
+/// One colour for each value a framebuffer pixel can take. Plain CHIP-8 only ever uses
+/// the first two, XO-CHIP's two bitplanes use all four: off, plane 1, plane 2 and both.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Palette {
+    pub colors: [Color; 4],
+}
+
+pub const PALETTE_NAMES: [&str; 6] = [
+    "tostiloco",
+    "green-phosphor",
+    "amber",
+    "lcd",
+    "octo",
+    "high-contrast",
+];
+
+impl Default for Palette {
+    fn default() -> Self {
+        Self::preset("tostiloco").unwrap()
+    }
+}
+
+impl Palette {
+    pub fn new(colors: [Color; 4]) -> Self {
+        Self { colors }
+    }
+
+    pub fn preset(name: &str) -> Option<Self> {
+        let colors = match name {
+            "tostiloco" => [
+                Color::rgb(0x00, 0x00, 0x00),
+                Color::rgb(0x80, 0x1a, 0x4d),
+                Color::rgb(0x1a, 0x4d, 0x80),
+                Color::rgb(0xe0, 0xa0, 0xc0),
+            ],
+            "green-phosphor" => [
+                Color::rgb(0x0a, 0x14, 0x0a),
+                Color::rgb(0x33, 0xff, 0x66),
+                Color::rgb(0x1a, 0x80, 0x33),
+                Color::rgb(0xb3, 0xff, 0xc6),
+            ],
+            "amber" => [
+                Color::rgb(0x14, 0x0c, 0x00),
+                Color::rgb(0xff, 0xb0, 0x00),
+                Color::rgb(0x80, 0x58, 0x00),
+                Color::rgb(0xff, 0xe0, 0x99),
+            ],
+            "lcd" => [
+                Color::rgb(0xc4, 0xcf, 0xa1),
+                Color::rgb(0x38, 0x3e, 0x2c),
+                Color::rgb(0x8b, 0x95, 0x6d),
+                Color::rgb(0x1a, 0x1e, 0x14),
+            ],
+            "octo" => [
+                Color::rgb(0x99, 0x66, 0x00),
+                Color::rgb(0xff, 0xcc, 0x00),
+                Color::rgb(0xff, 0x66, 0x00),
+                Color::rgb(0x66, 0x22, 0x00),
+            ],
+            "high-contrast" => [
+                Color::rgb(0x00, 0x00, 0x00),
+                Color::rgb(0xff, 0xff, 0xff),
+                Color::rgb(0xff, 0xff, 0x00),
+                Color::rgb(0x00, 0xff, 0xff),
+            ],
+            _ => return None,
+        };
+        Some(Self::new(colors))
+    }
+
+    /// Which of `PALETTE_NAMES` this is, `None` for custom colours
+    pub fn preset_name(&self) -> Option<&'static str> {
+        PALETTE_NAMES
+            .into_iter()
+            .find(|&name| Self::preset(name).as_ref() == Some(self))
+    }
+
+    /// Builds a palette from up to four `#rrggbb` colours, anything left out comes from
+    /// the default palette
+    pub fn from_hex_colors(hex_colors: &[&str]) -> Option<Self> {
+        if hex_colors.len() > 4 {
+            return None;
+        }
+        let mut palette = Self::default();
+        for (index, hex) in hex_colors.iter().enumerate() {
+            palette.colors[index] = Color::from_hex(hex)?;
+        }
+        Some(palette)
+    }
+
+    pub fn color(&self, pixel: u8) -> Color {
+        self.colors[(pixel & 0x3) as usize]
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn from_hex() {
+        assert_eq!(
+            Color::from_hex("#ffcc00"),
+            Some(Color::rgb(0xff, 0xcc, 0x00))
+        );
+        assert_eq!(
+            Color::from_hex("12AB9f"),
+            Some(Color::rgb(0x12, 0xab, 0x9f))
+        );
+        assert_eq!(Color::from_hex("#fc0"), None);
+        assert_eq!(Color::from_hex("#gggggg"), None);
+        assert_eq!(Color::from_hex("#+fffff"), None);
+    }
+
+    #[test]
+    fn preset() {
+        for name in PALETTE_NAMES {
+            assert!(Palette::preset(name).is_some(), "missing preset {}", name);
+        }
+        assert_eq!(Palette::preset("sepia"), None);
+        assert_eq!(Palette::default().preset_name(), Some("tostiloco"));
+        let custom = Palette::from_hex_colors(&["#000000", "#ff0000"]).unwrap();
+        assert_eq!(custom.preset_name(), None);
+    }
+
+    #[test]
+    fn from_hex_colors() {
+        let palette = Palette::from_hex_colors(&["#000000", "#ffffff"]).unwrap();
+        assert_eq!(palette.colors[0], Color::rgb(0, 0, 0));
+        assert_eq!(palette.colors[1], Color::rgb(0xff, 0xff, 0xff));
+        assert_eq!(palette.colors[2], Palette::default().colors[2]);
+
+        assert_eq!(Palette::from_hex_colors(&["#000000", "nope"]), None);
+    }
+}
@@ -0,0 +1,271 @@
+use crate::display::framebuffer::{Framebuffer, CHIP8_HEIGHT, CHIP8_WIDTH};
+use crate::display::palette::Palette;
+use std::fmt;
+
+// gif frame delays are in hundredths of a second
+const CENTISECONDS_PER_FRAME: f64 = 100.0 / 60.0;
+
+/// Why the recording couldn't be encoded
+#[derive(Clone, Debug, PartialEq)]
+pub enum RecordingError {
+    /// Scaled up it's more than the 65535 pixels a side a GIF can be
+    TooLarge { width: usize, height: usize },
+}
+
+impl fmt::Display for RecordingError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RecordingError::TooLarge { width, height } => write!(
+                f,
+                "A {}x{} recording is too large for a GIF, try a smaller scale",
+                width, height
+            ),
+        }
+    }
+}
+
+impl std::error::Error for RecordingError {}
+
+struct RecordedFrame {
+    framebuffer: Framebuffer,
+    // how many presents in a row showed this frame
+    repeats: u32,
+}
+
+/// Collects the presented frames between `start` and `stop` for an animated GIF. A frame
+/// identical to the one before it is only stored once and shown for longer instead.
+#[derive(Default)]
+pub struct GifRecorder {
+    recording: bool,
+    frames: Vec<RecordedFrame>,
+}
+
+impl GifRecorder {
+    pub fn start(&mut self) {
+        self.frames.clear();
+        self.recording = true;
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.recording
+    }
+
+    pub fn record(&mut self, framebuffer: &Framebuffer) {
+        if !self.recording {
+            return;
+        }
+        match self.frames.last_mut() {
+            Some(last) if last.framebuffer == *framebuffer => last.repeats += 1,
+            _ => self.frames.push(RecordedFrame {
+                framebuffer: framebuffer.clone(),
+                repeats: 1,
+            }),
+        }
+    }
+
+    /// Number of distinct frames recorded so far
+    pub fn frame_count(&self) -> usize {
+        self.frames.len()
+    }
+
+    /// Stops recording and encodes everything recorded as a looping GIF in `palette`'s
+    /// colours, every chip8 pixel drawn `scale` pixels square. Fails without stopping if
+    /// that's too large for a GIF, a smaller `scale` might still fit.
+    pub fn stop(&mut self, palette: &Palette, scale: usize) -> Result<Vec<u8>, RecordingError> {
+        let gif_bytes = self.encode(palette, scale)?;
+        self.recording = false;
+        self.frames.clear();
+        Ok(gif_bytes)
+    }
+
+    fn encode(&self, palette: &Palette, scale: usize) -> Result<Vec<u8>, RecordingError> {
+        let scale = scale.max(1);
+        // lores frames recorded alongside hires ones are blown up to the hires size
+        let width = self
+            .frames
+            .iter()
+            .map(|f| f.framebuffer.get_width())
+            .max()
+            .unwrap_or(CHIP8_WIDTH);
+        let height = self
+            .frames
+            .iter()
+            .map(|f| f.framebuffer.get_height())
+            .max()
+            .unwrap_or(CHIP8_HEIGHT);
+        let too_large = || RecordingError::TooLarge {
+            width: width.saturating_mul(scale),
+            height: height.saturating_mul(scale),
+        };
+        let gif_side = |side: usize| {
+            side.checked_mul(scale)
+                .and_then(|side| u16::try_from(side).ok())
+                .ok_or_else(too_large)
+        };
+        let (gif_width, gif_height) = (gif_side(width)?, gif_side(height)?);
+
+        let global_palette: Vec<u8> = palette
+            .colors
+            .iter()
+            .flat_map(|c| [c.r, c.g, c.b])
+            .collect();
+
+        let mut gif_bytes = Vec::new();
+        {
+            let mut encoder =
+                gif::Encoder::new(&mut gif_bytes, gif_width, gif_height, &global_palette).unwrap();
+            encoder.set_repeat(gif::Repeat::Infinite).unwrap();
+
+            // delays are rounded off the running total so they don't drift over time
+            let mut presents: u64 = 0;
+            for recorded in &self.frames {
+                let start = (presents as f64 * CENTISECONDS_PER_FRAME).round() as u64;
+                presents += recorded.repeats as u64;
+                let end = (presents as f64 * CENTISECONDS_PER_FRAME).round() as u64;
+
+                let frame_scale = scale * width / recorded.framebuffer.get_width();
+                let pixels = scale_pixels(&recorded.framebuffer, frame_scale);
+                let mut frame =
+                    gif::Frame::from_indexed_pixels(gif_width, gif_height, pixels, None);
+                // a frame still showing after ten minutes is cut short there
+                frame.delay = u16::try_from(end - start).unwrap_or(u16::MAX);
+                encoder.write_frame(&frame).unwrap();
+            }
+        }
+        Ok(gif_bytes)
+    }
+}
+
+/// The framebuffer's palette indexes with every pixel repeated `scale` times each way
+fn scale_pixels(framebuffer: &Framebuffer, scale: usize) -> Vec<u8> {
+    let mut scaled = Vec::with_capacity(framebuffer.pixels().len() * scale * scale);
+    for row in framebuffer.pixels().chunks(framebuffer.get_width()) {
+        let scaled_row: Vec<u8> = row
+            .iter()
+            .flat_map(|&pixel| std::iter::repeat_n(pixel & 3, scale))
+            .collect();
+        for _ in 0..scale {
+            scaled.extend_from_slice(&scaled_row);
+        }
+    }
+    scaled
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn decode(gif_bytes: &[u8]) -> Vec<(u16, u16, u16, Vec<u8>)> {
+        let mut options = gif::DecodeOptions::new();
+        options.set_color_output(gif::ColorOutput::Indexed);
+        let mut decoder = options.read_info(gif_bytes).unwrap();
+        let mut frames = Vec::new();
+        while let Some(frame) = decoder.read_next_frame().unwrap() {
+            frames.push((
+                frame.width,
+                frame.height,
+                frame.delay,
+                frame.buffer.to_vec(),
+            ));
+        }
+        frames
+    }
+
+    #[test]
+    fn record_deduplicates_frames() {
+        let mut recorder = GifRecorder::default();
+        let mut framebuffer = Framebuffer::default();
+
+        // nothing is kept until recording starts
+        recorder.record(&framebuffer);
+        assert_eq!(recorder.frame_count(), 0);
+
+        recorder.start();
+        recorder.record(&framebuffer);
+        recorder.record(&framebuffer);
+        framebuffer.set_pixel(3, 2, true);
+        recorder.record(&framebuffer);
+        recorder.record(&framebuffer);
+        recorder.record(&framebuffer);
+        assert_eq!(recorder.frame_count(), 2);
+
+        let frames = decode(&recorder.stop(&Palette::default(), 1).unwrap());
+        assert!(!recorder.is_recording());
+        assert_eq!(frames.len(), 2);
+        // two and then three 60hz frames
+        assert_eq!(frames[0].2, 3);
+        assert_eq!(frames[1].2, 5);
+        assert_eq!(frames[0].3.iter().filter(|&&p| p != 0).count(), 0);
+        assert_eq!(frames[1].3[2 * 64 + 3], 1);
+    }
+
+    #[test]
+    fn record_scaled_and_mixed_resolutions() {
+        let mut recorder = GifRecorder::default();
+        let mut lores = Framebuffer::default();
+        lores.set_pixel(1, 0, true);
+        let hires = Framebuffer::new(128, 64);
+
+        recorder.start();
+        recorder.record(&lores);
+        recorder.record(&hires);
+        let frames = decode(&recorder.stop(&Palette::default(), 2).unwrap());
+
+        assert_eq!(frames.len(), 2);
+        for (width, height, _, _) in &frames {
+            assert_eq!((*width, *height), (256, 128));
+        }
+        // the lores pixel is 4 gif pixels wide
+        let row = &frames[0].3[0..256];
+        assert_eq!(&row[0..8], &[0, 0, 0, 0, 1, 1, 1, 1]);
+    }
+
+    #[test]
+    fn stop_refuses_a_scale_too_large_for_a_gif() {
+        let mut recorder = GifRecorder::default();
+        recorder.start();
+        recorder.record(&Framebuffer::new(128, 64));
+
+        assert_eq!(
+            recorder.stop(&Palette::default(), 1024),
+            Err(RecordingError::TooLarge {
+                width: 131072,
+                height: 65536
+            })
+        );
+        assert_eq!(
+            recorder.stop(&Palette::default(), usize::MAX).err(),
+            Some(RecordingError::TooLarge {
+                width: usize::MAX,
+                height: usize::MAX
+            })
+        );
+        // still recording, so a smaller scale gets the frames
+        assert!(recorder.is_recording());
+        let frames = decode(&recorder.stop(&Palette::default(), 2).unwrap());
+        assert_eq!((frames[0].0, frames[0].1), (256, 128));
+    }
+
+    #[test]
+    fn long_recordings_keep_their_delays() {
+        let mut recorder = GifRecorder::default();
+        let mut framebuffer = Framebuffer::default();
+        recorder.start();
+        // over 11 minutes of one frame, then a frame with a pixel that changes every
+        // other present
+        for _ in 0..40_000 {
+            recorder.record(&framebuffer);
+        }
+        for present in 0..4 {
+            framebuffer.set_pixel(0, 0, present % 2 == 0);
+            recorder.record(&framebuffer);
+        }
+
+        let frames = decode(&recorder.stop(&Palette::default(), 1).unwrap());
+        assert_eq!(frames.len(), 5);
+        assert_eq!(frames[0].2, u16::MAX);
+        for frame in &frames[1..] {
+            assert!((1..=2).contains(&frame.2));
+        }
+    }
+}
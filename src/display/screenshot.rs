@@ -0,0 +1,155 @@
+use crate::display::framebuffer::Framebuffer;
+use crate::display::palette::Palette;
+use std::fmt;
+
+// 64MB of RGBA, a hires screen at scale 32
+const MAX_PIXELS: usize = 4096 * 4096;
+
+/// Why the screenshot couldn't be encoded
+#[derive(Clone, Debug, PartialEq)]
+pub enum ScreenshotError {
+    /// Scaled up it's more pixels than a screenshot is allowed
+    TooLarge { width: usize, height: usize },
+    /// The PNG encoder gave up
+    Png(String),
+}
+
+impl fmt::Display for ScreenshotError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ScreenshotError::TooLarge { width, height } => write!(
+                f,
+                "A {}x{} screenshot is too large, try a smaller scale",
+                width, height
+            ),
+            ScreenshotError::Png(e) => write!(f, "Couldn't encode the screenshot: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for ScreenshotError {}
+
+/// Encodes the framebuffer as a PNG in `palette`'s colours, every chip8 pixel drawn as a
+/// `scale` by `scale` square
+pub fn encode_png(
+    framebuffer: &Framebuffer,
+    palette: &Palette,
+    scale: usize,
+) -> Result<Vec<u8>, ScreenshotError> {
+    let scale = scale.max(1);
+    let too_large = || ScreenshotError::TooLarge {
+        width: framebuffer.get_width().saturating_mul(scale),
+        height: framebuffer.get_height().saturating_mul(scale),
+    };
+    let width = framebuffer
+        .get_width()
+        .checked_mul(scale)
+        .ok_or_else(too_large)?;
+    let height = framebuffer
+        .get_height()
+        .checked_mul(scale)
+        .ok_or_else(too_large)?;
+    let pixels = width.checked_mul(height).ok_or_else(too_large)?;
+    if pixels > MAX_PIXELS {
+        return Err(too_large());
+    }
+    let png_width = u32::try_from(width).map_err(|_| too_large())?;
+    let png_height = u32::try_from(height).map_err(|_| too_large())?;
+
+    let rgba = framebuffer.to_rgba(palette);
+    let mut scaled = Vec::with_capacity(pixels * 4);
+    for row in rgba.chunks(framebuffer.get_width() * 4) {
+        let scaled_row: Vec<u8> = row
+            .chunks(4)
+            .flat_map(|pixel| pixel.repeat(scale))
+            .collect();
+        for _ in 0..scale {
+            scaled.extend_from_slice(&scaled_row);
+        }
+    }
+
+    let png_error = |e: png::EncodingError| ScreenshotError::Png(e.to_string());
+    let mut png_bytes = Vec::new();
+    let mut encoder = png::Encoder::new(&mut png_bytes, png_width, png_height);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header().map_err(png_error)?;
+    writer.write_image_data(&scaled).map_err(png_error)?;
+    writer.finish().map_err(png_error)?;
+
+    Ok(png_bytes)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn decode(png_bytes: &[u8]) -> (png::OutputInfo, Vec<u8>) {
+        let mut reader = png::Decoder::new(png_bytes).read_info().unwrap();
+        let mut pixels = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut pixels).unwrap();
+        (info, pixels)
+    }
+
+    fn rgba(palette: &Palette, pixel: u8) -> [u8; 4] {
+        let color = palette.color(pixel);
+        [color.r, color.g, color.b, 0xff]
+    }
+
+    #[test]
+    fn encode_png_native() {
+        let palette = Palette::preset("high-contrast").unwrap();
+        let mut framebuffer = Framebuffer::default();
+        framebuffer.set_pixel(1, 0, true);
+
+        let (info, pixels) = decode(&encode_png(&framebuffer, &palette, 1).unwrap());
+        assert_eq!((info.width, info.height), (64, 32));
+        assert_eq!(&pixels[0..4], &rgba(&palette, 0));
+        assert_eq!(&pixels[4..8], &rgba(&palette, 1));
+    }
+
+    #[test]
+    fn encode_png_scaled() {
+        let palette = Palette::default();
+        let mut framebuffer = Framebuffer::new(128, 64);
+        framebuffer.set_pixel(1, 1, true);
+
+        let (info, pixels) = decode(&encode_png(&framebuffer, &palette, 3).unwrap());
+        assert_eq!((info.width, info.height), (384, 192));
+        let lit = rgba(&palette, 1);
+        let row_bytes = 384 * 4;
+        for y in 0..6 {
+            for x in 0..6 {
+                let offset = y * row_bytes + x * 4;
+                let expected = if (3..6).contains(&x) && (3..6).contains(&y) {
+                    lit
+                } else {
+                    rgba(&palette, 0)
+                };
+                assert_eq!(&pixels[offset..offset + 4], &expected);
+            }
+        }
+    }
+
+    #[test]
+    fn encode_png_too_large() {
+        let framebuffer = Framebuffer::new(128, 64);
+
+        assert_eq!(
+            encode_png(&framebuffer, &Palette::default(), 2000),
+            Err(ScreenshotError::TooLarge {
+                width: 256000,
+                height: 128000
+            })
+        );
+        assert_eq!(
+            encode_png(&framebuffer, &Palette::default(), usize::MAX),
+            Err(ScreenshotError::TooLarge {
+                width: usize::MAX,
+                height: usize::MAX
+            })
+        );
+        // the largest scale that fits
+        assert!(encode_png(&framebuffer, &Palette::default(), 32).is_ok());
+    }
+}
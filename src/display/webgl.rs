@@ -1,128 +1,202 @@
+use crate::display::blend::{BlendMode, FrameBlender};
+use crate::display::crt::CrtSettings;
+use crate::display::framebuffer::Framebuffer;
+use crate::display::palette::Palette;
 use crate::display::Display;
 use wasm_bindgen::JsCast;
 use web_sys::{
-    HtmlCanvasElement, WebGl2RenderingContext, WebGlProgram, WebGlShader, WebGlUniformLocation,
+    HtmlCanvasElement, WebGl2RenderingContext, WebGlFramebuffer, WebGlProgram, WebGlShader,
+    WebGlTexture,
 };
 
-const CHIP8_WIDTH: usize = 64;
-const CHIP8_HEIGHT: usize = 32;
+// both programs draw the same fullscreen quad, pin its attribute to one location
+const POSITION_ATTRIBUTE_LOCATION: u32 = 0;
 
-//const SUPER_CHIP8_WIDTH: u8 = 128;
-//const SUPER_CHIP8_HEIGHT: u8 = 64;
+const QUAD_VERTEX_SHADER: &str = r#"#version 300 es
+
+            layout(location = 0) in vec2 a_position;
+            uniform bool u_flip_y;
+            out vec2 v_texcoord;
+
+            void main() {
+               // clip space to texture space, the uploaded framebuffer has its first
+               // row at the top rather than the bottom
+               v_texcoord = (a_position + 1.0) * 0.5;
+               if (u_flip_y) {
+                   v_texcoord.y = 1.0 - v_texcoord.y;
+               }
+
+               gl_Position = vec4(a_position, 0, 1);
+            }
+            "#;
+
+// maps framebuffer pixels to colours, blends the last few frames together against
+// flicker and leaves switched off pixels glowing for a while
+const SCREEN_FRAGMENT_SHADER: &str = r#"#version 300 es
+
+            precision highp float;
+            // the last few framebuffers, newest first
+            uniform highp sampler2DArray u_frames;
+            uniform int u_frame_count;
+            // 0 newest frame only, 1 or, 2 average, 3 keep one frame
+            uniform int u_blend_mode;
+            uniform sampler2D u_previous;
+            // off, plane 1, plane 2, both planes
+            uniform vec3 u_palette[4];
+            uniform float u_persistence;
+
+            in vec2 v_texcoord;
+            out vec4 outColor;
+
+            int pixel_at(int frame) {
+               // the byte values come back normalised to 0.0 - 1.0
+               float value = texture(u_frames, vec3(v_texcoord, float(frame))).r;
+               return int(value * 255.0 + 0.5) & 3;
+            }
+
+            void main() {
+               int pixel = pixel_at(0);
+               vec3 color = u_palette[pixel];
+               if (u_blend_mode == 1) {
+                   for (int frame = 1; frame < u_frame_count; frame++) {
+                       pixel |= pixel_at(frame);
+                   }
+                   color = u_palette[pixel];
+               } else if (u_blend_mode == 2) {
+                   for (int frame = 1; frame < u_frame_count; frame++) {
+                       color += u_palette[pixel_at(frame)];
+                   }
+                   color /= float(u_frame_count);
+               } else if (u_blend_mode == 3 && pixel == 0 && u_frame_count > 1) {
+                   color = u_palette[pixel_at(1)];
+               }
+
+               vec3 background = u_palette[0];
+               vec3 previous = texelFetch(u_previous, ivec2(gl_FragCoord.xy), 0).rgb;
+               vec3 faded = background + (previous - background) * u_persistence;
+
+               outColor = vec4(color == background ? faded : color, 1.0);
+            }
+            "#;
+
+// scanlines, bloom and barrel distortion on the way to the canvas
+const CRT_FRAGMENT_SHADER: &str = r#"#version 300 es
+
+            precision highp float;
+            uniform sampler2D u_image;
+            uniform vec2 u_source_size;
+            uniform float u_scanlines;
+            uniform float u_bloom;
+            uniform float u_curvature;
+
+            in vec2 v_texcoord;
+            out vec4 outColor;
+
+            vec2 barrel(vec2 uv) {
+               vec2 centered = uv * 2.0 - 1.0;
+               centered *= 1.0 + u_curvature * dot(centered, centered);
+               return (centered + 1.0) * 0.5;
+            }
+
+            void main() {
+               vec2 uv = barrel(v_texcoord);
+               if (any(lessThan(uv, vec2(0.0))) || any(greaterThan(uv, vec2(1.0)))) {
+                   outColor = vec4(0.0, 0.0, 0.0, 1.0);
+                   return;
+               }
+               vec3 color = texture(u_image, uv).rgb;
+
+               vec2 texel = 1.0 / u_source_size;
+               vec3 glow = vec3(0.0);
+               for (int dx = -1; dx <= 1; dx++) {
+                   for (int dy = -1; dy <= 1; dy++) {
+                       glow += texture(u_image, uv + vec2(dx, dy) * texel).rgb;
+                   }
+               }
+               color += glow / 9.0 * u_bloom;
+
+               // brightest through the middle of each row of pixels, dark between them
+               float row = fract(uv.y * u_source_size.y);
+               float scanline = 0.5 + 0.5 * cos((row - 0.5) * 6.2831853);
+               color *= mix(1.0, scanline, u_scanlines);
+
+               outColor = vec4(color, 1.0);
+            }
+            "#;
+
+/// An offscreen colour texture the size of the chip8 framebuffer
+struct RenderTarget {
+    texture: WebGlTexture,
+    framebuffer: WebGlFramebuffer,
+}
 
 pub struct WebGLDisplay {
     gl_context: WebGl2RenderingContext,
-    vram: [u8; (CHIP8_HEIGHT * CHIP8_WIDTH)],
-    color_uniform_location: Option<WebGlUniformLocation>,
     canvas: HtmlCanvasElement,
+    screen_program: Option<WebGlProgram>,
+    crt_program: Option<WebGlProgram>,
+    // the framebuffers being blended, a layer per frame and one red channel byte per
+    // pixel
+    screen_texture: Option<WebGlTexture>,
+    blender: FrameBlender,
+    // what was on screen last frame and what's on it this frame, swapped every present
+    render_targets: Vec<RenderTarget>,
+    render_target_size: (usize, usize),
+    palette: Palette,
+    crt_settings: CrtSettings,
 }
 
-impl Default for WebGLDisplay {
-    fn default() -> Self {
-        let document = web_sys::window().unwrap().document().unwrap();
-
-        // prevent webgl from clearing the buffer on each draw automatically so we can
-        // preserve our previous pixels
-        let context_options = js_sys::Object::new();
-        js_sys::Reflect::set(
-            &context_options,
-            &"preserveDrawingBuffer".into(),
-            &wasm_bindgen::JsValue::TRUE,
-        )
-        .unwrap();
-        let canvas = document
-            .query_selector("#glCanvas")
-            .unwrap()
-            .unwrap()
-            .dyn_into::<web_sys::HtmlCanvasElement>()
-            .unwrap();
+impl WebGLDisplay {
+    /// `None` when the browser can't give the canvas a WebGL2 context
+    pub fn new(canvas: HtmlCanvasElement) -> Option<Self> {
         let gl_context = canvas
-            .get_context_with_context_options("webgl2", &context_options)
-            .unwrap()
-            .unwrap()
+            .get_context("webgl2")
+            .ok()
+            .flatten()?
             .dyn_into::<WebGl2RenderingContext>()
-            .unwrap();
-        Self {
+            .ok()?;
+        Some(Self {
             gl_context,
-            vram: [0; (CHIP8_HEIGHT * CHIP8_WIDTH) as usize],
-            color_uniform_location: None,
             canvas,
-        }
+            screen_program: None,
+            crt_program: None,
+            screen_texture: None,
+            blender: FrameBlender::default(),
+            render_targets: Vec::new(),
+            render_target_size: (0, 0),
+            palette: Palette::default(),
+            crt_settings: CrtSettings::default(),
+        })
     }
 }
 
 impl Display for WebGLDisplay {
-    fn clear(&mut self) {
-        self.vram.iter_mut().for_each(|m| *m = 0);
-        self.gl_context.clear_color(0.0, 0.0, 0.0, 1.0);
-        self.gl_context
-            .clear(WebGl2RenderingContext::COLOR_BUFFER_BIT);
-    }
-
-    fn get_width(&self) -> usize {
-        CHIP8_WIDTH
-    }
-
-    fn get_height(&self) -> usize {
-        CHIP8_HEIGHT
-    }
-
-    fn draw_pixel(&mut self, x: usize, y: usize, block_size: f32, turn_on: bool) {
-        // set color
-        if turn_on {
-            self.gl_context
-                .uniform4f(self.color_uniform_location.as_ref(), 0.5, 0.1, 0.3, 1.0);
-            self.vram[y * self.get_width() + x] = 1;
-        } else {
-            self.gl_context
-                .uniform4f(self.color_uniform_location.as_ref(), 0.0, 0.0, 0.0, 1.0);
-            self.vram[y * self.get_width() + x] = 0;
-        }
-
-        let x1 = x as f32 * block_size;
-        let x2 = x1 + block_size;
-        let y1 = y as f32 * block_size;
-        let y2 = y1 + block_size;
-        let buffer_data: [f32; 12] = [x1, y1, x2, y1, x1, y2, x1, y2, x2, y1, x2, y2];
-        unsafe {
-            let positions_array_buffer_view = js_sys::Float32Array::view(&buffer_data);
-            self.gl_context.buffer_data_with_array_buffer_view(
-                WebGl2RenderingContext::ARRAY_BUFFER,
-                &positions_array_buffer_view,
-                WebGl2RenderingContext::STATIC_DRAW,
-            );
-        }
-
-        self.gl_context
-            .draw_arrays(WebGl2RenderingContext::TRIANGLES, 0, 6);
-    }
-
-    fn get_pixel(&self, x: usize, y: usize) -> bool {
-        self.vram[y * self.get_width() + x] == 1
-    }
-
     fn initialize(&mut self) {
-        let program = self.get_program();
-
-        let position_attribute_location =
-            self.gl_context.get_attrib_location(&program, "a_position");
-
-        let resolution_uniform_location = self
-            .gl_context
-            .get_uniform_location(&program, "u_resolution");
-        self.color_uniform_location = self.gl_context.get_uniform_location(&program, "u_color");
+        let screen_program = self.build_program(QUAD_VERTEX_SHADER, SCREEN_FRAGMENT_SHADER);
+        let crt_program = self.build_program(QUAD_VERTEX_SHADER, CRT_FRAGMENT_SHADER);
 
+        // two triangles covering the whole of clip space
+        let quad: [f32; 12] = [
+            -1.0, -1.0, 1.0, -1.0, -1.0, 1.0, -1.0, 1.0, 1.0, -1.0, 1.0, 1.0,
+        ];
         let position_buffer = self.gl_context.create_buffer().unwrap();
-
         let vao = self.gl_context.create_vertex_array();
         self.gl_context.bind_vertex_array(vao.as_ref());
         self.gl_context
-            .enable_vertex_attrib_array(position_attribute_location as u32);
+            .enable_vertex_attrib_array(POSITION_ATTRIBUTE_LOCATION);
         self.gl_context
             .bind_buffer(WebGl2RenderingContext::ARRAY_BUFFER, Some(&position_buffer));
+        unsafe {
+            let positions_array_buffer_view = js_sys::Float32Array::view(&quad);
+            self.gl_context.buffer_data_with_array_buffer_view(
+                WebGl2RenderingContext::ARRAY_BUFFER,
+                &positions_array_buffer_view,
+                WebGl2RenderingContext::STATIC_DRAW,
+            );
+        }
 
         self.gl_context.vertex_attrib_pointer_with_i32(
-            position_attribute_location as u32,
+            POSITION_ATTRIBUTE_LOCATION,
             2,                             // components per iteration
             WebGl2RenderingContext::FLOAT, // data type
             false,                         // data normalization
@@ -130,71 +204,270 @@ impl Display for WebGLDisplay {
             0,                             // offset
         );
 
-        self.gl_context.viewport(
-            0,
-            0,
-            self.canvas.width() as i32,
-            self.canvas.height() as i32,
+        // rows of single byte pixels aren't 4 byte aligned
+        self.gl_context
+            .pixel_storei(WebGl2RenderingContext::UNPACK_ALIGNMENT, 1);
+        self.screen_texture = Some(self.create_texture(WebGl2RenderingContext::TEXTURE_2D_ARRAY));
+
+        // samplers never change texture unit so they only need setting the once
+        self.gl_context.use_program(Some(&screen_program));
+        self.set_uniform_1i(&screen_program, "u_frames", 0);
+        self.set_uniform_1i(&screen_program, "u_previous", 1);
+        self.set_uniform_1i(&screen_program, "u_flip_y", 1);
+        self.gl_context.use_program(Some(&crt_program));
+        self.set_uniform_1i(&crt_program, "u_image", 0);
+        self.set_uniform_1i(&crt_program, "u_flip_y", 0);
+
+        self.screen_program = Some(screen_program);
+        self.crt_program = Some(crt_program);
+    }
+
+    fn present(&mut self, framebuffer: &Framebuffer) {
+        let width = framebuffer.get_width();
+        let height = framebuffer.get_height();
+        if self.render_target_size != (width, height) {
+            self.create_render_targets(width, height);
+        }
+        self.resize_canvas();
+        self.blender.push(framebuffer);
+        let frames: Vec<u8> = self
+            .blender
+            .frames()
+            .flat_map(|frame| frame.pixels().iter().copied())
+            .collect();
+        let frame_count = (frames.len() / framebuffer.pixels().len()) as i32;
+
+        // first pass: the framebuffer with its palette and phosphor trails into the
+        // offscreen target at native resolution
+        let screen_program = self.screen_program.as_ref().unwrap();
+        self.gl_context.use_program(Some(screen_program));
+        self.set_uniform_1f(
+            screen_program,
+            "u_persistence",
+            self.crt_settings.persistence,
         );
-        self.gl_context.clear_color(0.0, 0.0, 0.0, 0.0);
-        self.gl_context.clear(
-            WebGl2RenderingContext::COLOR_BUFFER_BIT | WebGl2RenderingContext::DEPTH_BUFFER_BIT,
+        self.set_uniform_1i(screen_program, "u_frame_count", frame_count);
+        let blend_mode = match self.blender.mode() {
+            BlendMode::Off => 0,
+            BlendMode::Or(_) => 1,
+            BlendMode::Average(_) => 2,
+            BlendMode::KeepOneFrame => 3,
+        };
+        self.set_uniform_1i(screen_program, "u_blend_mode", blend_mode);
+        let colors: Vec<f32> = self
+            .palette
+            .colors
+            .iter()
+            .flat_map(|c| c.to_f32())
+            .collect();
+        self.gl_context.uniform3fv_with_f32_array(
+            self.gl_context
+                .get_uniform_location(screen_program, "u_palette")
+                .as_ref(),
+            &colors,
         );
-        self.gl_context.bind_vertex_array(vao.as_ref());
 
-        // set resolution
+        self.gl_context
+            .active_texture(WebGl2RenderingContext::TEXTURE0);
+        self.gl_context.bind_texture(
+            WebGl2RenderingContext::TEXTURE_2D_ARRAY,
+            self.screen_texture.as_ref(),
+        );
+        self.gl_context
+            .tex_image_3d_with_opt_u8_array(
+                WebGl2RenderingContext::TEXTURE_2D_ARRAY,
+                0,                                     // mip level
+                WebGl2RenderingContext::R8 as i32,     // internal format
+                width as i32,                          // width
+                height as i32,                         // height
+                frame_count,                           // depth
+                0,                                     // border
+                WebGl2RenderingContext::RED,           // source format
+                WebGl2RenderingContext::UNSIGNED_BYTE, // source type
+                Some(&frames),
+            )
+            .unwrap();
+        self.gl_context
+            .active_texture(WebGl2RenderingContext::TEXTURE1);
+        self.gl_context.bind_texture(
+            WebGl2RenderingContext::TEXTURE_2D,
+            Some(&self.render_targets[0].texture),
+        );
+        self.gl_context.bind_framebuffer(
+            WebGl2RenderingContext::FRAMEBUFFER,
+            Some(&self.render_targets[1].framebuffer),
+        );
+        self.gl_context.viewport(0, 0, width as i32, height as i32);
+        self.gl_context
+            .draw_arrays(WebGl2RenderingContext::TRIANGLES, 0, 6);
+
+        // second pass: the crt effects on the way to the canvas
+        let crt_program = self.crt_program.as_ref().unwrap();
+        self.gl_context.use_program(Some(crt_program));
+        self.set_uniform_1f(crt_program, "u_scanlines", self.crt_settings.scanlines);
+        self.set_uniform_1f(crt_program, "u_bloom", self.crt_settings.bloom);
+        self.set_uniform_1f(crt_program, "u_curvature", self.crt_settings.curvature);
         self.gl_context.uniform2f(
-            resolution_uniform_location.as_ref(),
-            self.canvas.width() as f32,
-            self.canvas.height() as f32,
+            self.gl_context
+                .get_uniform_location(crt_program, "u_source_size")
+                .as_ref(),
+            width as f32,
+            height as f32,
+        );
+
+        self.gl_context
+            .bind_framebuffer(WebGl2RenderingContext::FRAMEBUFFER, None);
+        self.gl_context
+            .active_texture(WebGl2RenderingContext::TEXTURE0);
+        self.gl_context.bind_texture(
+            WebGl2RenderingContext::TEXTURE_2D,
+            Some(&self.render_targets[1].texture),
         );
+        self.set_viewport(framebuffer);
+        self.gl_context
+            .draw_arrays(WebGl2RenderingContext::TRIANGLES, 0, 6);
+
+        // this frame is the previous one next time around
+        self.render_targets.swap(0, 1);
     }
-}
 
-impl WebGLDisplay {
-    fn get_program(&self) -> WebGlProgram {
-        let vertex_shader = self.compile_shader(
-            WebGl2RenderingContext::VERTEX_SHADER,
-            r#"#version 300 es
+    fn set_palette(&mut self, palette: &Palette) {
+        self.palette = *palette;
+    }
 
-            in vec2 a_position;
-            uniform vec2 u_resolution;
+    fn set_crt_settings(&mut self, settings: &CrtSettings) {
+        self.crt_settings = settings.clamped();
+    }
 
-            void main() {
-                // convert the rectangle from pixels to 0.0 to 1.0
-               vec2 zeroToOne = a_position / u_resolution;
+    fn set_blend_mode(&mut self, mode: BlendMode) {
+        self.blender.set_mode(mode);
+    }
+}
 
-               // convert from 0->1 to 0->2
-               vec2 zeroToTwo = zeroToOne * 2.0;
+impl WebGLDisplay {
+    /// Matches the drawing buffer to however big the page has made the canvas
+    fn resize_canvas(&self) {
+        let width = self.canvas.client_width() as u32;
+        let height = self.canvas.client_height() as u32;
+        if width > 0
+            && height > 0
+            && (self.canvas.width() != width || self.canvas.height() != height)
+        {
+            self.canvas.set_width(width);
+            self.canvas.set_height(height);
+        }
+    }
 
-               // convert from 0->2 to -1->+1 (clipspace)
-               vec2 clipSpace = zeroToTwo - 1.0;
+    /// Letterboxes the screen into the canvas keeping the framebuffer's aspect ratio
+    fn set_viewport(&self, framebuffer: &Framebuffer) {
+        let canvas_width = self.canvas.width() as f32;
+        let canvas_height = self.canvas.height() as f32;
+        let scale = (canvas_width / framebuffer.get_width() as f32)
+            .min(canvas_height / framebuffer.get_height() as f32);
+        let width = framebuffer.get_width() as f32 * scale;
+        let height = framebuffer.get_height() as f32 * scale;
 
-               gl_Position = vec4(clipSpace * vec2(1, -1), 0, 1);
-            }
-            "#,
+        self.gl_context
+            .viewport(0, 0, canvas_width as i32, canvas_height as i32);
+        let [r, g, b] = self.palette.colors[0].to_f32();
+        self.gl_context.clear_color(r, g, b, 1.0);
+        self.gl_context
+            .clear(WebGl2RenderingContext::COLOR_BUFFER_BIT);
+        self.gl_context.viewport(
+            ((canvas_width - width) / 2.0) as i32,
+            ((canvas_height - height) / 2.0) as i32,
+            width as i32,
+            height as i32,
         );
-        let fragment_shader = self.compile_shader(
-            WebGl2RenderingContext::FRAGMENT_SHADER,
-            r#"#version 300 es
+    }
 
-            precision highp float;
-            uniform vec4 u_color;
+    /// Nearest neighbour so chip8 pixels stay crisp squares at any canvas size
+    fn create_texture(&self, target: u32) -> WebGlTexture {
+        let texture = self.gl_context.create_texture().unwrap();
+        self.gl_context.bind_texture(target, Some(&texture));
+        for (parameter, value) in [
+            (
+                WebGl2RenderingContext::TEXTURE_MIN_FILTER,
+                WebGl2RenderingContext::NEAREST,
+            ),
+            (
+                WebGl2RenderingContext::TEXTURE_MAG_FILTER,
+                WebGl2RenderingContext::NEAREST,
+            ),
+            (
+                WebGl2RenderingContext::TEXTURE_WRAP_S,
+                WebGl2RenderingContext::CLAMP_TO_EDGE,
+            ),
+            (
+                WebGl2RenderingContext::TEXTURE_WRAP_T,
+                WebGl2RenderingContext::CLAMP_TO_EDGE,
+            ),
+        ] {
+            self.gl_context
+                .tex_parameteri(target, parameter, value as i32);
+        }
+        texture
+    }
 
-            out vec4 outColor;
+    fn create_render_targets(&mut self, width: usize, height: usize) {
+        self.render_targets.clear();
+        for _ in 0..2 {
+            let texture = self.create_texture(WebGl2RenderingContext::TEXTURE_2D);
+            self.gl_context
+                .tex_image_2d_with_i32_and_i32_and_i32_and_format_and_type_and_opt_u8_array(
+                    WebGl2RenderingContext::TEXTURE_2D,
+                    0,
+                    WebGl2RenderingContext::RGBA8 as i32,
+                    width as i32,
+                    height as i32,
+                    0,
+                    WebGl2RenderingContext::RGBA,
+                    WebGl2RenderingContext::UNSIGNED_BYTE,
+                    None,
+                )
+                .unwrap();
+            let framebuffer = self.gl_context.create_framebuffer().unwrap();
+            self.gl_context
+                .bind_framebuffer(WebGl2RenderingContext::FRAMEBUFFER, Some(&framebuffer));
+            self.gl_context.framebuffer_texture_2d(
+                WebGl2RenderingContext::FRAMEBUFFER,
+                WebGl2RenderingContext::COLOR_ATTACHMENT0,
+                WebGl2RenderingContext::TEXTURE_2D,
+                Some(&texture),
+                0,
+            );
+            self.render_targets.push(RenderTarget {
+                texture,
+                framebuffer,
+            });
+        }
+        self.gl_context
+            .bind_framebuffer(WebGl2RenderingContext::FRAMEBUFFER, None);
+        self.render_target_size = (width, height);
+    }
 
-            void main() {
-               outColor = u_color;
-            }
-            "#,
+    fn set_uniform_1i(&self, program: &WebGlProgram, name: &str, value: i32) {
+        self.gl_context.uniform1i(
+            self.gl_context.get_uniform_location(program, name).as_ref(),
+            value,
         );
+    }
 
-        let program = self
-            .link_program(&vertex_shader.unwrap(), &fragment_shader.unwrap())
-            .unwrap();
-        self.gl_context.use_program(Some(&program));
+    fn set_uniform_1f(&self, program: &WebGlProgram, name: &str, value: f32) {
+        self.gl_context.uniform1f(
+            self.gl_context.get_uniform_location(program, name).as_ref(),
+            value,
+        );
+    }
 
-        program
+    fn build_program(&self, vertex_source: &str, fragment_source: &str) -> WebGlProgram {
+        let vertex_shader =
+            self.compile_shader(WebGl2RenderingContext::VERTEX_SHADER, vertex_source);
+        let fragment_shader =
+            self.compile_shader(WebGl2RenderingContext::FRAGMENT_SHADER, fragment_source);
+
+        self.link_program(&vertex_shader.unwrap(), &fragment_shader.unwrap())
+            .unwrap()
     }
 
     fn compile_shader(&self, shader_type: u32, source: &str) -> Result<WebGlShader, String> {
@@ -0,0 +1,36 @@
+use crate::flags::{FlagStore, Flags, FLAG_COUNT};
+use web_sys::Storage;
+
+const KEY_PREFIX: &str = "rpl:";
+
+/// Keeps the flags in the page's `localStorage` as hex, under `rpl:` and the rom's hash
+#[derive(Default)]
+pub struct BrowserFlagStore {}
+
+impl BrowserFlagStore {
+    fn storage() -> Option<Storage> {
+        web_sys::window()?.local_storage().ok()?
+    }
+}
+
+impl FlagStore for BrowserFlagStore {
+    fn load(&mut self, key: &str) -> Option<Flags> {
+        let hex = Self::storage()?
+            .get_item(&format!("{}{}", KEY_PREFIX, key))
+            .ok()??;
+        let mut flags = [0; FLAG_COUNT];
+        for (flag, digits) in flags.iter_mut().zip(hex.as_bytes().chunks(2)) {
+            *flag = u8::from_str_radix(std::str::from_utf8(digits).ok()?, 16).ok()?;
+        }
+        Some(flags)
+    }
+
+    fn save(&mut self, key: &str, flags: &Flags) {
+        let hex: String = flags.iter().map(|flag| format!("{:02x}", flag)).collect();
+        let saved = Self::storage()
+            .map(|storage| storage.set_item(&format!("{}{}", KEY_PREFIX, key), &hex));
+        if !matches!(saved, Some(Ok(()))) {
+            log::warn!("Couldn't save the RPL flags");
+        }
+    }
+}
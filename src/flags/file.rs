@@ -0,0 +1,68 @@
+use crate::flags::{FlagStore, Flags, FLAG_COUNT};
+use std::fs;
+use std::path::PathBuf;
+
+/// Keeps each rom's flags in a file named after its hash, `<hash>.rpl` in `dir`
+pub struct FileFlagStore {
+    dir: PathBuf,
+}
+
+impl FileFlagStore {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    fn path(&self, key: &str) -> PathBuf {
+        self.dir.join(format!("{}.rpl", key))
+    }
+}
+
+impl FlagStore for FileFlagStore {
+    fn load(&mut self, key: &str) -> Option<Flags> {
+        let bytes = fs::read(self.path(key)).ok()?;
+        // files saved with fewer flags leave the rest at zero
+        let mut flags = [0; FLAG_COUNT];
+        let count = bytes.len().min(FLAG_COUNT);
+        flags[..count].copy_from_slice(&bytes[..count]);
+        Some(flags)
+    }
+
+    fn save(&mut self, key: &str, flags: &Flags) {
+        let saved = fs::create_dir_all(&self.dir).and_then(|_| fs::write(self.path(key), flags));
+        if let Err(e) = saved {
+            log::warn!(
+                "Couldn't save the RPL flags to {}: {}",
+                self.path(key).display(),
+                e
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::env;
+
+    #[test]
+    fn save_and_load() {
+        let dir = env::temp_dir().join(format!("tostiloco-flags-{}", std::process::id()));
+        let mut store = FileFlagStore::new(&dir);
+        assert_eq!(store.load("abc"), None);
+
+        let mut flags = [0; FLAG_COUNT];
+        flags[0] = 0x12;
+        flags[7] = 0xff;
+        store.save("abc", &flags);
+        assert_eq!(FileFlagStore::new(&dir).load("abc"), Some(flags));
+
+        // an older file with only SUPER-CHIP's 8
+        fs::write(dir.join("short.rpl"), [1, 2, 3, 4, 5, 6, 7, 8]).unwrap();
+        assert_eq!(
+            store.load("short").unwrap()[..9],
+            [1, 2, 3, 4, 5, 6, 7, 8, 0]
+        );
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
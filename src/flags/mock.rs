@@ -0,0 +1,18 @@
+use crate::flags::{FlagStore, Flags};
+use std::collections::HashMap;
+
+/// Keeps the flags in memory, for as long as the emulator runs
+#[derive(Default)]
+pub struct MockFlagStore {
+    pub saved: HashMap<String, Flags>,
+}
+
+impl FlagStore for MockFlagStore {
+    fn load(&mut self, key: &str) -> Option<Flags> {
+        self.saved.get(key).copied()
+    }
+
+    fn save(&mut self, key: &str, flags: &Flags) {
+        self.saved.insert(key.to_string(), *flags);
+    }
+}
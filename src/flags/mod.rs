@@ -0,0 +1,85 @@
+pub mod browser;
+pub mod file;
+pub mod mock;
+
+use crate::display::AsAny;
+
+/// SUPER-CHIP has 8 RPL user flags, XO-CHIP went up to 16
+pub const FLAG_COUNT: usize = 16;
+
+pub type Flags = [u8; FLAG_COUNT];
+
+/// Where the RPL user flags are kept between runs, each rom's under its own key
+pub trait FlagStore: AsAny {
+    fn load(&mut self, key: &str) -> Option<Flags>;
+    fn save(&mut self, key: &str, flags: &Flags);
+}
+
+/// The RPL user flags `Fx75` and `Fx85` save registers to and restore them from, which
+/// games use for their high scores. They're saved for the rom that was loaded last.
+pub struct RplFlags {
+    flags: Flags,
+    // the rom's hash, nothing is saved until a rom has been loaded with `Chip8::load_rom`
+    key: Option<String>,
+    pub store: Box<dyn FlagStore>,
+}
+
+impl RplFlags {
+    pub fn new(store: Box<dyn FlagStore>) -> Self {
+        Self {
+            flags: [0; FLAG_COUNT],
+            key: None,
+            store,
+        }
+    }
+
+    /// Switches to the flags saved under `key`, all zero if there aren't any yet
+    pub fn open(&mut self, key: &str) {
+        self.flags = self.store.load(key).unwrap_or([0; FLAG_COUNT]);
+        self.key = Some(key.to_string());
+    }
+
+    pub fn get(&self) -> &Flags {
+        &self.flags
+    }
+
+    /// Overwrites the flags from the first one on with `values` and saves them all if that
+    /// changed anything
+    pub fn set(&mut self, values: &[u8]) {
+        if self.flags[..values.len()] == *values {
+            return;
+        }
+        self.flags[..values.len()].copy_from_slice(values);
+        if let Some(key) = &self.key {
+            self.store.save(key, &self.flags);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::flags::mock::MockFlagStore;
+
+    #[test]
+    fn set_and_open() {
+        let mut flags = RplFlags::new(Box::new(MockFlagStore::default()));
+
+        // kept but not saved until there's a rom to save them for
+        flags.set(&[1, 2]);
+        assert_eq!(&flags.get()[..3], &[1, 2, 0]);
+        flags.open("rom");
+        assert_eq!(flags.get(), &[0; FLAG_COUNT]);
+
+        flags.set(&[3, 4, 5]);
+        flags.set(&[6]);
+        flags.open("another rom");
+        assert_eq!(flags.get(), &[0; FLAG_COUNT]);
+        flags.open("rom");
+        assert_eq!(&flags.get()[..4], &[6, 4, 5, 0]);
+
+        let store = flags.store.as_ref().as_any();
+        let store = store.downcast_ref::<MockFlagStore>().unwrap();
+        assert_eq!(store.saved.len(), 1);
+    }
+}
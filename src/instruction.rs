@@ -1,3 +1,4 @@
+#[derive(Clone, Copy)]
 pub struct Instruction {
     pub raw_bytes: u16,
     pub first: u8,
@@ -10,7 +11,7 @@ pub struct Instruction {
 
 impl Instruction {
     pub fn new(bytes: u16) -> Instruction {
-        return Instruction {
+        Instruction {
             raw_bytes: bytes,
             first: (bytes >> 12 & 0xf) as u8,
             nnn: bytes & 0xfff,
@@ -18,7 +19,7 @@ impl Instruction {
             x: (bytes >> 8 & 0xf) as usize,
             y: (bytes >> 4 & 0xf) as usize,
             n: (bytes & 0xf) as u8,
-        };
+        }
     }
 }
 
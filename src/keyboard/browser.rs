@@ -1,8 +1,9 @@
-use crate::keyboard::Keyboard;
+use crate::keyboard::{Keyboard, Keymap};
 
 #[derive(Default)]
 pub struct BrowserKeyboard {
     key_pressed: u8,
+    keymap: Keymap,
 }
 
 impl Keyboard for BrowserKeyboard {
@@ -23,6 +24,10 @@ impl Keyboard for BrowserKeyboard {
     }
 
     fn set_key(&mut self, key: u8) {
+        if let Some(chip8_key) = self.keymap.chip8_key(key) {
+            self.key_pressed = chip8_key;
+            return;
+        }
         match key {
             // 1 to 9
             49 => self.key_pressed = 0x1,
@@ -51,4 +56,8 @@ impl Keyboard for BrowserKeyboard {
     fn get_key(&mut self) -> u8 {
         self.key_pressed
     }
+
+    fn set_keymap(&mut self, keymap: &Keymap) {
+        self.keymap = keymap.clone();
+    }
 }
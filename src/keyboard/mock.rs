@@ -1,8 +1,9 @@
-use crate::keyboard::Keyboard;
+use crate::keyboard::{Keyboard, Keymap};
 
 #[derive(Default)]
 pub struct MockKeyboard {
     key_pressed: u8,
+    pub keymap: Keymap,
 }
 
 impl Keyboard for MockKeyboard {
@@ -17,4 +18,8 @@ impl Keyboard for MockKeyboard {
     fn get_key(&mut self) -> u8 {
         self.key_pressed
     }
+
+    fn set_keymap(&mut self, keymap: &Keymap) {
+        self.keymap = keymap.clone();
+    }
 }
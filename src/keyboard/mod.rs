@@ -1,8 +1,27 @@
 pub mod browser;
 pub mod mock;
 
-pub trait Keyboard {
+use crate::display::AsAny;
+
+/// Extra keys bound to a rom's controls on top of 0-9 and A-F, as key codes and the chip8
+/// key each one presses
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Keymap {
+    pub bindings: Vec<(u8, u8)>,
+}
+
+impl Keymap {
+    pub fn chip8_key(&self, key_code: u8) -> Option<u8> {
+        self.bindings
+            .iter()
+            .find(|(bound_code, _)| *bound_code == key_code)
+            .map(|&(_, key)| key)
+    }
+}
+
+pub trait Keyboard: AsAny {
     fn initialize(&mut self);
     fn set_key(&mut self, key: u8);
     fn get_key(&mut self) -> u8;
+    fn set_keymap(&mut self, _keymap: &Keymap) {}
 }
@@ -1,16 +1,36 @@
-mod audio;
-mod chip8;
-mod cpu;
-mod display;
-mod instruction;
-mod keyboard;
-
-use crate::chip8::{Chip8, Chip8Platform};
+// CPU, BROWSER etc. read better in caps for an emulator
+#![allow(clippy::upper_case_acronyms)]
+
+pub mod archive;
+pub mod audio;
+pub mod cartridge;
+pub mod chip8;
+pub mod cpu;
+pub mod database;
+pub mod display;
+pub mod flags;
+pub mod instruction;
+pub mod keyboard;
+pub mod octo;
+pub mod quirks;
+// the model the cpu is checked against, only built for its tests and the fuzz target
+#[cfg(any(test, feature = "fuzzing"))]
+pub mod reference;
+pub mod timing;
+
+use crate::audio::synth::{ToneSettings, Waveform, WAVEFORM_NAMES};
+use crate::chip8::{Chip8, Chip8Platform, TickResult};
 use crate::cpu::CPU;
+use crate::database::RomDatabase;
+use crate::display::blend::BlendMode;
+use crate::display::crt::CrtSettings;
+use crate::display::palette::{Palette, PALETTE_NAMES};
 use crate::display::Display;
 use crate::keyboard::Keyboard;
+use crate::timing::TimingMode;
 use log::Level;
 use std::panic;
+use std::ptr::addr_of_mut;
 use wasm_bindgen::prelude::*;
 
 #[wasm_bindgen]
@@ -20,6 +40,12 @@ extern "C" {
 
 static mut EMULATOR: Option<Chip8> = None;
 
+fn emulator() -> &'static mut Chip8 {
+    // wasm is single threaded and nothing holds on to the returned reference
+    // past the exported call it was made in
+    unsafe { (*addr_of_mut!(EMULATOR)).as_mut().unwrap() }
+}
+
 pub fn init_logging() {
     panic::set_hook(Box::new(console_error_panic_hook::hook));
     console_log::init_with_level(Level::Debug).expect("Error initializing log!");
@@ -28,54 +54,306 @@ pub fn init_logging() {
 #[wasm_bindgen]
 pub fn boot_emulator() {
     init_logging();
+    let mut emulator = Chip8::new(Chip8Platform::BROWSER);
+    emulator.bus.display.initialize();
+    emulator.bus.keyboard.initialize();
     unsafe {
-        let mut emulator = Chip8::new(Chip8Platform::BROWSER);
-        emulator.bus.display.initialize();
-        emulator.bus.keyboard.initialize();
         EMULATOR = Some(emulator);
     }
 }
 
 #[wasm_bindgen]
 pub fn key_down(key_code: u8) {
-    unsafe {
-        let emulator: &mut Chip8 = EMULATOR.as_mut().unwrap();
-        emulator.bus.keyboard.set_key(key_code);
-    }
+    emulator().bus.keyboard.set_key(key_code);
 }
 
 #[wasm_bindgen]
 pub fn key_up() {
-    unsafe {
-        let emulator: &mut Chip8 = EMULATOR.as_mut().unwrap();
-        emulator.bus.keyboard.set_key(0);
-    }
+    emulator().bus.keyboard.set_key(0);
 }
 
 #[wasm_bindgen]
-pub fn tick() {
-    unsafe {
-        let emulator: &mut Chip8 = EMULATOR.as_mut().unwrap();
-        emulator.fetch_and_execute_instruction();
-    }
+pub fn tick() -> TickResult {
+    let emulator = emulator();
+    let result = emulator.fetch_and_execute_instruction();
+    emulator.present();
+    result
 }
 
 #[wasm_bindgen]
-pub fn handle_timers() {
-    unsafe {
-        let emulator: &mut Chip8 = EMULATOR.as_mut().unwrap();
-        emulator.cpu.handler_timers(&mut emulator.bus.audio);
-    }
+pub fn end_frame() {
+    emulator().end_frame();
 }
 
 #[wasm_bindgen]
-pub fn load_rom(rom_bytes: &[u8]) {
-    unsafe {
-        let emulator = EMULATOR.as_mut().unwrap();
-        emulator.reset();
-        emulator.load_rom_into_memory(rom_bytes);
+pub fn run_frame(elapsed_ms: f64) -> u32 {
+    emulator().run_frame(elapsed_ms)
+}
+
+#[wasm_bindgen]
+pub fn set_clock_speed(hz: u32) {
+    emulator().set_clock_speed(hz);
+}
+
+#[wasm_bindgen]
+pub fn set_cosmac_vip_timing(enabled: bool) {
+    emulator().set_timing_mode(if enabled {
+        TimingMode::CosmacVip
+    } else {
+        TimingMode::Fixed
+    });
+}
+
+/// The clock speed the current rom runs at, whole instructions per frame
+#[wasm_bindgen]
+pub fn clock_speed() -> u32 {
+    emulator().instructions_per_frame * 60
+}
+
+#[wasm_bindgen]
+pub fn display_wait() -> bool {
+    emulator().cpu.quirks.display_wait
+}
+
+#[wasm_bindgen]
+pub fn sprite_wrapping() -> bool {
+    emulator().cpu.quirks.wrap_sprites
+}
+
+#[wasm_bindgen]
+pub fn set_display_wait(enabled: bool) {
+    emulator().set_display_wait(enabled);
+}
+
+#[wasm_bindgen]
+pub fn set_sprite_wrapping(enabled: bool) {
+    emulator().cpu.quirks.wrap_sprites = enabled;
+}
+
+#[wasm_bindgen]
+pub fn palette_names() -> js_sys::Array {
+    PALETTE_NAMES
+        .iter()
+        .map(|&name| JsValue::from(name))
+        .collect()
+}
+
+/// Switches to one of the preset palettes from `palette_names`
+#[wasm_bindgen]
+pub fn set_palette(name: &str) -> Result<(), JsValue> {
+    let palette =
+        Palette::preset(name).ok_or_else(|| JsValue::from(format!("Unknown palette {}", name)))?;
+    emulator().set_palette(palette);
+    Ok(())
+}
+
+/// The preset palette in use, `None` for custom colours like a rom's own from the database
+#[wasm_bindgen]
+pub fn palette_name() -> Option<String> {
+    emulator().palette().preset_name().map(String::from)
+}
+
+/// Sets a custom palette from a comma separated list of up to four `#rrggbb` colours:
+/// off, plane 1, plane 2 and both planes
+#[wasm_bindgen]
+pub fn set_custom_palette(hex_colors: &str) -> Result<(), JsValue> {
+    let hex_colors: Vec<&str> = hex_colors.split(',').collect();
+    let palette = Palette::from_hex_colors(&hex_colors)
+        .ok_or_else(|| JsValue::from("Expected up to four #rrggbb colours"))?;
+    emulator().set_palette(palette);
+    Ok(())
+}
+
+/// Phosphor persistence, scanlines, bloom and curvature, each 0.0 to turn it off
+#[wasm_bindgen]
+pub fn set_crt_effects(persistence: f32, scanlines: f32, bloom: f32, curvature: f32) {
+    let settings = CrtSettings {
+        persistence,
+        scanlines,
+        bloom,
+        curvature,
+    };
+    emulator().bus.display.set_crt_settings(&settings);
+}
+
+/// Turns all of the CRT effects on with some reasonable defaults, or all off
+#[wasm_bindgen]
+pub fn set_crt_preset(enabled: bool) {
+    let settings = if enabled {
+        CrtSettings::crt()
+    } else {
+        CrtSettings::default()
+    };
+    emulator().bus.display.set_crt_settings(&settings);
+}
 
-        emulator.bus.audio.initialize();
-        emulator.bus.audio.start_sound();
+/// Anti-flicker blending of the last `frames` frames: `off`, `or`, `average` or `keep`
+/// (pixels stay lit for one extra frame after switching off)
+#[wasm_bindgen]
+pub fn set_frame_blend(mode: &str, frames: usize) -> Result<(), JsValue> {
+    let mode = BlendMode::from_name(mode, frames)
+        .ok_or_else(|| JsValue::from(format!("Unknown frame blend mode {}", mode)))?;
+    emulator().bus.display.set_blend_mode(mode);
+    Ok(())
+}
+
+#[wasm_bindgen]
+pub fn waveform_names() -> js_sys::Array {
+    WAVEFORM_NAMES
+        .iter()
+        .map(|&name| JsValue::from(name))
+        .collect()
+}
+
+/// Master volume of the buzzer, 0.0 to 1.0
+#[wasm_bindgen]
+pub fn set_volume(volume: f32) {
+    let emulator = emulator();
+    let tone = ToneSettings {
+        volume,
+        ..emulator.tone()
+    };
+    emulator.set_tone(tone);
+}
+
+/// Silences the buzzer without losing the volume it goes back to
+#[wasm_bindgen]
+pub fn set_muted(muted: bool) {
+    let emulator = emulator();
+    let tone = ToneSettings {
+        muted,
+        ..emulator.tone()
+    };
+    emulator.set_tone(tone);
+}
+
+/// Shape of the buzzer's tone, one of `waveform_names`
+#[wasm_bindgen]
+pub fn set_waveform(name: &str) -> Result<(), JsValue> {
+    let waveform = Waveform::from_name(name)
+        .ok_or_else(|| JsValue::from(format!("Unknown waveform {}", name)))?;
+    let emulator = emulator();
+    let tone = ToneSettings {
+        waveform,
+        ..emulator.tone()
+    };
+    emulator.set_tone(tone);
+    Ok(())
+}
+
+/// Pitch of the buzzer in hz, XO-CHIP roms that load their own sound set theirs instead
+#[wasm_bindgen]
+pub fn set_tone_frequency(frequency: f32) {
+    let emulator = emulator();
+    let tone = ToneSettings {
+        frequency,
+        ..emulator.tone()
+    };
+    emulator.set_tone(tone);
+}
+
+/// What's on screen right now as PNG bytes in the current palette, each chip8 pixel
+/// drawn `scale` pixels square
+#[wasm_bindgen]
+pub fn screenshot(scale: usize) -> Result<Vec<u8>, JsValue> {
+    emulator()
+        .screenshot(scale)
+        .map_err(|e| JsValue::from(e.to_string()))
+}
+
+#[wasm_bindgen]
+pub fn start_recording() {
+    emulator().start_recording();
+}
+
+/// Everything shown since `start_recording` as animated GIF bytes, repeated frames are
+/// only stored once
+#[wasm_bindgen]
+pub fn stop_recording(scale: usize) -> Result<Vec<u8>, JsValue> {
+    emulator()
+        .stop_recording(scale)
+        .map_err(|e| JsValue::from(e.to_string()))
+}
+
+#[wasm_bindgen]
+pub fn start_audio_recording() {
+    emulator().start_audio_recording();
+}
+
+/// Everything played since `start_audio_recording` as WAV bytes
+#[wasm_bindgen]
+pub fn stop_audio_recording() -> Vec<u8> {
+    emulator().stop_audio_recording().unwrap_or_default()
+}
+
+/// Loads the rom, or the first one in a zip that loads, with the database's settings for
+/// it, returning its title if it was in there. The file's name says which platform a rom
+/// that isn't in the database was written for.
+#[wasm_bindgen]
+pub fn load_rom(rom_bytes: &[u8], file_name: Option<String>) -> Result<Option<String>, JsValue> {
+    let emulator = emulator();
+    let settings = match file_name {
+        Some(file_name) => emulator.load_rom_file(&file_name, rom_bytes),
+        None => emulator.load_rom(rom_bytes),
     }
+    .map_err(|e| JsValue::from(e.to_string()))?;
+    emulator.present();
+
+    emulator.bus.audio.initialize();
+    Ok(settings.map(|s| s.title))
+}
+
+/// Names of the roms in the zip the current rom came out of, for picking another one
+#[wasm_bindgen]
+pub fn archive_rom_names() -> js_sys::Array {
+    emulator()
+        .archive_rom_names()
+        .into_iter()
+        .map(JsValue::from)
+        .collect()
+}
+
+/// Which of `archive_rom_names` is running, if any of them would load
+#[wasm_bindgen]
+pub fn archive_rom_name() -> Option<String> {
+    emulator().archive_rom_name().map(String::from)
+}
+
+/// Loads one of the roms from `archive_rom_names`, returning its title like `load_rom`
+#[wasm_bindgen]
+pub fn load_archive_rom(name: &str) -> Result<Option<String>, JsValue> {
+    let emulator = emulator();
+    let settings = emulator
+        .load_archive_rom(name)
+        .map_err(|e| JsValue::from(e.to_string()))?;
+    emulator.present();
+    Ok(settings.map(|s| s.title))
+}
+
+/// The chip-8-database's `sha1-hashes.json`, `programs.json` and `platforms.json`
+#[wasm_bindgen]
+pub fn load_rom_database(
+    hashes_json: &str,
+    programs_json: &str,
+    platforms_json: &str,
+) -> Result<(), JsValue> {
+    let database = RomDatabase::from_json(hashes_json, programs_json, platforms_json)
+        .map_err(|e| JsValue::from(format!("Couldn't read the ROM database: {}", e)))?;
+    emulator().database = database;
+    Ok(())
+}
+
+/// Where roms load and start running from, 0x200 for most and 0x600 for ETI-660 ones.
+/// Takes effect when the next rom is loaded.
+#[wasm_bindgen]
+pub fn set_load_address(address: u16) -> Result<(), JsValue> {
+    emulator()
+        .set_load_address(address)
+        .map_err(|e| JsValue::from(e.to_string()))
+}
+
+/// Browsers only let audio start from a user gesture, the page calls this on the first one
+#[wasm_bindgen]
+pub fn enable_audio() {
+    emulator().bus.audio.initialize();
 }
@@ -0,0 +1,1121 @@
+//! A compiler for Octo's assembly language (https://github.com/JohnEarnest/Octo), the
+//! source Octo cartridges carry. It covers labels, `:const`, `:alias`, `:calc`, macros,
+//! `:unpack`, `:next`, `:org`, every CHIP-8, SUPER-CHIP and XO-CHIP statement, and Octo's
+//! `if`, `loop` and `while`. `:stringmode`, `:assert` and the debugger's directives aren't
+//! supported.
+
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+
+const PROGRAM_START: usize = 0x200;
+const MEMORY_SIZE: usize = 0x10000;
+// a jump to `main` goes here unless `main` is the first thing in the program
+const MAIN_SLOT_END: usize = PROGRAM_START + 2;
+// stops a macro that expands itself from hanging the page
+const MAX_MACRO_EXPANSIONS: u32 = 100_000;
+// operators and brackets a `:calc` expression can nest, each one is a call deeper in the
+// wasm stack
+const MAX_CALC_DEPTH: u32 = 256;
+
+/// Why a program didn't compile, and the line it got to
+#[derive(Clone, Debug, PartialEq)]
+pub struct CompileError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for CompileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for CompileError {}
+
+/// Compiles the source into a rom to load at 0x200
+pub fn compile(source: &str) -> Result<Vec<u8>, CompileError> {
+    let mut compiler = Compiler::new(tokenize(source));
+    compiler.compile()?;
+    Ok(compiler.rom[PROGRAM_START..compiler.end].to_vec())
+}
+
+#[derive(Clone, Debug)]
+struct Token {
+    text: String,
+    line: usize,
+}
+
+/// Splits on whitespace, `#` comments out the rest of the line and a string stays one token
+fn tokenize(source: &str) -> VecDeque<Token> {
+    let mut tokens = VecDeque::new();
+    for (index, mut rest) in source.lines().enumerate() {
+        loop {
+            rest = rest.trim_start();
+            if rest.is_empty() || rest.starts_with('#') {
+                break;
+            }
+            let end = if let Some(string) = rest.strip_prefix('"') {
+                string.find('"').map_or(rest.len(), |end| end + 2)
+            } else {
+                rest.find(char::is_whitespace).unwrap_or(rest.len())
+            };
+            tokens.push_back(Token {
+                text: rest[..end].to_string(),
+                line: index + 1,
+            });
+            rest = &rest[end..];
+        }
+    }
+    tokens
+}
+
+fn parse_number(text: &str) -> Option<f64> {
+    let (negative, digits) = match text.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, text),
+    };
+    let value = if let Some(hex) = digits.strip_prefix("0x") {
+        i64::from_str_radix(hex, 16).ok()?
+    } else if let Some(binary) = digits.strip_prefix("0b") {
+        i64::from_str_radix(binary, 2).ok()?
+    } else if !digits.is_empty() && digits.bytes().all(|b| b.is_ascii_digit()) {
+        digits.parse().ok()?
+    } else {
+        return None;
+    };
+    let value = if negative { -value } else { value };
+    Some(value as f64)
+}
+
+/// What goes into an instruction once the label it names has an address
+#[derive(Clone, Copy, Debug)]
+enum Fixup {
+    /// The low 12 bits of the instruction at the address
+    Address(usize),
+    /// Both bytes at the address, after `i := long` and `:pointer`
+    Long(usize),
+    /// The byte of `:unpack`'s first `vx := nn`, with the nibble on top
+    UnpackHigh(usize, u8),
+    /// The byte of `:unpack`'s second `vx := nn`
+    UnpackLow(usize),
+}
+
+/// A 12 or 16 bit address, or the name of a label that isn't defined yet
+enum Address {
+    Known(usize),
+    Forward(String),
+}
+
+/// `if` and `while` before they're turned into skips
+struct Condition {
+    register: u8,
+    comparison: String,
+    operand: Option<Operand>,
+}
+
+enum Operand {
+    Register(u8),
+    Byte(u8),
+}
+
+struct Macro {
+    args: Vec<String>,
+    body: Vec<Token>,
+    calls: u32,
+}
+
+struct Loop {
+    start: usize,
+    // the jumps out of the loop its `while`s make
+    exits: Vec<usize>,
+    line: usize,
+}
+
+struct Compiler {
+    tokens: VecDeque<Token>,
+    rom: Vec<u8>,
+    here: usize,
+    // one past the last byte written
+    end: usize,
+    line: usize,
+    labels: HashMap<String, usize>,
+    constants: HashMap<String, f64>,
+    aliases: HashMap<String, u8>,
+    macros: HashMap<String, Macro>,
+    macro_expansions: u32,
+    calc_depth: u32,
+    fixups: Vec<(String, Fixup, usize)>,
+    // the jumps `if ... begin` and `else` make, with their lines
+    branches: Vec<(usize, usize)>,
+    loops: Vec<Loop>,
+    has_main_slot: bool,
+}
+
+impl Compiler {
+    fn new(tokens: VecDeque<Token>) -> Self {
+        let aliases = [
+            ("compare-temp", 0xf),
+            ("unpack-hi", 0x0),
+            ("unpack-lo", 0x1),
+        ];
+        Self {
+            tokens,
+            rom: vec![0; MEMORY_SIZE],
+            here: MAIN_SLOT_END,
+            end: MAIN_SLOT_END,
+            line: 1,
+            labels: HashMap::new(),
+            constants: HashMap::new(),
+            aliases: aliases
+                .iter()
+                .map(|&(name, register)| (name.to_string(), register))
+                .collect(),
+            macros: HashMap::new(),
+            macro_expansions: 0,
+            calc_depth: 0,
+            fixups: Vec::new(),
+            branches: Vec::new(),
+            loops: Vec::new(),
+            has_main_slot: true,
+        }
+    }
+
+    fn error<T>(&self, message: String) -> Result<T, CompileError> {
+        Err(CompileError {
+            line: self.line,
+            message,
+        })
+    }
+
+    fn compile(&mut self) -> Result<(), CompileError> {
+        while let Some(token) = self.tokens.pop_front() {
+            self.line = token.line;
+            self.statement(token.text)?;
+        }
+
+        if let Some(&(_, line)) = self.branches.last() {
+            self.line = line;
+            return self.error(String::from("This 'if' has no 'end'"));
+        }
+        if let Some(line) = self.loops.last().map(|l| l.line) {
+            self.line = line;
+            return self.error(String::from("This 'loop' has no 'again'"));
+        }
+        let Some(&main) = self.labels.get("main") else {
+            return self.error(String::from("The program has no 'main' label"));
+        };
+        if self.has_main_slot {
+            self.patch(Fixup::Address(PROGRAM_START), main, 0x1000)?;
+        }
+        for (name, fixup, line) in std::mem::take(&mut self.fixups) {
+            self.line = line;
+            let Some(&address) = self.labels.get(&name) else {
+                return self.error(format!("'{}' isn't defined", name));
+            };
+            self.patch(fixup, address, 0)?;
+        }
+        Ok(())
+    }
+
+    fn next(&mut self) -> Result<String, CompileError> {
+        match self.tokens.pop_front() {
+            Some(token) => {
+                self.line = token.line;
+                Ok(token.text)
+            }
+            None => self.error(String::from(
+                "The program ends in the middle of a statement",
+            )),
+        }
+    }
+
+    fn expect(&mut self, expected: &str) -> Result<(), CompileError> {
+        let token = self.next()?;
+        if token != expected {
+            return self.error(format!("Expected '{}', got '{}'", expected, token));
+        }
+        Ok(())
+    }
+
+    fn byte(&mut self, byte: u8) -> Result<(), CompileError> {
+        if self.here >= MEMORY_SIZE {
+            return self.error(String::from("The program doesn't fit in 64K"));
+        }
+        self.rom[self.here] = byte;
+        self.here += 1;
+        self.end = self.end.max(self.here);
+        Ok(())
+    }
+
+    fn instruction(&mut self, opcode: u16) -> Result<(), CompileError> {
+        let [high, low] = opcode.to_be_bytes();
+        self.byte(high)?;
+        self.byte(low)
+    }
+
+    /// Fills in `fixup` with `address`, `opcode` going in the top nibble of an address
+    fn patch(&mut self, fixup: Fixup, address: usize, opcode: u16) -> Result<(), CompileError> {
+        match fixup {
+            Fixup::Address(at) => {
+                if address > 0xfff {
+                    return self.error(format!(
+                        "{:#x} is past the 4K an instruction can reach, use 'i := long'",
+                        address
+                    ));
+                }
+                let [high, low] = (opcode | address as u16).to_be_bytes();
+                self.rom[at] |= high;
+                self.rom[at + 1] = low;
+            }
+            Fixup::Long(at) => {
+                let [high, low] = (address as u16).to_be_bytes();
+                self.rom[at] = high;
+                self.rom[at + 1] = low;
+            }
+            Fixup::UnpackHigh(at, nibble) => {
+                self.rom[at + 1] = nibble << 4 | (address >> 8) as u8 & 0xf;
+            }
+            Fixup::UnpackLow(at) => self.rom[at + 1] = address as u8,
+        }
+        Ok(())
+    }
+
+    /// `opcode` with the address from the next token, filled in later for a label that
+    /// isn't defined yet
+    fn address_instruction(&mut self, opcode: u16) -> Result<(), CompileError> {
+        let at = self.here;
+        self.instruction(opcode)?;
+        let token = self.next()?;
+        match self.address(token)? {
+            Address::Known(address) => self.patch(Fixup::Address(at), address, opcode),
+            Address::Forward(name) => {
+                self.fixups.push((name, Fixup::Address(at), self.line));
+                Ok(())
+            }
+        }
+    }
+
+    /// Both bytes at `here` get the address, `i := long` and `:pointer`
+    fn long_address(&mut self) -> Result<(), CompileError> {
+        let at = self.here;
+        self.instruction(0)?;
+        let token = self.next()?;
+        match self.address(token)? {
+            Address::Known(address) => self.patch(Fixup::Long(at), address, 0),
+            Address::Forward(name) => {
+                self.fixups.push((name, Fixup::Long(at), self.line));
+                Ok(())
+            }
+        }
+    }
+
+    fn address(&mut self, token: String) -> Result<Address, CompileError> {
+        if let Some(value) = self.constant(&token)? {
+            if !(0.0..MEMORY_SIZE as f64).contains(&value) {
+                return self.error(format!("{} isn't an address", value));
+            }
+            return Ok(Address::Known(value as usize));
+        }
+        if self.register(&token).is_some() || token.starts_with(':') {
+            return self.error(format!("Expected an address, got '{}'", token));
+        }
+        Ok(Address::Forward(token))
+    }
+
+    /// The value of a number, constant, label or `{ expression }`, `None` for anything else
+    fn constant(&mut self, token: &str) -> Result<Option<f64>, CompileError> {
+        if token == "{" {
+            return self.calc("}").map(Some);
+        }
+        Ok(parse_number(token)
+            .or_else(|| self.constants.get(token).copied())
+            .or_else(|| self.labels.get(token).map(|&address| address as f64)))
+    }
+
+    fn value(&mut self, min: f64, max: f64, what: &str) -> Result<f64, CompileError> {
+        let token = self.next()?;
+        match self.constant(&token)? {
+            Some(value) if (min..=max).contains(&value) => Ok(value),
+            Some(value) => self.error(format!("{} doesn't fit in {}", value, what)),
+            None => self.error(format!("Expected a number, got '{}'", token)),
+        }
+    }
+
+    fn byte_value(&mut self) -> Result<u8, CompileError> {
+        Ok(self.value(-128.0, 255.0, "a byte")? as i64 as u8)
+    }
+
+    fn nibble_value(&mut self) -> Result<u8, CompileError> {
+        Ok(self.value(0.0, 15.0, "a nibble")? as u8)
+    }
+
+    fn register(&self, token: &str) -> Option<u8> {
+        if let Some(&register) = self.aliases.get(token) {
+            return Some(register);
+        }
+        let digit = token.strip_prefix(['v', 'V'])?;
+        if digit.len() != 1 {
+            return None;
+        }
+        u8::from_str_radix(digit, 16).ok()
+    }
+
+    fn next_register(&mut self) -> Result<u8, CompileError> {
+        let token = self.next()?;
+        match self.register(&token) {
+            Some(register) => Ok(register),
+            None => self.error(format!("Expected a register, got '{}'", token)),
+        }
+    }
+
+    fn name(&mut self) -> Result<String, CompileError> {
+        let name = self.next()?;
+        if self.register(&name).is_some() || parse_number(&name).is_some() {
+            return self.error(format!("'{}' can't be used as a name", name));
+        }
+        Ok(name)
+    }
+
+    fn define_label(&mut self, name: String, address: usize) -> Result<(), CompileError> {
+        if self.labels.contains_key(&name) || self.constants.contains_key(&name) {
+            return self.error(format!("'{}' is already defined", name));
+        }
+        self.labels.insert(name, address);
+        Ok(())
+    }
+
+    fn statement(&mut self, token: String) -> Result<(), CompileError> {
+        if let Some(register) = self.register(&token) {
+            return self.register_statement(register);
+        }
+        match token.as_str() {
+            ":" => {
+                let name = self.name()?;
+                // no need for the jump when main comes first
+                if name == "main" && self.has_main_slot && self.here == MAIN_SLOT_END {
+                    self.has_main_slot = false;
+                    self.here = PROGRAM_START;
+                    self.end = PROGRAM_START;
+                }
+                self.define_label(name, self.here)?;
+            }
+            ":next" => {
+                let name = self.name()?;
+                self.define_label(name, self.here + 1)?;
+            }
+            ":const" => {
+                let name = self.name()?;
+                let value = self.value(f64::MIN, f64::MAX, "a constant")?;
+                self.constants.insert(name, value);
+            }
+            ":calc" => {
+                let name = self.name()?;
+                self.expect("{")?;
+                let value = self.calc("}")?;
+                self.constants.insert(name, value);
+            }
+            ":alias" => {
+                let name = self.name()?;
+                let token = self.next()?;
+                let register = match self.register(&token) {
+                    Some(register) => register,
+                    None => self.error(format!("Expected a register, got '{}'", token))?,
+                };
+                self.aliases.insert(name, register);
+            }
+            ":unpack" => {
+                let nibble = self.nibble_value()?;
+                let (high, low) = (self.aliases["unpack-hi"], self.aliases["unpack-lo"]);
+                let at = self.here;
+                self.instruction(0x6000 | (high as u16) << 8)?;
+                self.instruction(0x6000 | (low as u16) << 8)?;
+                let token = self.next()?;
+                match self.address(token)? {
+                    Address::Known(address) => {
+                        self.patch(Fixup::UnpackHigh(at, nibble), address, 0)?;
+                        self.patch(Fixup::UnpackLow(at + 2), address, 0)?;
+                    }
+                    Address::Forward(name) => {
+                        let line = self.line;
+                        self.fixups
+                            .push((name.clone(), Fixup::UnpackHigh(at, nibble), line));
+                        self.fixups.push((name, Fixup::UnpackLow(at + 2), line));
+                    }
+                }
+            }
+            ":org" => {
+                self.here =
+                    self.value(PROGRAM_START as f64, MEMORY_SIZE as f64 - 1.0, "memory")? as usize;
+                self.has_main_slot &= self.here != PROGRAM_START;
+            }
+            ":byte" => {
+                let byte = self.byte_value()?;
+                self.byte(byte)?;
+            }
+            ":pointer" => self.long_address()?,
+            ":call" => self.address_instruction(0x2000)?,
+            ":macro" => self.define_macro()?,
+            ":proto" | ":breakpoint" => {
+                self.next()?;
+            }
+            ":monitor" => {
+                self.next()?;
+                self.next()?;
+            }
+            ";" | "return" => self.instruction(0x00ee)?,
+            "clear" => self.instruction(0x00e0)?,
+            "exit" => self.instruction(0x00fd)?,
+            "hires" => self.instruction(0x00ff)?,
+            "lores" => self.instruction(0x00fe)?,
+            "scroll-left" => self.instruction(0x00fc)?,
+            "scroll-right" => self.instruction(0x00fb)?,
+            "audio" => self.instruction(0xf002)?,
+            "scroll-down" => {
+                let n = self.nibble_value()?;
+                self.instruction(0x00c0 | n as u16)?;
+            }
+            "scroll-up" => {
+                let n = self.nibble_value()?;
+                self.instruction(0x00d0 | n as u16)?;
+            }
+            "plane" => {
+                let n = self.nibble_value()?;
+                self.instruction(0xf001 | (n as u16) << 8)?;
+            }
+            "bcd" => self.register_instruction(0xf033)?,
+            "saveflags" => self.register_instruction(0xf075)?,
+            "loadflags" => self.register_instruction(0xf085)?,
+            "save" | "load" => {
+                let x = self.next_register()? as u16;
+                if self.tokens.front().is_some_and(|token| token.text == "-") {
+                    self.next()?;
+                    let y = self.next_register()? as u16;
+                    let n = if token == "save" { 0x2 } else { 0x3 };
+                    self.instruction(0x5000 | x << 8 | y << 4 | n)?;
+                } else {
+                    let kk = if token == "save" { 0x55 } else { 0x65 };
+                    self.instruction(0xf000 | x << 8 | kk)?;
+                }
+            }
+            "sprite" => {
+                let x = self.next_register()? as u16;
+                let y = self.next_register()? as u16;
+                let n = self.nibble_value()? as u16;
+                self.instruction(0xd000 | x << 8 | y << 4 | n)?;
+            }
+            "jump" => self.address_instruction(0x1000)?,
+            "jump0" => self.address_instruction(0xb000)?,
+            "native" => self.address_instruction(0x0000)?,
+            "delay" | "buzzer" | "pitch" => {
+                self.expect(":=")?;
+                let kk = match token.as_str() {
+                    "delay" => 0x15,
+                    "buzzer" => 0x18,
+                    _ => 0x3a,
+                };
+                self.register_instruction(0xf000 | kk)?;
+            }
+            "i" => self.i_statement()?,
+            "if" => {
+                let condition = self.condition()?;
+                match self.next()?.as_str() {
+                    // skips the next statement unless the condition holds
+                    "then" => self.skip(&condition, false)?,
+                    // jumps past the block unless it holds
+                    "begin" => {
+                        self.skip(&condition, true)?;
+                        self.branches.push((self.here, self.line));
+                        self.instruction(0x1000)?;
+                    }
+                    other => self.error(format!("Expected 'then' or 'begin', got '{}'", other))?,
+                }
+            }
+            "else" => {
+                let Some((branch, line)) = self.branches.pop() else {
+                    return self.error(String::from("This 'else' has no 'if'"));
+                };
+                self.branches.push((self.here, line));
+                self.instruction(0x1000)?;
+                self.patch(Fixup::Address(branch), self.here, 0)?;
+            }
+            "end" => {
+                let Some((branch, _)) = self.branches.pop() else {
+                    return self.error(String::from("This 'end' has no 'if'"));
+                };
+                self.patch(Fixup::Address(branch), self.here, 0)?;
+            }
+            "loop" => self.loops.push(Loop {
+                start: self.here,
+                exits: Vec::new(),
+                line: self.line,
+            }),
+            "while" => {
+                if self.loops.is_empty() {
+                    return self.error(String::from("This 'while' isn't in a loop"));
+                }
+                let condition = self.condition()?;
+                self.skip(&condition, true)?;
+                let here = self.here;
+                self.loops.last_mut().unwrap().exits.push(here);
+                self.instruction(0x1000)?;
+            }
+            "again" => {
+                let Some(lp) = self.loops.pop() else {
+                    return self.error(String::from("This 'again' has no 'loop'"));
+                };
+                let at = self.here;
+                self.instruction(0x1000)?;
+                self.patch(Fixup::Address(at), lp.start, 0)?;
+                for exit in lp.exits {
+                    self.patch(Fixup::Address(exit), self.here, 0)?;
+                }
+            }
+            _ if self.macros.contains_key(&token) => self.expand_macro(&token)?,
+            // a label is called, numbers and constants are data
+            _ if self.labels.contains_key(&token) => {
+                let address = self.labels[&token];
+                let at = self.here;
+                self.instruction(0x2000)?;
+                self.patch(Fixup::Address(at), address, 0x2000)?;
+            }
+            _ => match self.constant(&token)? {
+                Some(value) if (-128.0..=255.0).contains(&value) => {
+                    self.byte(value as i64 as u8)?;
+                }
+                Some(value) => self.error(format!("{} doesn't fit in a byte", value))?,
+                None if token.starts_with(':') || token.starts_with('"') => {
+                    self.error(format!("'{}' isn't supported", token))?;
+                }
+                // a call to a label further down
+                None => {
+                    self.fixups
+                        .push((token, Fixup::Address(self.here), self.line));
+                    self.instruction(0x2000)?;
+                }
+            },
+        }
+        Ok(())
+    }
+
+    /// `opcode` with the next token's register as X
+    fn register_instruction(&mut self, opcode: u16) -> Result<(), CompileError> {
+        let x = self.next_register()? as u16;
+        self.instruction(opcode | x << 8)
+    }
+
+    fn register_statement(&mut self, x: u8) -> Result<(), CompileError> {
+        let x = x as u16;
+        let operator = self.next()?;
+        let operand = self.next()?;
+        if let Some(y) = self.register(&operand) {
+            let n = match operator.as_str() {
+                ":=" => 0x0,
+                "|=" => 0x1,
+                "&=" => 0x2,
+                "^=" => 0x3,
+                "+=" => 0x4,
+                "-=" => 0x5,
+                ">>=" => 0x6,
+                "=-" => 0x7,
+                "<<=" => 0xe,
+                _ => return self.error(format!("'{}' can't go between registers", operator)),
+            };
+            return self.instruction(0x8000 | x << 8 | (y as u16) << 4 | n);
+        }
+
+        match (operator.as_str(), operand.as_str()) {
+            (":=", "key") => self.instruction(0xf00a | x << 8),
+            (":=", "delay") => self.instruction(0xf007 | x << 8),
+            (":=", "random") => {
+                let kk = self.byte_value()?;
+                self.instruction(0xc000 | x << 8 | kk as u16)
+            }
+            (":=" | "+=" | "-=", _) => {
+                self.tokens.push_front(Token {
+                    text: operand,
+                    line: self.line,
+                });
+                let kk = self.byte_value()?;
+                match operator.as_str() {
+                    ":=" => self.instruction(0x6000 | x << 8 | kk as u16),
+                    "+=" => self.instruction(0x7000 | x << 8 | kk as u16),
+                    _ => self.instruction(0x7000 | x << 8 | kk.wrapping_neg() as u16),
+                }
+            }
+            _ => self.error(format!("Expected a register after '{}'", operator)),
+        }
+    }
+
+    fn i_statement(&mut self) -> Result<(), CompileError> {
+        match self.next()?.as_str() {
+            "+=" => self.register_instruction(0xf01e),
+            ":=" => match self.tokens.front().map(|token| token.text.as_str()) {
+                Some("hex") => {
+                    self.next()?;
+                    self.register_instruction(0xf029)
+                }
+                Some("bighex") => {
+                    self.next()?;
+                    self.register_instruction(0xf030)
+                }
+                Some("long") => {
+                    self.next()?;
+                    self.instruction(0xf000)?;
+                    self.long_address()
+                }
+                _ => self.address_instruction(0xa000),
+            },
+            other => self.error(format!("Expected ':=' or '+=' after 'i', got '{}'", other)),
+        }
+    }
+
+    fn condition(&mut self) -> Result<Condition, CompileError> {
+        let register = self.next_register()?;
+        let comparison = self.next()?;
+        let operand = match comparison.as_str() {
+            "key" | "-key" => None,
+            "==" | "!=" | "<" | ">" | "<=" | ">=" => {
+                let token = self.next()?;
+                match self.register(&token) {
+                    Some(y) => Some(Operand::Register(y)),
+                    None => {
+                        self.tokens.push_front(Token {
+                            text: token,
+                            line: self.line,
+                        });
+                        Some(Operand::Byte(self.byte_value()?))
+                    }
+                }
+            }
+            other => return self.error(format!("'{}' isn't a comparison", other)),
+        };
+        Ok(Condition {
+            register,
+            comparison,
+            operand,
+        })
+    }
+
+    /// Skips the next instruction unless the condition holds, or if it does when `negated`
+    fn skip(&mut self, condition: &Condition, negated: bool) -> Result<(), CompileError> {
+        let x = condition.register as u16;
+        let mut comparison = condition.comparison.as_str();
+        if negated {
+            comparison = match comparison {
+                "==" => "!=",
+                "!=" => "==",
+                "key" => "-key",
+                "-key" => "key",
+                "<" => ">=",
+                ">=" => "<",
+                ">" => "<=",
+                _ => ">",
+            };
+        }
+        let operand = |register_opcode: u16, byte_opcode: u16| match condition.operand {
+            Some(Operand::Register(y)) => register_opcode | x << 8 | (y as u16) << 4,
+            Some(Operand::Byte(kk)) => byte_opcode | x << 8 | kk as u16,
+            None => unreachable!("only key and -key have no operand"),
+        };
+        match comparison {
+            "==" => self.instruction(operand(0x9000, 0x4000)),
+            "!=" => self.instruction(operand(0x5000, 0x3000)),
+            "key" => self.instruction(0xe0a1 | x << 8),
+            "-key" => self.instruction(0xe09e | x << 8),
+            // the comparison's flag is left in VF by subtracting in the temporary register
+            _ => {
+                let temp = self.aliases["compare-temp"] as u16;
+                let load = match condition.operand {
+                    Some(Operand::Register(y)) => 0x8000 | temp << 8 | (y as u16) << 4,
+                    Some(Operand::Byte(kk)) => 0x6000 | temp << 8 | kk as u16,
+                    None => unreachable!("only key and -key have no operand"),
+                };
+                self.instruction(load)?;
+                let subtract = if matches!(comparison, ">" | "<=") {
+                    0x5
+                } else {
+                    0x7
+                };
+                self.instruction(0x8000 | temp << 8 | x << 4 | subtract)?;
+                let skip = if matches!(comparison, ">" | "<") {
+                    0x3f01
+                } else {
+                    0x4f01
+                };
+                self.instruction(skip)
+            }
+        }
+    }
+
+    fn define_macro(&mut self) -> Result<(), CompileError> {
+        let name = self.name()?;
+        let mut args = Vec::new();
+        loop {
+            let token = self.next()?;
+            if token == "{" {
+                break;
+            }
+            args.push(token);
+        }
+        let mut body = Vec::new();
+        let mut depth = 1;
+        loop {
+            let Some(token) = self.tokens.pop_front() else {
+                return self.error(format!("The macro '{}' has no closing '}}'", name));
+            };
+            match token.text.as_str() {
+                "{" => depth += 1,
+                "}" => {
+                    depth -= 1;
+                    if depth == 0 {
+                        break;
+                    }
+                }
+                _ => {}
+            }
+            body.push(token);
+        }
+        self.macros.insert(
+            name,
+            Macro {
+                args,
+                body,
+                calls: 0,
+            },
+        );
+        Ok(())
+    }
+
+    /// Puts the macro's body in the arguments that follow in front of what's left to compile
+    fn expand_macro(&mut self, name: &str) -> Result<(), CompileError> {
+        self.macro_expansions += 1;
+        if self.macro_expansions > MAX_MACRO_EXPANSIONS {
+            return self.error(format!("The macro '{}' never stops expanding", name));
+        }
+        let arg_count = self.macros[name].args.len();
+        let values = (0..arg_count)
+            .map(|_| self.next())
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let makro = self.macros.get_mut(name).unwrap();
+        let calls = makro.calls;
+        makro.calls += 1;
+        let expanded: Vec<Token> = makro
+            .body
+            .iter()
+            .map(|token| {
+                let text = match makro.args.iter().position(|arg| *arg == token.text) {
+                    Some(index) => values[index].clone(),
+                    None => token.text.clone(),
+                };
+                Token {
+                    text,
+                    line: token.line,
+                }
+            })
+            .collect();
+        self.constants.insert(String::from("CALLS"), calls as f64);
+        for token in expanded.into_iter().rev() {
+            self.tokens.push_front(token);
+        }
+        Ok(())
+    }
+
+    /// A `:calc` expression up to `close`. Like Octo's, they're worked out right to left
+    /// with no precedence, brackets go round anything that should be done first.
+    fn calc(&mut self, close: &str) -> Result<f64, CompileError> {
+        self.nested_calc(|compiler| compiler.calc_expression(close))
+    }
+
+    fn calc_term(&mut self) -> Result<f64, CompileError> {
+        self.nested_calc(Self::calc_value)
+    }
+
+    /// Runs a part of a `:calc` expression one level deeper
+    fn nested_calc(
+        &mut self,
+        part: impl FnOnce(&mut Self) -> Result<f64, CompileError>,
+    ) -> Result<f64, CompileError> {
+        if self.calc_depth >= MAX_CALC_DEPTH {
+            return self.error(String::from("The expression is nested too deeply"));
+        }
+        self.calc_depth += 1;
+        let value = part(self);
+        self.calc_depth -= 1;
+        value
+    }
+
+    fn calc_expression(&mut self, close: &str) -> Result<f64, CompileError> {
+        let left = self.calc_term()?;
+        let operator = self.next()?;
+        if operator == close {
+            return Ok(left);
+        }
+        let right = self.calc(close)?;
+        let (a, b) = (left as i64, right as i64);
+        Ok(match operator.as_str() {
+            "+" => left + right,
+            "-" => left - right,
+            "*" => left * right,
+            "/" => left / right,
+            "%" => left % right,
+            "pow" => left.powf(right),
+            "min" => left.min(right),
+            "max" => left.max(right),
+            "&" => (a & b) as f64,
+            "|" => (a | b) as f64,
+            "^" => (a ^ b) as f64,
+            "<<" => a.wrapping_shl(b as u32) as f64,
+            ">>" => a.wrapping_shr(b as u32) as f64,
+            "<" => (left < right) as u8 as f64,
+            ">" => (left > right) as u8 as f64,
+            "<=" => (left <= right) as u8 as f64,
+            ">=" => (left >= right) as u8 as f64,
+            "==" => (left == right) as u8 as f64,
+            "!=" => (left != right) as u8 as f64,
+            _ => return self.error(format!("'{}' isn't an operator", operator)),
+        })
+    }
+
+    fn calc_value(&mut self) -> Result<f64, CompileError> {
+        let token = self.next()?;
+        let unary: Option<fn(f64) -> f64> = match token.as_str() {
+            "(" => return self.calc(")"),
+            "HERE" => return Ok(self.here as f64),
+            "PI" => return Ok(std::f64::consts::PI),
+            "E" => return Ok(std::f64::consts::E),
+            "-" => Some(|v| -v),
+            "~" => Some(|v| !(v as i64) as f64),
+            "!" => Some(|v| (v == 0.0) as u8 as f64),
+            "sin" => Some(f64::sin),
+            "cos" => Some(f64::cos),
+            "tan" => Some(f64::tan),
+            "exp" => Some(f64::exp),
+            "log" => Some(f64::ln),
+            "abs" => Some(f64::abs),
+            "sqrt" => Some(f64::sqrt),
+            "sign" => Some(f64::signum),
+            "ceil" => Some(f64::ceil),
+            "floor" => Some(f64::floor),
+            _ => None,
+        };
+        if let Some(unary) = unary {
+            return Ok(unary(self.calc_term()?));
+        }
+        if token == "@" {
+            let address = self.calc_term()? as usize;
+            return Ok(self.rom.get(address).copied().unwrap_or(0) as f64);
+        }
+        match self.constant(&token)? {
+            Some(value) => Ok(value),
+            None => self.error(format!("'{}' isn't defined", token)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn error_line(source: &str) -> usize {
+        compile(source).unwrap_err().line
+    }
+
+    #[test]
+    fn tokenize_skips_comments() {
+        let tokens: Vec<String> = tokenize(": main # the start\n  clear\n:assert \"a b\"")
+            .into_iter()
+            .map(|token| token.text)
+            .collect();
+        assert_eq!(tokens, vec![":", "main", "clear", ":assert", "\"a b\""]);
+    }
+
+    #[test]
+    fn compile_a_program() {
+        let source = "
+            : main
+              v0 := 5
+              i := box
+              loop
+                sprite v0 v1 3
+                v0 += 1
+                if v0 == 10 then v0 := 0
+              again
+            : box
+              0xF0 0b10010000 240
+        ";
+        assert_eq!(
+            compile(source).unwrap(),
+            vec![
+                0x60, 0x05, 0xa2, 0x0e, 0xd0, 0x13, 0x70, 0x01, 0x40, 0x0a, 0x60, 0x00, 0x12, 0x04,
+                0xf0, 0x90, 0xf0
+            ]
+        );
+    }
+
+    #[test]
+    fn main_after_other_code_gets_jumped_to() {
+        assert_eq!(
+            compile(": data 1 2 : main clear data").unwrap(),
+            vec![0x12, 0x04, 0x01, 0x02, 0x00, 0xe0, 0x22, 0x02]
+        );
+    }
+
+    #[test]
+    fn statements() {
+        let source = "
+            : main
+              clear return ; exit hires lores scroll-left scroll-right audio
+              scroll-down 3 scroll-up 2 plane 3
+              bcd v1 saveflags v2 loadflags v3 save v4 load v5 save v1 - v3 load v3 - v1
+              delay := v6 buzzer := v7 pitch := v8
+              i := hex v9 i := bighex va i += vb i := 0x123
+              va := vb vb |= vc vc &= vd vd ^= ve ve += vf vf -= v0 v0 >>= v1 v1 =- v2
+              v2 <<= v3 v3 := key v4 := delay v5 := random 0x0f v6 += 2 v7 -= 1 v8 := -1
+              jump0 0x300 native 0x123 :call 0x456
+        ";
+        let expected: Vec<u16> = vec![
+            0x00e0, 0x00ee, 0x00ee, 0x00fd, 0x00ff, 0x00fe, 0x00fc, 0x00fb, 0xf002, 0x00c3, 0x00d2,
+            0xf301, 0xf133, 0xf275, 0xf385, 0xf455, 0xf565, 0x5132, 0x5313, 0xf615, 0xf718, 0xf83a,
+            0xf929, 0xfa30, 0xfb1e, 0xa123, 0x8ab0, 0x8bc1, 0x8cd2, 0x8de3, 0x8ef4, 0x8f05, 0x8016,
+            0x8127, 0x823e, 0xf30a, 0xf407, 0xc50f, 0x7602, 0x77ff, 0x68ff, 0xb300, 0x0123, 0x2456,
+        ];
+        let bytes: Vec<u8> = expected.iter().flat_map(|op| op.to_be_bytes()).collect();
+        assert_eq!(compile(source).unwrap(), bytes);
+    }
+
+    #[test]
+    fn if_begin_else_end() {
+        let source = "
+            : main
+              if v0 != v1 begin
+                v2 := 1
+              else
+                v2 := 2
+              end
+              if v3 key then clear
+        ";
+        assert_eq!(
+            compile(source).unwrap(),
+            vec![
+                0x90, 0x10, 0x12, 0x08, 0x62, 0x01, 0x12, 0x0a, 0x62, 0x02, 0xe3, 0xa1, 0x00, 0xe0
+            ]
+        );
+    }
+
+    #[test]
+    fn comparisons_go_through_vf() {
+        assert_eq!(
+            compile(": main if v1 > 5 then clear").unwrap(),
+            vec![0x6f, 0x05, 0x8f, 0x15, 0x3f, 0x01, 0x00, 0xe0]
+        );
+        // a loop runs while it holds
+        assert_eq!(
+            compile(": main loop while v1 <= v2 v1 += 1 again").unwrap(),
+            vec![0x8f, 0x20, 0x8f, 0x15, 0x3f, 0x01, 0x12, 0x0c, 0x71, 0x01, 0x12, 0x00]
+        );
+    }
+
+    #[test]
+    fn constants_aliases_and_calc() {
+        let source = "
+            :const SPEED 3
+            :alias x v4
+            :calc DOUBLE { SPEED * 2 + 1 }
+            :calc MASK { ( 1 << 4 ) - 1 }
+            : main
+              x := SPEED
+              x += DOUBLE
+              :byte { MASK & 0xff }
+              :byte { - 2 * 3 }
+              SPEED
+        ";
+        assert_eq!(
+            compile(source).unwrap(),
+            vec![0x64, 0x03, 0x74, 0x09, 0x0f, 0xfa, 0x03]
+        );
+    }
+
+    #[test]
+    fn macros() {
+        let source = "
+            :macro add-both a b { a += b b += 1 }
+            : main
+              add-both v1 v2
+              add-both v3 v4
+        ";
+        assert_eq!(
+            compile(source).unwrap(),
+            vec![0x81, 0x24, 0x72, 0x01, 0x83, 0x44, 0x74, 0x01]
+        );
+        assert_eq!(
+            compile(":macro twice { :byte { CALLS * 2 } } : main twice twice").unwrap(),
+            vec![0x00, 0x02]
+        );
+        assert!(compile(":macro forever { forever } : main forever").is_err());
+    }
+
+    #[test]
+    fn calc_nesting_is_limited() {
+        let deep = format!(": main :byte {{ {}1 }}", "- ".repeat(200_000));
+        assert_eq!(
+            compile(&deep).unwrap_err().message,
+            "The expression is nested too deeply"
+        );
+        let brackets = format!(
+            ": main :byte {{ {}1{} }}",
+            "( ".repeat(1000),
+            " )".repeat(1000)
+        );
+        assert!(compile(&brackets).is_err());
+        let operators = format!(": main :byte {{ {}1 }}", "1 + ".repeat(1000));
+        assert!(compile(&operators).is_err());
+        // plenty for anything a person writes
+        let nested = format!(": main :byte {{ {}1{} }}", "( ".repeat(50), " )".repeat(50));
+        assert_eq!(compile(&nested), Ok(vec![0x01]));
+    }
+
+    #[test]
+    fn forward_references() {
+        let source = "
+            : main
+              i := long far
+              :unpack 0xA later
+              jump later
+              later
+            : later
+              :next patched v0 := 0
+              :org 0x1234
+            : far
+        ";
+        assert_eq!(
+            compile(source).unwrap(),
+            &[0xf0, 0x00, 0x12, 0x34, 0x60, 0xa2, 0x61, 0x0c, 0x12, 0x0c, 0x22, 0x0c, 0x60, 0x00]
+        );
+        assert_eq!(
+            compile(": main :pointer later : later").unwrap(),
+            vec![0x02, 0x02]
+        );
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(
+            compile("clear").unwrap_err().message,
+            "The program has no 'main' label"
+        );
+        assert_eq!(error_line(": main\n  clear\n  v0 := 300"), 3);
+        assert_eq!(error_line(": main\n  nowhere\n"), 2);
+        assert_eq!(error_line(": main\n\n  if v0 == 1 begin clear"), 3);
+        assert_eq!(error_line(": main\n  loop\n  again\n  again"), 4);
+        assert_eq!(error_line(": main : main"), 1);
+        assert_eq!(error_line(": main\n  :stringmode"), 2);
+        assert_eq!(error_line(": main\n  i := long"), 2);
+        assert_eq!(
+            compile(": main :org 0x1000 : high : other jump high")
+                .unwrap_err()
+                .message,
+            "0x1000 is past the 4K an instruction can reach, use 'i := long'"
+        );
+    }
+}
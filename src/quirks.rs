@@ -0,0 +1,56 @@
+/// Where `Fx55` and `Fx65` leave I once they're done
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum MemoryIncrement {
+    /// I stays where it was, like SUPER-CHIP
+    #[default]
+    Unchanged,
+    /// I moves on by X, CHIP-48 was one short
+    ByX,
+    /// I ends up just past the last register, like the original COSMAC VIP interpreter
+    ByXPlusOne,
+}
+
+/// Behaviours that differ between CHIP-8 interpreters and that games end up relying on
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Quirks {
+    /// `DRW` waits for the next vertical blank before drawing, limiting games to 60
+    /// sprites a second like the original COSMAC VIP interpreter
+    pub display_wait: bool,
+    /// Sprites running off the right or bottom edge carry on from the opposite edge
+    /// instead of being clipped
+    pub wrap_sprites: bool,
+    /// `8xy6` and `8xyE` shift VY into VX like the COSMAC VIP rather than shifting VX in
+    /// place
+    pub shift_vy: bool,
+    /// `8xy1`, `8xy2` and `8xy3` reset VF to 0, a side effect of how the COSMAC VIP did them
+    pub vf_reset: bool,
+    pub memory_increment: MemoryIncrement,
+    /// `Bnnn` jumps to `xnn` plus VX, SUPER-CHIP's misreading of `nnn` plus V0
+    pub jump_vx: bool,
+}
+
+impl Quirks {
+    /// What SUPER-CHIP 1.1 roms expect, as modern interpreters run them
+    pub fn superchip() -> Self {
+        Self {
+            display_wait: false,
+            wrap_sprites: false,
+            shift_vy: false,
+            vf_reset: false,
+            memory_increment: MemoryIncrement::Unchanged,
+            jump_vx: true,
+        }
+    }
+
+    /// What XO-CHIP roms expect, Octo's defaults
+    pub fn xo_chip() -> Self {
+        Self {
+            display_wait: false,
+            wrap_sprites: true,
+            shift_vy: true,
+            vf_reset: false,
+            memory_increment: MemoryIncrement::ByXPlusOne,
+            jump_vx: false,
+        }
+    }
+}
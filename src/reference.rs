@@ -0,0 +1,97 @@
+//! A plain model of what the instructions should do, for the property tests and the fuzz
+//! target to check the CPU against
+
+use crate::chip8::Chip8;
+use crate::cpu::InstructionSet;
+use crate::instruction::Instruction;
+
+/// Whether the CPU can run `instruction` with the instruction set, it panics on anything
+/// else
+pub fn is_known_instruction(instruction: &Instruction, instruction_set: InstructionSet) -> bool {
+    let superchip = instruction_set >= InstructionSet::Superchip;
+    let xo_chip = instruction_set == InstructionSet::XoChip;
+    match instruction.first {
+        0x0 => match instruction.kk {
+            0xe0 | 0xee => true,
+            0xc0..=0xcf | 0xfb..=0xff => superchip,
+            0xd0..=0xdf => xo_chip,
+            _ => false,
+        },
+        0x8 => matches!(instruction.n, 0x0..=0x7 | 0xe),
+        0xe => matches!(instruction.kk, 0x9e | 0xa1),
+        0xf => match instruction.kk {
+            0x07 | 0x0a | 0x15 | 0x18 | 0x1e | 0x29 | 0x33 | 0x55 | 0x65 => true,
+            0x30 | 0x75 | 0x85 => superchip,
+            0x00 | 0x02 => xo_chip && instruction.x == 0,
+            0x01 | 0x3a => xo_chip,
+            _ => false,
+        },
+        _ => true,
+    }
+}
+
+/// VX and VF after an 8xyN arithmetic instruction with the default quirks, `None` for
+/// anything else
+pub fn alu(instruction: &Instruction, vx: u8, vy: u8) -> Option<(u8, u8)> {
+    if instruction.first != 0x8 {
+        return None;
+    }
+    match instruction.n {
+        0x4 => {
+            let (result, carry) = vx.overflowing_add(vy);
+            Some((result, carry as u8))
+        }
+        0x5 => Some((vx.wrapping_sub(vy), (vx >= vy) as u8)),
+        0x6 => Some((vx >> 1, vx & 0x1)),
+        0x7 => Some((vy.wrapping_sub(vx), (vy >= vx) as u8)),
+        0xe => Some((vx << 1, vx >> 7)),
+        _ => None,
+    }
+}
+
+/// Runs `instruction` and panics with what went wrong if it leaves the CPU somewhere it
+/// never should be, or gets a different answer to `alu`
+pub fn execute_and_check(chip8: &mut Chip8, instruction: Instruction) {
+    let vx = chip8.cpu.v_registers[instruction.x];
+    let vy = chip8.cpu.v_registers[instruction.y];
+    chip8.cpu.execute_instruction(instruction, &mut chip8.bus);
+
+    let cpu = &chip8.cpu;
+    let memory_size = cpu.instruction_set.memory_size();
+    let raw_bytes = instruction.raw_bytes;
+    assert!(
+        cpu.stack_pointer < 16,
+        "{:#06x} left SP at {}",
+        raw_bytes,
+        cpu.stack_pointer
+    );
+    assert!(
+        (cpu.program_counter as usize) < memory_size,
+        "{:#06x} left PC at {:#x}",
+        raw_bytes,
+        cpu.program_counter
+    );
+    assert!(
+        (cpu.address_i as usize) < memory_size,
+        "{:#06x} left I at {:#x}",
+        raw_bytes,
+        cpu.address_i
+    );
+
+    if let Some((result, flag)) = alu(&instruction, vx, vy) {
+        // with VF as the destination the flag overwrites the result
+        let expected = if instruction.x == 0xf {
+            (flag, flag)
+        } else {
+            (result, flag)
+        };
+        assert_eq!(
+            (cpu.v_registers[instruction.x], cpu.v_registers[0xf]),
+            expected,
+            "{:#06x} with VX {:#04x} and VY {:#04x}, (VX, VF) should be",
+            raw_bytes,
+            vx,
+            vy
+        );
+    }
+}
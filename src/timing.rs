@@ -0,0 +1,126 @@
+use crate::instruction::Instruction;
+
+/// How the frame scheduler decides how many instructions fit into a 60hz frame
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TimingMode {
+    /// Run a flat `instructions_per_frame` every frame
+    Fixed,
+    /// Charge every instruction what it cost the COSMAC VIP interpreter and run as many
+    /// as fit into the machine cycles the VIP had left over each frame
+    CosmacVip,
+}
+
+// the VIP's 1802 runs off a 1.76064 MHz crystal and takes 8 clock pulses per machine cycle
+pub const VIP_CYCLES_PER_FRAME: u32 = 1_760_640 / 8 / 60;
+// the 1861 video chip steals 8 cycles of DMA on each of the 128 visible scanlines
+const VIP_DMA_CYCLES: u32 = 128 * 8;
+// the interrupt routine that sets up DMA and counts down the timers
+const VIP_INTERRUPT_CYCLES: u32 = 46;
+pub const VIP_CYCLES_AVAILABLE: u32 = VIP_CYCLES_PER_FRAME - VIP_DMA_CYCLES - VIP_INTERRUPT_CYCLES;
+
+// fetching and decoding the next instruction in the interpreter's main loop
+const VIP_FETCH_CYCLES: u32 = 40;
+// a taken skip only has to bump the program counter once more
+const VIP_SKIP_CYCLES: u32 = 4;
+
+/// Approximate number of VIP machine cycles taken by `instruction`. `v_registers` is
+/// the register state the instruction ran with, `skipped` whether it skipped the next
+/// instruction.
+pub fn vip_cycles(instruction: &Instruction, v_registers: &[u8; 16], skipped: bool) -> u32 {
+    let x = instruction.x;
+    let execute = match instruction.first {
+        0x0 => match instruction.kk {
+            // clearing the 256 bytes of display memory one at a time
+            0xe0 => 24 + 3078,
+            0xee => 10,
+            _ => 0,
+        },
+        0x1 => 12,
+        0x2 => 26,
+        0x3 | 0x4 => 10,
+        0x5 | 0x9 => 14,
+        0x6 => 6,
+        0x7 => 10,
+        // the interpreter builds the ALU instruction on the stack and runs it
+        0x8 => 44,
+        0xa => 12,
+        0xb => 22,
+        0xc => 36,
+        0xd => drw_cycles(v_registers[x], instruction.n),
+        0xe => 14,
+        0xf => match instruction.kk {
+            0x1e | 0x29 => 16,
+            // repeated subtraction, so every unit in the decimal digits costs a loop
+            0x33 => {
+                let value = v_registers[x] as u32;
+                80 + 16 * (value / 100 + value % 100 / 10 + value % 10)
+            }
+            0x55 | 0x65 => 14 + 14 * (x as u32 + 1),
+            _ => 10,
+        },
+        _ => 0,
+    };
+
+    let is_skip = matches!(instruction.first, 0x3 | 0x4 | 0x5 | 0x9 | 0xe);
+    let skip = if skipped && is_skip {
+        VIP_SKIP_CYCLES
+    } else {
+        0
+    };
+    VIP_FETCH_CYCLES + execute + skip
+}
+
+fn drw_cycles(vx: u8, rows: u8) -> u32 {
+    // sprites that don't start on a byte boundary are shifted into two bytes per row
+    let cycles_per_row = if vx.is_multiple_of(8) { 46 } else { 70 };
+    26 + cycles_per_row * rows as u32
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn vip_cycles_fixed_cost() {
+        let registers = [0; 16];
+        assert_eq!(vip_cycles(&Instruction::new(0x6a12), &registers, false), 46);
+        assert_eq!(vip_cycles(&Instruction::new(0x8124), &registers, false), 84);
+        assert_eq!(
+            vip_cycles(&Instruction::new(0x00e0), &registers, false),
+            3142
+        );
+    }
+
+    #[test]
+    fn vip_cycles_skip() {
+        let registers = [0; 16];
+        assert_eq!(vip_cycles(&Instruction::new(0x3a12), &registers, false), 50);
+        assert_eq!(vip_cycles(&Instruction::new(0x3a12), &registers, true), 54);
+    }
+
+    #[test]
+    fn vip_cycles_variable_cost() {
+        let mut registers = [0; 16];
+        registers[0x1] = 8;
+        registers[0x2] = 123;
+
+        // aligned and unaligned sprites
+        assert_eq!(
+            vip_cycles(&Instruction::new(0xd125), &registers, false),
+            296
+        );
+        assert_eq!(
+            vip_cycles(&Instruction::new(0xd215), &registers, false),
+            416
+        );
+
+        assert_eq!(
+            vip_cycles(&Instruction::new(0xf233), &registers, false),
+            216
+        );
+        assert_eq!(
+            vip_cycles(&Instruction::new(0xf355), &registers, false),
+            110
+        );
+    }
+}
@@ -0,0 +1,410 @@
+//! Golden image conformance tests. Each ROM runs on the mock platform for a fixed number of
+//! frames and whatever the mock display was last given is compared with an image stored in
+//! `tests/golden`, one line per row with `#` for a lit pixel.
+//!
+//! The community test ROMs aren't checked in, `make test-roms` fetches them into
+//! `tests/roms` and they're skipped until then. Once a new ROM's output has been checked by
+//! eye `BLESS=1 cargo test --test conformance` writes its golden image.
+
+use std::env;
+use std::fs;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::Path;
+use tostiloco::chip8::{Chip8, Chip8Platform};
+use tostiloco::cpu::InstructionSet;
+use tostiloco::display::mock::MockDisplay;
+
+const ROMS_DIR: &str = "tests/roms";
+const GOLDEN_DIR: &str = "tests/golden";
+
+// the Timendus suite runs the test picked here instead of waiting on its menu
+const TEST_SELECT_ADDRESS: usize = 0x1ff;
+
+/// Part of the screen showing the result for one opcode or check
+struct Region {
+    name: String,
+    x: usize,
+    y: usize,
+    width: usize,
+    height: usize,
+}
+
+struct RomTest {
+    name: &'static str,
+    rom: Rom,
+    instruction_set: InstructionSet,
+    frames: u32,
+    // written to `TEST_SELECT_ADDRESS` before starting
+    test_select: Option<u8>,
+    // (frame, key) pairs, key 0 lets go
+    key_presses: &'static [(u32, u8)],
+    // reported on one by one, the whole screen is compared either way
+    regions: Vec<Region>,
+}
+
+enum Rom {
+    File(&'static str),
+    Built(Vec<u8>),
+}
+
+fn rom_tests() -> Vec<RomTest> {
+    let community = |name, file, frames, test_select, key_presses| RomTest {
+        name,
+        rom: Rom::File(file),
+        instruction_set: InstructionSet::Chip8,
+        frames,
+        test_select,
+        key_presses,
+        regions: Vec::new(),
+    };
+    let (opcode_rom, opcode_regions) = opcode_rom();
+
+    vec![
+        RomTest {
+            name: "opcodes",
+            rom: Rom::Built(opcode_rom),
+            // for the RPL flags
+            instruction_set: InstructionSet::Superchip,
+            frames: 60,
+            test_select: None,
+            key_presses: &[],
+            regions: opcode_regions,
+        },
+        community("ibm-logo", "2-ibm-logo.ch8", 60, None, &[]),
+        community("corax-plus", "3-corax+.ch8", 120, None, &[]),
+        community("flags", "4-flags.ch8", 240, None, &[]),
+        // 1 is the original CHIP-8
+        community("quirks", "5-quirks.ch8", 600, Some(1), &[]),
+        // 3 is the FX0A test, which needs a key pressed and let go
+        community(
+            "keypad",
+            "6-keypad.ch8",
+            120,
+            Some(3),
+            &[(30, 0x5), (40, 0)],
+        ),
+    ]
+}
+
+/// Runs the ROM and returns what the mock display was last given
+fn run(test: &RomTest, rom_bytes: &[u8]) -> Vec<u8> {
+    let mut chip8 = Chip8::new(Chip8Platform::MOCK);
+    chip8.cpu.instruction_set = test.instruction_set;
+    chip8.reset();
+    chip8.load_rom_into_memory(rom_bytes).unwrap();
+    if let Some(test_select) = test.test_select {
+        chip8.bus.memory[TEST_SELECT_ADDRESS] = test_select;
+    }
+
+    for frame in 0..test.frames {
+        for &(_, key) in test.key_presses.iter().filter(|(at, _)| *at == frame) {
+            chip8.bus.keyboard.set_key(key);
+        }
+        chip8.step_frame();
+    }
+
+    let display = chip8.bus.display.as_ref().as_any();
+    display.downcast_ref::<MockDisplay>().unwrap().vram.clone()
+}
+
+fn to_text(vram: &[u8], width: usize) -> String {
+    vram.chunks(width)
+        .map(|row| {
+            let mut line: String = row
+                .iter()
+                .map(|&pixel| if pixel != 0 { '#' } else { '.' })
+                .collect();
+            line.push('\n');
+            line
+        })
+        .collect()
+}
+
+fn width_of(vram: &[u8]) -> usize {
+    // 2:1 screens only, 64x32 or 128x64
+    ((vram.len() * 2) as f64).sqrt() as usize
+}
+
+/// Names of the regions that differ from the golden image
+fn failed_regions<'a>(test: &'a RomTest, actual: &str, golden: &str) -> Vec<&'a str> {
+    let actual: Vec<&[u8]> = actual.lines().map(str::as_bytes).collect();
+    let golden: Vec<&[u8]> = golden.lines().map(str::as_bytes).collect();
+    test.regions
+        .iter()
+        .filter(|region| {
+            (region.y..region.y + region.height).any(|y| {
+                let columns = region.x..region.x + region.width;
+                actual.get(y).map(|row| &row[columns.clone()])
+                    != golden.get(y).map(|row| &row[columns.clone()])
+            })
+        })
+        .map(|region| region.name.as_str())
+        .collect()
+}
+
+#[test]
+fn golden_images() {
+    let bless = env::var("BLESS").is_ok();
+    let mut failures = Vec::new();
+
+    for test in rom_tests() {
+        let rom_bytes = match &test.rom {
+            Rom::Built(bytes) => bytes.clone(),
+            Rom::File(file) => match fs::read(Path::new(ROMS_DIR).join(file)) {
+                Ok(bytes) => bytes,
+                Err(_) => {
+                    println!("{:<12} skipped, {} isn't in {}", test.name, file, ROMS_DIR);
+                    continue;
+                }
+            },
+        };
+
+        let vram = match panic::catch_unwind(AssertUnwindSafe(|| run(&test, &rom_bytes))) {
+            Ok(vram) => vram,
+            Err(_) => {
+                println!("{:<12} FAILED, the emulator panicked", test.name);
+                failures.push(test.name.to_string());
+                continue;
+            }
+        };
+        let actual = to_text(&vram, width_of(&vram));
+
+        let golden_path = Path::new(GOLDEN_DIR).join(format!("{}.txt", test.name));
+        if bless {
+            fs::create_dir_all(GOLDEN_DIR).unwrap();
+            fs::write(&golden_path, &actual).unwrap();
+            println!("{:<12} blessed", test.name);
+            continue;
+        }
+        let Ok(golden) = fs::read_to_string(&golden_path) else {
+            println!(
+                "{:<12} FAILED, no golden image at {}, check the output and bless it:\n{}",
+                test.name,
+                golden_path.display(),
+                actual
+            );
+            failures.push(test.name.to_string());
+            continue;
+        };
+
+        if actual == golden {
+            println!("{:<12} ok", test.name);
+            for region in &test.regions {
+                println!("    {:<16} ok", region.name);
+            }
+            continue;
+        }
+
+        let failed = failed_regions(&test, &actual, &golden);
+        println!(
+            "{:<12} FAILED, expected:\n{}got:\n{}",
+            test.name, golden, actual
+        );
+        for region in &test.regions {
+            let result = if failed.contains(&region.name.as_str()) {
+                "FAILED"
+            } else {
+                "ok"
+            };
+            println!("    {:<16} {}", region.name, result);
+        }
+        if failed.is_empty() {
+            failures.push(test.name.to_string());
+        } else {
+            failures.push(format!("{} ({})", test.name, failed.join(", ")));
+        }
+    }
+
+    assert!(failures.is_empty(), "failed: {}", failures.join(", "));
+}
+
+// built in opcode test, each check draws a tick or a cross into its own 8x6 cell
+const CELL_WIDTH: usize = 8;
+const CELL_HEIGHT: usize = 6;
+const CELLS_PER_ROW: usize = 8;
+
+const TICK: [u8; 5] = [0x02, 0x04, 0x88, 0x50, 0x20];
+const CROSS: [u8; 5] = [0x88, 0x50, 0x20, 0x50, 0x88];
+
+// a jump to MAIN, the two sprites and a subroutine in front of the checks
+const ROM_START: u16 = 0x200;
+const TICK_SPRITE: u16 = 0x202;
+const CROSS_SPRITE: u16 = 0x207;
+// LD V0, 0x99; RET
+const SUBROUTINE: u16 = 0x20c;
+const MAIN: u16 = 0x210;
+// somewhere well past the end of the rom to write to
+const SCRATCH: u16 = 0xe00;
+
+/// The register that's checked and what it should hold, VF is copied into V3 straight
+/// after the instruction under test so drawing the earlier checks can't clobber it
+struct Check(&'static str, u8, u8);
+
+struct OpcodeCase {
+    setup: Vec<u16>,
+    checks: Vec<Check>,
+}
+
+fn case(setup: &[u16], checks: Vec<Check>) -> OpcodeCase {
+    OpcodeCase {
+        setup: setup.to_vec(),
+        checks,
+    }
+}
+
+fn opcode_cases() -> Vec<OpcodeCase> {
+    vec![
+        case(&[0x6042], vec![Check("6xkk", 0x0, 0x42)]),
+        case(&[0x6010, 0x7005], vec![Check("7xkk", 0x0, 0x15)]),
+        case(&[0x6133, 0x8010], vec![Check("8xy0", 0x0, 0x33)]),
+        case(&[0x600f, 0x61f0, 0x8011], vec![Check("8xy1", 0x0, 0xff)]),
+        case(&[0x603c, 0x610f, 0x8012], vec![Check("8xy2", 0x0, 0x0c)]),
+        case(&[0x603c, 0x610f, 0x8013], vec![Check("8xy3", 0x0, 0x33)]),
+        case(
+            &[0x60f0, 0x6120, 0x8014],
+            vec![Check("8xy4", 0x0, 0x10), Check("8xy4 carry", 0x3, 1)],
+        ),
+        case(
+            &[0x6001, 0x6102, 0x8014],
+            vec![Check("8xy4 no carry", 0x3, 0)],
+        ),
+        case(
+            &[0x6005, 0x6103, 0x8015],
+            vec![Check("8xy5", 0x0, 0x02), Check("8xy5 no borrow", 0x3, 1)],
+        ),
+        case(
+            &[0x6003, 0x6105, 0x8015],
+            vec![Check("8xy5 wraps", 0x0, 0xfe), Check("8xy5 borrow", 0x3, 0)],
+        ),
+        case(
+            &[0x6003, 0x6105, 0x8017],
+            vec![Check("8xy7", 0x0, 0x02), Check("8xy7 no borrow", 0x3, 1)],
+        ),
+        // the carry wins over the sum when VF is the destination
+        case(
+            &[0x60f0, 0x6f20, 0x8f04],
+            vec![Check("8xy4 into VF", 0x3, 1)],
+        ),
+        // vx and vy hold the same value so the shift quirk doesn't matter
+        case(
+            &[0x6005, 0x6105, 0x8016],
+            vec![Check("8xy6", 0x0, 0x02), Check("8xy6 flag", 0x3, 1)],
+        ),
+        case(
+            &[0x6081, 0x6181, 0x801e],
+            vec![Check("8xyE", 0x0, 0x02), Check("8xyE flag", 0x3, 1)],
+        ),
+        // V2 only keeps its 1 if the load after the skip is skipped
+        case(
+            &[0x6201, 0x6007, 0x3007, 0x6200],
+            vec![Check("3xkk", 0x2, 1)],
+        ),
+        case(
+            &[0x6201, 0x6007, 0x4008, 0x6200],
+            vec![Check("4xkk", 0x2, 1)],
+        ),
+        case(
+            &[0x6201, 0x6007, 0x6107, 0x5010, 0x6200],
+            vec![Check("5xy0", 0x2, 1)],
+        ),
+        case(
+            &[0x6201, 0x6007, 0x6108, 0x9010, 0x6200],
+            vec![Check("9xy0", 0x2, 1)],
+        ),
+        case(
+            &[0x6000, 0x2000 | SUBROUTINE],
+            vec![Check("2nnn 00EE", 0x0, 0x99)],
+        ),
+        case(&[0xc000], vec![Check("Cxkk", 0x0, 0)]),
+        // DT might tick down once between setting and reading it, halving hides that
+        case(
+            &[0x6081, 0xf015, 0xf107, 0x8116],
+            vec![Check("Fx15 Fx07", 0x1, 0x40)],
+        ),
+        // the first row of the font's A
+        case(&[0x600a, 0xf029, 0xf065], vec![Check("Fx29", 0x0, 0xf0)]),
+        case(
+            &[0x607b, 0xa000 | SCRATCH, 0xf033, 0xf265],
+            vec![Check("Fx33 hundreds", 0x0, 1), Check("Fx33 ones", 0x2, 3)],
+        ),
+        case(
+            &[
+                0x6010,
+                0xa000 | SCRATCH,
+                0xf01e,
+                0x6077,
+                0xf055,
+                0x6000,
+                0xa000 | (SCRATCH + 0x10),
+                0xf065,
+            ],
+            vec![Check("Fx1E Fx55 Fx65", 0x0, 0x77)],
+        ),
+        case(
+            &[0x6033, 0x6144, 0xf175, 0x6000, 0x6100, 0xf185],
+            vec![Check("Fx75 Fx85", 0x1, 0x44)],
+        ),
+    ]
+}
+
+/// A ROM running `opcode_cases`, plus the screen region each check draws into
+fn opcode_rom() -> (Vec<u8>, Vec<Region>) {
+    let mut rom: Vec<u8> = (0x1000 | MAIN).to_be_bytes().to_vec();
+    rom.extend(TICK);
+    rom.extend(CROSS);
+    rom.extend([0x60, 0x99, 0x00, 0xee]);
+    assert_eq!(ROM_START as usize + rom.len(), MAIN as usize);
+
+    let mut program = Vec::new();
+    let mut regions = Vec::new();
+    for case in opcode_cases() {
+        program.extend(&case.setup);
+        // LD V3, VF
+        program.push(0x83f0);
+        for check in case.checks {
+            push_check(&mut program, &mut regions, check);
+        }
+    }
+
+    // JP over a load that shouldn't happen
+    program.push(0x6000);
+    let after_load = MAIN + (program.len() as u16 + 2) * 2;
+    program.extend([0x1000 | after_load, 0x6001]);
+    push_check(&mut program, &mut regions, Check("1nnn", 0x0, 0));
+
+    // JP V0 over another, two bytes short of it
+    program.extend([0x6200, 0x6002]);
+    let after_load = MAIN + (program.len() as u16 + 2) * 2;
+    program.extend([0xb000 | (after_load - 2), 0x6201]);
+    push_check(&mut program, &mut regions, Check("Bnnn", 0x2, 0));
+
+    // and then wait here forever
+    let halt = MAIN + program.len() as u16 * 2;
+    program.push(0x1000 | halt);
+
+    rom.extend(program.iter().flat_map(|op| op.to_be_bytes()));
+    (rom, regions)
+}
+
+/// Draws a tick in the next free cell if the register holds what it should, a cross if not
+fn push_check(program: &mut Vec<u16>, regions: &mut Vec<Region>, check: Check) {
+    let Check(name, register, expected) = check;
+    let cell = regions.len();
+    let x = cell % CELLS_PER_ROW * CELL_WIDTH;
+    let y = cell / CELLS_PER_ROW * CELL_HEIGHT;
+    program.extend([
+        0xa000 | TICK_SPRITE,
+        0x3000 | (register as u16) << 8 | expected as u16,
+        0xa000 | CROSS_SPRITE,
+        0x6e00 | x as u16,
+        0x6d00 | y as u16,
+        0xded5,
+    ]);
+    regions.push(Region {
+        name: name.to_string(),
+        x,
+        y,
+        width: CELL_WIDTH,
+        height: CELL_HEIGHT,
+    });
+}